@@ -0,0 +1,21 @@
+#![no_main]
+
+// Feeds arbitrary bytes to the binary input decoder, which reads every message
+// from the "input" data channel. Run with 'cargo +nightly fuzz run wire_decode'.
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/events.rs"]
+mod events;
+#[path = "../../src/wire.rs"]
+mod wire;
+
+fuzz_target!(|bytes: &[u8]| {
+    // Any input must decode or fail cleanly; whatever decodes must survive
+    // being encoded and decoded again. The first encoding normalizes what the
+    // decoder ignores (unused flag bits, NaN payloads), so compare from there.
+    let Ok(event) = wire::decode(bytes) else { return };
+    let encoded = wire::encode(&event);
+    let again = wire::decode(&encoded).expect("an encoded event must decode");
+    assert_eq!(wire::encode(&again), encoded);
+});
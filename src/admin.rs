@@ -0,0 +1,281 @@
+use std::{
+    sync::Arc,
+    time::{Duration, UNIX_EPOCH},
+};
+use axum::{
+    extract::{rejection::JsonRejection, Path, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::{get, post, put},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::{
+    protocol::{DisconnectReason, HostMessage, NoticeLevel},
+    session::{Role, Session},
+    AppState,
+};
+
+// Admin REST API, nested under '/admin'. Every request needs
+// 'Authorization: Bearer <token>' with the token from 'LOCALBRIDGE_ADMIN_TOKEN';
+// without that variable the API is off.
+//
+//   GET    /admin/sessions              -> [SessionInfo]
+//   GET    /admin/sessions/:id          -> SessionInfo
+//   DELETE /admin/sessions/:id          -> 204, the client gets 'disconnect' (kicked)
+//   PUT    /admin/sessions/:id/role     {"role": "view"}          -> SessionInfo
+//   POST   /admin/sessions/:id/keyframe -> 202
+//   PUT    /admin/sessions/:id/bitrate  {"bitrate_bps": 4000000}  -> 202
+//   GET    /admin/metrics               -> '/metrics' plus per-session series
+//
+// All sessions receive the same encoded stream, so a keyframe or bitrate forced for
+// one session applies to every session.
+
+// Accepted range for a forced bitrate.
+const MIN_BITRATE_BPS: u32 = 250_000;
+const MAX_BITRATE_BPS: u32 = 100_000_000;
+// How long a kicked client gets to receive its 'disconnect' before the connection closes.
+const KICK_GRACE: Duration = Duration::from_millis(500);
+
+/// 'AdminConfig' holds the API token read from the environment at startup.
+pub struct AdminConfig {
+    token: Option<String>,
+}
+
+impl AdminConfig {
+    pub fn from_env() -> Self {
+        let token = std::env::var("LOCALBRIDGE_ADMIN_TOKEN").ok().filter(|t| !t.is_empty());
+        if token.is_some() {
+            info!("Admin API enabled at /admin");
+        }
+        Self { token }
+    }
+
+    /// Compares in constant time so the token can't be guessed byte by byte.
+    fn accepts(&self, candidate: &str) -> bool {
+        let Some(token) = &self.token else { return false };
+        token.len() == candidate.len()
+            && token.bytes().zip(candidate.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+    }
+}
+
+/// Builds the '/admin' routes, all behind the token check.
+pub fn router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/sessions",              get(list_sessions))
+        .route("/sessions/:id",          get(get_session).delete(kick_session))
+        .route("/sessions/:id/role",     put(set_role))
+        .route("/sessions/:id/keyframe", post(force_keyframe))
+        .route("/sessions/:id/bitrate",  put(set_bitrate))
+        .route("/metrics",               get(serve_metrics))
+        .route_layer(middleware::from_fn_with_state(state, require_token))
+}
+
+/// 'SessionInfo' is what the API reports about one session.
+#[derive(Debug, Serialize)]
+pub struct SessionInfo {
+    pub id:             String,
+    pub remote_address: String,
+    pub role:           Role,
+    // WebRTC connection state ("new", "connecting", "connected", ...).
+    pub state:          String,
+    // Unix time in seconds.
+    pub connected_at:   u64,
+    pub connected_secs: u64,
+    // Averaged since the session's statistics were last read; 'null' on the first read.
+    pub bitrate_bps:    Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct RoleBody {
+    role: Role,
+}
+
+#[derive(Deserialize)]
+struct BitrateBody {
+    bitrate_bps: u32,
+}
+
+/// 'AdminError' is sent as '{"error": code, "message": ...}' with a matching status.
+#[derive(Debug, Serialize)]
+pub struct AdminError {
+    #[serde(rename = "error")]
+    code:    AdminErrorCode,
+    message: String,
+}
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AdminErrorCode {
+    Disabled,
+    Unauthorized,
+    NotFound,
+    InvalidRequest,
+}
+
+impl AdminError {
+    fn new(code: AdminErrorCode, message: impl Into<String>) -> Self {
+        Self { code, message: message.into() }
+    }
+
+    fn not_found(id: &str) -> Self {
+        Self::new(AdminErrorCode::NotFound, format!("no session '{id}'"))
+    }
+}
+
+/// A body that isn't the expected JSON (wrong content type, syntax, missing or
+/// mistyped fields) is a 400 in the same shape as every other error.
+impl From<JsonRejection> for AdminError {
+    fn from(rejection: JsonRejection) -> Self {
+        Self::new(AdminErrorCode::InvalidRequest, rejection.body_text())
+    }
+}
+
+impl IntoResponse for AdminError {
+    fn into_response(self) -> Response {
+        let status = match self.code {
+            AdminErrorCode::Disabled       => StatusCode::NOT_FOUND,
+            AdminErrorCode::Unauthorized   => StatusCode::UNAUTHORIZED,
+            AdminErrorCode::NotFound       => StatusCode::NOT_FOUND,
+            AdminErrorCode::InvalidRequest => StatusCode::BAD_REQUEST,
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+async fn require_token(
+    State(state): State<AppState>,
+    request:      Request,
+    next:         Next,
+) -> Result<Response, AdminError> {
+    if state.admin.token.is_none() {
+        return Err(AdminError::new(
+            AdminErrorCode::Disabled,
+            "the admin API is off; set LOCALBRIDGE_ADMIN_TOKEN to enable it",
+        ));
+    }
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match bearer {
+        Some(token) if state.admin.accepts(token) => Ok(next.run(request).await),
+        _ => {
+            warn!("Admin API: rejected request to {} without a valid token", request.uri().path());
+            Err(AdminError::new(AdminErrorCode::Unauthorized, "missing or invalid bearer token"))
+        }
+    }
+}
+
+async fn find(state: &AppState, id: &str) -> Result<Arc<Session>, AdminError> {
+    state.peers.lock().await.get(id).cloned().ok_or_else(|| AdminError::not_found(id))
+}
+
+async fn describe(session: &Session) -> SessionInfo {
+    let connected_at = session.connected_at.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let connected_secs = session.connected_at.elapsed().unwrap_or_default();
+    SessionInfo {
+        id:             session.id.clone(),
+        remote_address: session.remote_addr.to_string(),
+        role:           session.role(),
+        state:          session.pc.connection_state().to_string(),
+        connected_at:   connected_at.as_secs(),
+        connected_secs: connected_secs.as_secs(),
+        bitrate_bps:    session.video_stats().await.bitrate_bps,
+    }
+}
+
+async fn list_sessions(State(state): State<AppState>) -> Json<Vec<SessionInfo>> {
+    // Copy the sessions out so the lock isn't held while their statistics are read.
+    let sessions: Vec<_> = state.peers.lock().await.values().cloned().collect();
+    let mut infos = Vec::with_capacity(sessions.len());
+    for session in &sessions {
+        infos.push(describe(session).await);
+    }
+    infos.sort_by_key(|info| info.connected_at);
+    Json(infos)
+}
+
+async fn get_session(
+    State(state): State<AppState>,
+    Path(id):     Path<String>,
+) -> Result<Json<SessionInfo>, AdminError> {
+    let session = find(&state, &id).await?;
+    Ok(Json(describe(&session).await))
+}
+
+async fn kick_session(
+    State(state): State<AppState>,
+    Path(id):     Path<String>,
+) -> Result<StatusCode, AdminError> {
+    let session = state.peers.lock().await.remove(&id).ok_or_else(|| AdminError::not_found(&id))?;
+    info!("Admin API: kicking session {id} ({})", session.remote_addr);
+    session.input.notify(HostMessage::Disconnect {
+        reason:  DisconnectReason::Kicked,
+        message: "An administrator ended this session".to_owned(),
+    });
+    tokio::spawn(async move {
+        tokio::time::sleep(KICK_GRACE).await;
+        let _ = session.pc.close().await;
+    });
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn set_role(
+    State(state): State<AppState>,
+    Path(id):     Path<String>,
+    body:         Result<Json<RoleBody>, JsonRejection>,
+) -> Result<Json<SessionInfo>, AdminError> {
+    let session = find(&state, &id).await?;
+    let Json(body) = body?;
+    if session.role() != body.role {
+        info!("Admin API: session {id} is now {:?}", body.role);
+        session.set_role(body.role);
+        session.input.notify(HostMessage::notice(NoticeLevel::Info, match body.role {
+            Role::Control => "An administrator gave this session control of the host",
+            Role::View    => "An administrator made this session view-only",
+        }));
+    }
+    Ok(Json(describe(&session).await))
+}
+
+async fn force_keyframe(
+    State(state): State<AppState>,
+    Path(id):     Path<String>,
+) -> Result<StatusCode, AdminError> {
+    find(&state, &id).await?;
+    state.encoder.request_keyframe();
+    Ok(StatusCode::ACCEPTED)
+}
+
+async fn set_bitrate(
+    State(state): State<AppState>,
+    Path(id):     Path<String>,
+    body:         Result<Json<BitrateBody>, JsonRejection>,
+) -> Result<StatusCode, AdminError> {
+    find(&state, &id).await?;
+    let Json(body) = body?;
+    if !(MIN_BITRATE_BPS..=MAX_BITRATE_BPS).contains(&body.bitrate_bps) {
+        return Err(AdminError::new(
+            AdminErrorCode::InvalidRequest,
+            format!("bitrate_bps must be between {MIN_BITRATE_BPS} and {MAX_BITRATE_BPS}"),
+        ));
+    }
+    info!("Admin API: bitrate {} bps requested for session {id}", body.bitrate_bps);
+    state.encoder.set_bitrate(body.bitrate_bps);
+    Ok(StatusCode::ACCEPTED)
+}
+
+/// The Prometheus metrics of '/metrics' plus series labelled with session ids,
+/// which the unauthenticated endpoint leaves out.
+async fn serve_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    let sessions = state.peers.lock().await.values().cloned().collect();
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(sessions, true).await,
+    )
+}
@@ -0,0 +1,543 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    io::BufRead,
+    net::{IpAddr, SocketAddr},
+    sync::{mpsc, Arc, Mutex},
+    time::Duration,
+};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{Html, IntoResponse},
+    routing::{get, post},
+    Json, Router,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{oneshot, watch};
+use tracing::{error, info, warn};
+
+use crate::AppState;
+
+// Host-side approval of incoming sessions.
+// With 'LOCALBRIDGE_APPROVAL' set, '/offer' doesn't negotiate right away: it parks the
+// offer under a ticket, answers '{"status": "pending", "ticket": ...}' and asks the
+// person at the host through an 'Approver'. The browser shows a pending step and
+// polls 'GET /offer/<ticket>' until the negotiation ran (accepted) or failed
+// (rejected, timed out).
+//
+//   LOCALBRIDGE_APPROVAL=console   prompt on the host's console
+//   LOCALBRIDGE_APPROVAL=web       page at http://127.0.0.1:7878/approve (loopback only)
+//   LOCALBRIDGE_APPROVAL=script    run LOCALBRIDGE_APPROVAL_SCRIPT; exit code 0 accepts
+//
+// 'LOCALBRIDGE_APPROVAL_TIMEOUT' (seconds, default 60) rejects requests nobody answers.
+//
+// The '/approve' routes are served without CORS and only answer requests whose
+// 'Host' is a loopback name and whose 'Origin' (if any) is the page itself, so
+// neither another site open in the host's browser nor a DNS name rebound to
+// 127.0.0.1 can approve sessions.
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+// How long 'GET /offer/<ticket>' waits for a decision before answering "pending" again.
+const POLL_WAIT: Duration = Duration::from_secs(20);
+// How long an outcome is kept for a client that polls late.
+const OUTCOME_TTL: Duration = Duration::from_secs(60);
+// Requests that may wait for the host at once, from one address and in total, so
+// flooding '/offer' can't bury the host in prompts or park offers without limit.
+const MAX_WAITING_PER_ADDRESS: usize = 3;
+const MAX_WAITING: usize = 32;
+
+/// 'ApprovalRequest' is what the host is asked about.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApprovalRequest {
+    pub ticket:         String,
+    pub remote_address: SocketAddr,
+    pub user_agent:     Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Accept,
+    Reject,
+    // Nobody answered within the timeout.
+    TimedOut,
+    // Too many requests were already waiting; the host wasn't asked.
+    Busy,
+}
+
+impl Decision {
+    /// What the client is told when its session isn't accepted.
+    pub fn refusal(self) -> Option<&'static str> {
+        match self {
+            Decision::Accept   => None,
+            Decision::Reject   => Some("The host declined the connection"),
+            Decision::TimedOut => Some("The host didn't answer in time"),
+            Decision::Busy     => Some("Too many connection requests are waiting for the host; try again later"),
+        }
+    }
+}
+
+/// 'Approver' asks the host about a request and sends the answer on 'reply'.
+/// It must not block; the reply may come at any time, or never (the request then
+/// times out and 'reply' is closed).
+pub trait Approver: Send + Sync {
+    fn ask(&self, request: ApprovalRequest, reply: oneshot::Sender<Decision>);
+
+    /// Drops whatever is kept for a request that timed out.
+    fn forget(&self, _ticket: &str) {}
+}
+
+/// Where a parked offer stands.
+#[derive(Debug, Clone)]
+pub enum OfferStatus {
+    Pending,
+    // The SDP answer.
+    Answer(String),
+    Failed(String),
+}
+
+/// 'Approvals' parks offers until the approver decides on them.
+pub struct Approvals {
+    approver: Option<Arc<dyn Approver>>,
+    // Also in 'approver' when the web page is used; the '/approve' routes need it.
+    web:      Option<Arc<WebApprover>>,
+    timeout:  Duration,
+    offers:   Mutex<HashMap<String, watch::Receiver<OfferStatus>>>,
+    // Requests waiting for a decision, by address.
+    waiting:  Arc<Mutex<HashMap<IpAddr, usize>>>,
+}
+
+/// 'WaitingSlot' is a request's place among those waiting for the host, given back
+/// when it is dropped.
+struct WaitingSlot {
+    waiting: Arc<Mutex<HashMap<IpAddr, usize>>>,
+    address: IpAddr,
+}
+
+impl Drop for WaitingSlot {
+    fn drop(&mut self) {
+        let mut waiting = self.waiting.lock().unwrap();
+        if let Some(count) = waiting.get_mut(&self.address) {
+            *count -= 1;
+            if *count == 0 {
+                waiting.remove(&self.address);
+            }
+        }
+    }
+}
+
+impl Approvals {
+    pub fn from_env() -> Arc<Self> {
+        let timeout = std::env::var("LOCALBRIDGE_APPROVAL_TIMEOUT")
+            .ok()
+            .and_then(|v| v.parse::<u64>().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(DEFAULT_TIMEOUT);
+        let mode = std::env::var("LOCALBRIDGE_APPROVAL").unwrap_or_default();
+        let mut web = None;
+        let approver: Option<Arc<dyn Approver>> = match mode.as_str() {
+            "" | "off" => None,
+            "console" => Some(Arc::new(ConsoleApprover::spawn())),
+            "web" => {
+                let approver = Arc::new(WebApprover::default());
+                web = Some(Arc::clone(&approver));
+                Some(approver)
+            }
+            "script" => match std::env::var("LOCALBRIDGE_APPROVAL_SCRIPT") {
+                Ok(path) if !path.is_empty() => Some(Arc::new(ScriptApprover { path })),
+                _ => {
+                    // Failing closed: without a script nobody could ever be approved.
+                    warn!("LOCALBRIDGE_APPROVAL=script needs LOCALBRIDGE_APPROVAL_SCRIPT; rejecting every session");
+                    Some(Arc::new(RejectAll))
+                }
+            },
+            other => {
+                warn!("Unknown LOCALBRIDGE_APPROVAL '{other}' (expected console, web or script); rejecting every session");
+                Some(Arc::new(RejectAll))
+            }
+        };
+        if approver.is_some() {
+            info!("Sessions need approval at the host ({mode}, timeout {timeout:?})");
+        }
+        Arc::new(Self {
+            approver,
+            web,
+            timeout,
+            offers:  Mutex::new(HashMap::new()),
+            waiting: Arc::new(Mutex::new(HashMap::new())),
+        })
+    }
+
+    pub fn is_required(&self) -> bool {
+        self.approver.is_some()
+    }
+
+    /// Takes a place for a request from 'address', unless too many are waiting.
+    fn reserve(&self, address: IpAddr) -> Option<WaitingSlot> {
+        let mut waiting = self.waiting.lock().unwrap();
+        if waiting.values().sum::<usize>() >= MAX_WAITING {
+            warn!("Session request from {address} refused: {MAX_WAITING} requests are already waiting");
+            return None;
+        }
+        let count = waiting.entry(address).or_default();
+        if *count >= MAX_WAITING_PER_ADDRESS {
+            warn!("Session request from {address} refused: {count} of its requests are already waiting");
+            return None;
+        }
+        *count += 1;
+        Some(WaitingSlot { waiting: Arc::clone(&self.waiting), address })
+    }
+
+    /// Parks an offer: asks the approver and, once accepted, runs 'negotiate', which
+    /// returns the SDP answer. Nothing of the negotiation happens before that.
+    /// Returns 'Decision::Busy' without parking when too many requests are waiting.
+    pub fn park<F>(self: &Arc<Self>, request: ApprovalRequest, negotiate: F) -> Result<(), Decision>
+    where
+        F: Future<Output = anyhow::Result<String>> + Send + 'static,
+    {
+        let slot = self.reserve(request.remote_address.ip()).ok_or(Decision::Busy)?;
+        let ticket = request.ticket.clone();
+        let (status_tx, status_rx) = watch::channel(OfferStatus::Pending);
+        self.offers.lock().unwrap().insert(ticket.clone(), status_rx);
+
+        let approvals = Arc::clone(self);
+        tokio::spawn(async move {
+            let decision = approvals.wait_for_decision(request, slot).await;
+            let status = match decision.refusal() {
+                None => match negotiate.await {
+                    Ok(sdp) => OfferStatus::Answer(sdp),
+                    Err(e) => {
+                        error!("Offer error: {e}");
+                        OfferStatus::Failed(e.to_string())
+                    }
+                },
+                Some(refusal) => OfferStatus::Failed(refusal.to_owned()),
+            };
+            status_tx.send_replace(status);
+            tokio::time::sleep(OUTCOME_TTL).await;
+            approvals.offers.lock().unwrap().remove(&ticket);
+        });
+        Ok(())
+    }
+
+    /// Waits a while for a parked offer to be decided and returns where it stands,
+    /// or 'None' for an unknown ticket.
+    pub async fn status(&self, ticket: &str) -> Option<OfferStatus> {
+        let mut status = self.offers.lock().unwrap().get(ticket)?.clone();
+        let _ = tokio::time::timeout(POLL_WAIT, status.wait_for(|s| !matches!(s, OfferStatus::Pending))).await;
+        let current = status.borrow().clone();
+        Some(current)
+    }
+
+    /// Asks the approver about a request and waits for the answer (or the timeout).
+    /// Without an approver every request is accepted.
+    pub async fn ask(&self, request: ApprovalRequest) -> Decision {
+        if self.approver.is_none() {
+            return Decision::Accept;
+        }
+        match self.reserve(request.remote_address.ip()) {
+            Some(slot) => self.wait_for_decision(request, slot).await,
+            None => Decision::Busy,
+        }
+    }
+
+    /// 'ask' for a request that already has its place; the place is given back
+    /// once the decision is known.
+    async fn wait_for_decision(&self, request: ApprovalRequest, _slot: WaitingSlot) -> Decision {
+        let Some(approver) = &self.approver else { return Decision::Accept };
+        let ticket = request.ticket.clone();
+        let remote = request.remote_address;
+        info!("Session request {ticket} from {remote}: waiting for the host to approve");
+        let (reply_tx, reply_rx) = oneshot::channel();
+        approver.ask(request, reply_tx);
+        let decision = match tokio::time::timeout(self.timeout, reply_rx).await {
+            Ok(Ok(decision)) => decision,
+            // The approver gave up on the request.
+            Ok(Err(_)) => Decision::Reject,
+            Err(_) => {
+                approver.forget(&ticket);
+                Decision::TimedOut
+            }
+        };
+        info!("Session request {ticket} from {remote}: {decision:?}");
+        decision
+    }
+}
+
+/// Used when approval is on but misconfigured.
+struct RejectAll;
+
+impl Approver for RejectAll {
+    fn ask(&self, _request: ApprovalRequest, reply: oneshot::Sender<Decision>) {
+        let _ = reply.send(Decision::Reject);
+    }
+}
+
+/// Asks on the console. Requests are queued to an 'approval' thread that reads
+/// answers from stdin one at a time.
+struct ConsoleApprover {
+    tx: Mutex<mpsc::Sender<(ApprovalRequest, oneshot::Sender<Decision>)>>,
+}
+
+impl ConsoleApprover {
+    fn spawn() -> Self {
+        let (tx, rx) = mpsc::channel::<(ApprovalRequest, oneshot::Sender<Decision>)>();
+        std::thread::Builder::new()
+            .name("approval".to_owned())
+            .spawn(move || {
+                let stdin = std::io::stdin();
+                for (request, reply) in rx {
+                    // Timed out while an earlier prompt was open.
+                    if reply.is_closed() {
+                        continue;
+                    }
+                    println!(
+                        "\nAllow a remote session from {} ({})? [y/N]",
+                        request.remote_address,
+                        request.user_agent.as_deref().unwrap_or("unknown browser"),
+                    );
+                    let mut line = String::new();
+                    if stdin.lock().read_line(&mut line).is_err() {
+                        break;
+                    }
+                    let decision = match line.trim().to_ascii_lowercase().as_str() {
+                        "y" | "yes" => Decision::Accept,
+                        _ => Decision::Reject,
+                    };
+                    if reply.send(decision).is_err() {
+                        println!("Too late: that request already timed out.");
+                    }
+                }
+            })
+            .expect("failed to spawn approval thread");
+        Self { tx: Mutex::new(tx) }
+    }
+}
+
+impl Approver for ConsoleApprover {
+    fn ask(&self, request: ApprovalRequest, reply: oneshot::Sender<Decision>) {
+        // If the thread is gone, dropping 'reply' rejects the request.
+        let _ = self.tx.lock().unwrap().send((request, reply));
+    }
+}
+
+/// Runs a script with the request in its environment ('LOCALBRIDGE_TICKET',
+/// 'LOCALBRIDGE_PEER_ADDRESS', 'LOCALBRIDGE_PEER_USER_AGENT'). Exit code 0 accepts;
+/// the script is killed when the request times out.
+struct ScriptApprover {
+    path: String,
+}
+
+impl Approver for ScriptApprover {
+    fn ask(&self, request: ApprovalRequest, mut reply: oneshot::Sender<Decision>) {
+        let mut command = tokio::process::Command::new(&self.path);
+        command
+            .env("LOCALBRIDGE_TICKET", &request.ticket)
+            .env("LOCALBRIDGE_PEER_ADDRESS", request.remote_address.to_string())
+            .env("LOCALBRIDGE_PEER_USER_AGENT", request.user_agent.unwrap_or_default())
+            .kill_on_drop(true);
+        let path = self.path.clone();
+        tokio::spawn(async move {
+            let mut child = match command.spawn() {
+                Ok(child) => child,
+                Err(e) => {
+                    error!("Approval script '{path}' failed to start: {e}");
+                    let _ = reply.send(Decision::Reject);
+                    return;
+                }
+            };
+            tokio::select! {
+                status = child.wait() => {
+                    let accepted = matches!(status, Ok(s) if s.success());
+                    let _ = reply.send(if accepted { Decision::Accept } else { Decision::Reject });
+                }
+                // Timed out: dropping 'child' kills the script.
+                _ = reply.closed() => {}
+            }
+        });
+    }
+}
+
+/// Keeps requests until someone answers them on the '/approve' page or they time out.
+#[derive(Default)]
+pub struct WebApprover {
+    pending: Mutex<HashMap<String, (ApprovalRequest, oneshot::Sender<Decision>)>>,
+}
+
+impl Approver for WebApprover {
+    fn ask(&self, request: ApprovalRequest, reply: oneshot::Sender<Decision>) {
+        info!("Approve or reject it at http://127.0.0.1:7878/approve");
+        let mut pending = self.pending.lock().unwrap();
+        // Requests whose client went away before the timeout have a closed 'reply'.
+        pending.retain(|_, (_, reply)| !reply.is_closed());
+        pending.insert(request.ticket.clone(), (request, reply));
+    }
+
+    fn forget(&self, ticket: &str) {
+        self.pending.lock().unwrap().remove(ticket);
+    }
+}
+
+impl WebApprover {
+    /// Requests still waiting for an answer.
+    fn requests(&self) -> Vec<ApprovalRequest> {
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, (_, reply)| !reply.is_closed());
+        pending.values().map(|(request, _)| request.clone()).collect()
+    }
+
+    /// Answers a request; 'false' if it is unknown or already timed out.
+    fn decide(&self, ticket: &str, decision: Decision) -> bool {
+        match self.pending.lock().unwrap().remove(ticket) {
+            Some((_, reply)) => reply.send(decision).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Routes of the approval page. They only answer requests from the host itself,
+/// and are merged outside the CORS layer.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/approve",          get(serve_page))
+        .route("/approve/requests", get(list_requests))
+        .route("/approve/:ticket",  post(decide))
+}
+
+#[derive(Deserialize)]
+struct DecideBody {
+    accept: bool,
+}
+
+/// The web approver, if it is in use and the request comes from the host itself.
+fn local_web_approver(state: &AppState, remote: SocketAddr, headers: &HeaderMap) -> Result<Arc<WebApprover>, StatusCode> {
+    let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+    if !remote.ip().is_loopback() || !is_local_page(header(header::HOST), header(header::ORIGIN)) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    state.approvals.web.clone().ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Whether a request was addressed to the host by a loopback name and, if it came
+/// from a page, from the approval page itself.
+fn is_local_page(host: Option<&str>, origin: Option<&str>) -> bool {
+    let Some(host) = host else { return false };
+    // Drop the port; IPv6 addresses come in brackets.
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.bytes().all(|b| b.is_ascii_digit()) => name,
+        _ => host,
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    let loopback = name.eq_ignore_ascii_case("localhost") || name.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+    // Browsers send 'Origin' with every POST and cross-origin request; curl doesn't.
+    loopback && origin.is_none_or(|origin| origin.strip_prefix("http://") == Some(host))
+}
+
+async fn serve_page(
+    State(state):        State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers:             HeaderMap,
+) -> Result<impl IntoResponse, StatusCode> {
+    local_web_approver(&state, remote, &headers)?;
+    Ok(Html(include_str!("../client/approve.html")))
+}
+
+async fn list_requests(
+    State(state):        State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers:             HeaderMap,
+) -> Result<Json<Vec<ApprovalRequest>>, StatusCode> {
+    Ok(Json(local_web_approver(&state, remote, &headers)?.requests()))
+}
+
+async fn decide(
+    State(state):        State<AppState>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    headers:             HeaderMap,
+    Path(ticket):        Path<String>,
+    Json(body):          Json<DecideBody>,
+) -> StatusCode {
+    let web = match local_web_approver(&state, remote, &headers) {
+        Ok(web) => web,
+        Err(status) => return status,
+    };
+    let decision = if body.accept { Decision::Accept } else { Decision::Reject };
+    if web.decide(&ticket, decision) { StatusCode::NO_CONTENT } else { StatusCode::NOT_FOUND }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn approvals() -> Approvals {
+        Approvals {
+            approver: Some(Arc::new(RejectAll)),
+            web:      None,
+            timeout:  DEFAULT_TIMEOUT,
+            offers:   Mutex::new(HashMap::new()),
+            waiting:  Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    fn address(last: u8) -> IpAddr {
+        IpAddr::from([192, 168, 1, last])
+    }
+
+    #[test]
+    fn the_page_on_a_loopback_name_is_local() {
+        for host in ["127.0.0.1:7878", "localhost:7878", "LocalHost", "[::1]:7878", "127.0.0.1"] {
+            assert!(is_local_page(Some(host), None), "{host}");
+            assert!(is_local_page(Some(host), Some(&format!("http://{host}"))), "{host}");
+        }
+    }
+
+    #[test]
+    fn other_names_and_origins_are_not() {
+        // A DNS name rebound to 127.0.0.1 still carries its own name in 'Host'.
+        assert!(!is_local_page(Some("rebind.example:7878"), None));
+        assert!(!is_local_page(Some("127.0.0.1.example:7878"), None));
+        assert!(!is_local_page(None, None));
+        // Another site open in the host's browser.
+        assert!(!is_local_page(Some("127.0.0.1:7878"), Some("http://evil.example")));
+        assert!(!is_local_page(Some("127.0.0.1:7878"), Some("http://localhost:7878")));
+        assert!(!is_local_page(Some("127.0.0.1:7878"), Some("null")));
+    }
+
+    #[test]
+    fn waiting_requests_are_capped_per_address() {
+        let approvals = approvals();
+        let slots: Vec<_> = (0..MAX_WAITING_PER_ADDRESS).map(|_| approvals.reserve(address(1)).unwrap()).collect();
+        assert!(approvals.reserve(address(1)).is_none());
+        assert!(approvals.reserve(address(2)).is_some());
+        drop(slots);
+        assert!(approvals.reserve(address(1)).is_some());
+        assert!(approvals.waiting.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn timed_out_requests_leave_the_web_approver() {
+        let web = Arc::new(WebApprover::default());
+        let approvals = Approvals {
+            approver: Some(Arc::clone(&web) as Arc<dyn Approver>),
+            web:      Some(Arc::clone(&web)),
+            timeout:  Duration::from_millis(10),
+            ..approvals()
+        };
+        let request = ApprovalRequest {
+            ticket:         "ticket".to_owned(),
+            remote_address: SocketAddr::new(address(1), 50000),
+            user_agent:     None,
+        };
+        assert_eq!(approvals.ask(request).await, Decision::TimedOut);
+        assert!(web.pending.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn waiting_requests_are_capped_in_total() {
+        let approvals = approvals();
+        let slots: Vec<_> = (0..MAX_WAITING).map(|i| approvals.reserve(address(i as u8)).unwrap()).collect();
+        assert!(approvals.reserve(address(250)).is_none());
+        drop(slots);
+        assert!(approvals.reserve(address(250)).is_some());
+    }
+}
@@ -1,6 +1,6 @@
 use std::{sync::Arc, time::Duration};
 use anyhow::Result;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, watch};
 use tracing::{debug, error, info, warn};
 use webrtc::{media::Sample, track::track_local::track_local_static_sample::TrackLocalStaticSample};
 // 'windows_capture' is a library that provides high-performance screen capture on Windows.
@@ -15,7 +15,12 @@ use windows_capture::{
     },
 };
 
-use crate::encoder::H264Encoder;
+use crate::{
+    display::{self, DisplayGeometry},
+    encoder::{EncoderControl, EncoderSettings, H264Encoder},
+    latency::{self, FrameStamp},
+    metrics::Metrics,
+};
 
 // We want to capture and stream at 60 frames per second.
 const TARGET_FPS: u32 = 60;
@@ -27,6 +32,14 @@ struct CaptureFlags {
     track: Arc<TrackLocalStaticSample>,
     width: usize,
     height: usize,
+    monitor: Monitor,
+    display: Arc<watch::Sender<DisplayGeometry>>,
+    encoder_settings: Arc<watch::Sender<EncoderSettings>>,
+    encoder_control: Arc<EncoderControl>,
+    metrics: Arc<Metrics>,
+    // Whether this capture session draws the cursor, and whether it should.
+    with_cursor: bool,
+    capture_cursor: watch::Receiver<bool>,
 }
 
 fn select_capture_monitor() -> Result<Monitor> {
@@ -77,6 +90,13 @@ fn select_capture_monitor() -> Result<Monitor> {
 struct FrameHandler {
     encoder: H264Encoder,
     track:   Arc<TrackLocalStaticSample>,
+    monitor: Monitor,
+    display: Arc<watch::Sender<DisplayGeometry>>,
+    encoder_settings: Arc<watch::Sender<EncoderSettings>>,
+    encoder_control: Arc<EncoderControl>,
+    metrics: Arc<Metrics>,
+    with_cursor: bool,
+    capture_cursor: watch::Receiver<bool>,
     rt:      tokio::runtime::Handle,
     frame_count: u64,
     last_fps_log: std::time::Instant,
@@ -84,6 +104,52 @@ struct FrameHandler {
     accumulated_encoding_time: std::time::Duration,
 }
 
+impl FrameHandler {
+    /// Rebuilds the encoder for a new frame size and publishes the new geometry.
+    /// A fresh encoder starts with a keyframe, so clients receive new SPS/PPS
+    /// before any frame of the new size.
+    fn resize(&mut self, width: usize, height: usize) -> Result<()> {
+        let (old_w, old_h) = self.encoder.size();
+        info!("Capture size changed {old_w}x{old_h} -> {width}x{height}; rebuilding encoder");
+        self.encoder = H264Encoder::new(width, height, TARGET_FPS, self.encoder_control.bitrate())?;
+        self.encoder_settings.send_replace(self.encoder.settings());
+        // Input injection reads the geometry from this channel, so the mapping switches
+        // over in a single step together with the encoder.
+        self.refresh_geometry();
+        Ok(())
+    }
+
+    /// Rebuilds the encoder with a new target bitrate. Like a resize, the new
+    /// encoder starts with a keyframe.
+    fn set_bitrate(&mut self, bitrate_bps: u32) -> Result<()> {
+        let (width, height) = self.encoder.size();
+        info!("Bitrate changed {} -> {bitrate_bps} bps; rebuilding encoder", self.encoder.settings().bitrate_bps);
+        self.encoder = H264Encoder::new(width, height, TARGET_FPS, bitrate_bps)?;
+        self.encoder_settings.send_replace(self.encoder.settings());
+        Ok(())
+    }
+
+    /// Re-reads the monitor's position and DPI and publishes them if anything changed.
+    /// Rearranging monitors or changing the scaling doesn't always change the frame size,
+    /// so this also runs periodically.
+    fn refresh_geometry(&self) {
+        let (width, height) = self.encoder.size();
+        let geometry = display::query_geometry(&self.monitor, width as u32, height as u32);
+        self.display.send_if_modified(|current| {
+            if *current == geometry {
+                return false;
+            }
+            info!(
+                "Display geometry: {}x{} at ({}, {}), {} DPI ({:.0}%)",
+                geometry.width, geometry.height, geometry.x, geometry.y,
+                geometry.dpi, geometry.scale_factor() * 100.0
+            );
+            *current = geometry;
+            true
+        });
+    }
+}
+
 impl GraphicsCaptureApiHandler for FrameHandler {
     // These type aliases define what data we pass when creating a new handler.
     type Flags = CaptureFlags;
@@ -94,9 +160,18 @@ impl GraphicsCaptureApiHandler for FrameHandler {
         let flags = context.flags;
         
         // Initialize our H.264 encoder with the selected monitor's dimensions.
+        let encoder = H264Encoder::new(flags.width, flags.height, TARGET_FPS, flags.encoder_control.bitrate())?;
+        flags.encoder_settings.send_replace(encoder.settings());
         Ok(Self {
-            encoder: H264Encoder::new(flags.width, flags.height, TARGET_FPS)?,
+            encoder,
             track: flags.track,
+            monitor: flags.monitor,
+            display: flags.display,
+            encoder_settings: flags.encoder_settings,
+            encoder_control: flags.encoder_control,
+            metrics: flags.metrics,
+            with_cursor: flags.with_cursor,
+            capture_cursor: flags.capture_cursor,
             // We store a handle to the Tokio runtime so we can spawn tasks from inside 
             // the capture callback (which runs on its own thread).
             rt: tokio::runtime::Handle::current(),
@@ -111,18 +186,43 @@ impl GraphicsCaptureApiHandler for FrameHandler {
     fn on_frame_arrived(
         &mut self,
         frame: &mut Frame,
-        _ctrl: InternalCaptureControl,
+        ctrl:  InternalCaptureControl,
     ) -> Result<()> {
+        // Cursor capture can only be chosen when a capture session starts, so stop
+        // this one and let 'run' start a new one with the wanted setting.
+        if *self.capture_cursor.borrow() != self.with_cursor {
+            ctrl.stop();
+            return Ok(());
+        }
+
         let start = std::time::Instant::now();
+        let capture_us = latency::host_clock_us();
+
+        // 0. The frame pool is recreated by windows-capture when the monitor changes
+        // resolution or orientation, so the frame itself tells us the current size.
+        let frame_size = (frame.width() as usize, frame.height() as usize);
+        if frame_size != self.encoder.size() {
+            self.resize(frame_size.0, frame_size.1)?;
+        }
+        let bitrate = self.encoder_control.bitrate();
+        if bitrate != self.encoder.settings().bitrate_bps {
+            self.set_bitrate(bitrate)?;
+        }
+        if self.encoder_control.take_keyframe_request() {
+            self.encoder.request_keyframe();
+        }
 
         // 1. Get the raw pixel data (BGRA format) from the frame.
         let mut buf = frame.buffer()?;
         let raw = buf.as_nopadding_buffer()?;
         
         // 2. Encode the raw pixels into an H.264 bitstream (NAL units).
-        let nal = self.encoder.encode_bgra(raw)?;
+        let encoded = self.encoder.encode_bgra(raw)?;
         
         let elapsed = start.elapsed();
+        self.metrics.capture_frames.inc();
+        self.metrics.convert_seconds.observe_duration(encoded.convert_time);
+        self.metrics.encode_seconds.observe_duration(encoded.encode_time);
         self.accumulated_encoding_time += elapsed;
         self.encoded_frames_this_sec += 1;
 
@@ -145,26 +245,45 @@ impl GraphicsCaptureApiHandler for FrameHandler {
                 "Host Status -> Capturing & Encoding at {} FPS (avg encode time: {:.2?})",
                 actual_fps, avg_encode_time
             );
+            self.metrics.capture_fps.set(actual_fps as f64);
             self.encoded_frames_this_sec = 0;
             self.accumulated_encoding_time = std::time::Duration::from_secs(0);
             self.last_fps_log = std::time::Instant::now();
+            self.refresh_geometry();
         }
 
         // If the encoder didn't produce any data yet (some encoders buffer a few frames), just wait.
-        if nal.is_empty() { return Ok(()); }
+        // Rate control may also skip a frame entirely; either way nothing reaches the clients.
+        if encoded.data.is_empty() {
+            self.metrics.dropped_frames.inc();
+            return Ok(());
+        }
         self.frame_count += 1;
+        self.metrics.frame_bytes.observe(encoded.data.len() as f64);
+        if encoded.keyframe {
+            self.metrics.keyframes.inc();
+        }
+
+        // Stamp the frame so clients can measure capture-to-display latency.
+        let nal = latency::insert_sei(encoded.data, FrameStamp {
+            frame: self.frame_count,
+            capture_us,
+            encode_us: elapsed.as_micros() as u64,
+        });
 
         // 3. Send the encoded data to the WebRTC track.
         // We use 'rt.spawn' to move the network-sending work to an async task,
         // so we don't block the next frame from being captured.
         let track = self.track.clone();
         let dur   = Duration::from_secs(1) / TARGET_FPS;
+        let metrics = self.metrics.clone();
         self.rt.spawn(async move {
             if let Err(e) = track.write_sample(&Sample {
                 data:     nal.into(), // 'into()' converts Vec<u8> to Bytes
                 duration: dur,
                 ..Default::default()
             }).await {
+                metrics.dropped_frames.inc();
                 error!("write_sample: {e}");
             }
         });
@@ -180,8 +299,13 @@ impl GraphicsCaptureApiHandler for FrameHandler {
 
 /// The 'run' function starts the whole capture process.
 pub async fn run(
-    track: Arc<TrackLocalStaticSample>,
-    _tx:   broadcast::Sender<Vec<u8>>,
+    track:   Arc<TrackLocalStaticSample>,
+    _tx:     broadcast::Sender<Vec<u8>>,
+    display: watch::Sender<DisplayGeometry>,
+    encoder_settings: watch::Sender<EncoderSettings>,
+    encoder_control: Arc<EncoderControl>,
+    mut capture_cursor: watch::Receiver<bool>,
+    metrics: Arc<Metrics>,
 ) -> Result<()> {
     // Select the first monitor by index (with primary fallback).
     let mon = select_capture_monitor()?;
@@ -191,23 +315,57 @@ pub async fn run(
     let width = mon.width()? as usize;
     let height = mon.height()? as usize;
     info!("Capturing monitor #{mon_index}: {mon_name} ({mon_device}) {width}x{height}");
+    display.send_replace(display::query_geometry(&mon, width as u32, height as u32));
+    let display = Arc::new(display);
+    let encoder_settings = Arc::new(encoder_settings);
     
-    // Configure the capture settings.
-    let settings = Settings::new(
-        mon,
-        CursorCaptureSettings::WithCursor,    // Capture the mouse cursor too.
-        DrawBorderSettings::WithoutBorder,    // Don't show the yellow capture border.
-        SecondaryWindowSettings::Default,
-        MinimumUpdateIntervalSettings::Custom(Duration::from_millis(16)),
-        DirtyRegionSettings::ReportAndRender,
-        ColorFormat::Bgra8,                   // We want BGRA format (Blue-Green-Red-Alpha).
-        CaptureFlags { track, width, height }, // Pass track and size to keep encoder in sync.
-    );
+    loop {
+        let with_cursor = *capture_cursor.borrow_and_update();
+        // The frame size may have changed since the last capture session.
+        let (width, height) = {
+            let current = display.borrow();
+            (current.width as usize, current.height as usize)
+        };
+
+        // Configure the capture settings.
+        let settings = Settings::new(
+            mon,
+            if with_cursor {
+                CursorCaptureSettings::WithCursor     // Capture the mouse cursor too.
+            } else {
+                CursorCaptureSettings::WithoutCursor  // Relative (pointer lock) mode, or clients draw it.
+            },
+            DrawBorderSettings::WithoutBorder,    // Don't show the yellow capture border.
+            SecondaryWindowSettings::Default,
+            MinimumUpdateIntervalSettings::Custom(Duration::from_millis(16)),
+            DirtyRegionSettings::ReportAndRender,
+            ColorFormat::Bgra8,                   // We want BGRA format (Blue-Green-Red-Alpha).
+            CaptureFlags {                        // Pass track and size to keep encoder in sync.
+                track: track.clone(),
+                width,
+                height,
+                monitor: mon,
+                display: display.clone(),
+                encoder_settings: encoder_settings.clone(),
+                encoder_control: encoder_control.clone(),
+                metrics: metrics.clone(),
+                with_cursor,
+                capture_cursor: capture_cursor.clone(),
+            },
+        );
+
+        // 'FrameHandler::start' is a blocking call that begins the capture loop.
+        // We use 'spawn_blocking' because it uses a dedicated thread for heavy work.
+        tokio::task::spawn_blocking(|| FrameHandler::start(settings))
+            .await??;
 
-    // 'FrameHandler::start' is a blocking call that begins the capture loop.
-    // We use 'spawn_blocking' because it uses a dedicated thread for heavy work.
-    tokio::task::spawn_blocking(|| FrameHandler::start(settings))
-        .await??;
+        // The handler only stops on its own when the cursor setting changed;
+        // anything else (e.g. the monitor went away) ends the capture.
+        if *capture_cursor.borrow() == with_cursor {
+            break;
+        }
+        info!("Restarting capture {} cursor", if with_cursor { "without" } else { "with" });
+    }
         
     Ok(())
 }
@@ -0,0 +1,828 @@
+use std::{
+    collections::HashMap,
+    sync::{mpsc, Arc, Mutex},
+    time::{Duration, Instant},
+};
+use anyhow::Result;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc as async_mpsc;
+use tracing::{debug, info, warn};
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::{input::InputSession, protocol::ErrorCode};
+
+// Clipboard sync runs on its own reliable, ordered data channel ("clipboard"),
+// separate from the unordered input channel, so a large paste can't delay input
+// and a lost message can't corrupt a transfer.
+
+// How often the host clipboard is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+// Default limit for one clipboard item (serialized, so images count base64-encoded).
+const DEFAULT_MAX_BYTES: usize = 16 * 1024 * 1024;
+// Data channel messages are kept small; browsers and webrtc-rs both handle 16 KiB well.
+const CHUNK_SIZE: usize = 16 * 1024;
+
+/// 'ClipboardContent' is one clipboard item in every format we sync.
+/// Clients and backends fill in what they have. On write the X11 backend offers
+/// every format at once; arboard keeps the richest one (image, then HTML with its
+/// text fallback, then text).
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ClipboardContent {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text:  Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub html:  Option<String>,
+    // PNG file, base64-encoded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl ClipboardContent {
+    fn is_empty(&self) -> bool {
+        self.text.is_none() && self.html.is_none() && self.image.is_none()
+    }
+
+    fn size(&self) -> usize {
+        [&self.text, &self.html, &self.image].iter().map(|f| f.as_ref().map_or(0, String::len)).sum()
+    }
+}
+
+/// 'ClipboardMessage' is the protocol on the "clipboard" data channel.
+/// An item is sent as 'start', any number of 'chunk's whose 'data' concatenates to
+/// the JSON of a 'ClipboardContent', and 'end'.
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClipboardMessage {
+    // Host -> client, when the channel opens.
+    Policy { host_to_client: bool, client_to_host: bool, max_bytes: usize },
+    // Client -> host: turns sync on or off for this session.
+    Enable { enabled: bool },
+    Start { id: u32, size: usize },
+    Chunk { id: u32, data: String },
+    End { id: u32 },
+}
+
+/// Schema of the "clipboard" channel: the messages, and the item carried in the chunks.
+pub fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "message": schema_for!(ClipboardMessage),
+        "content": schema_for!(ClipboardContent),
+    })
+}
+
+/// 'ClipboardPolicy' says which directions are allowed and how large an item may be.
+/// Set with 'LOCALBRIDGE_CLIPBOARD' ("both", the default, "host-to-client",
+/// "client-to-host" or "off") and 'LOCALBRIDGE_CLIPBOARD_MAX_BYTES'.
+#[derive(Debug, Clone, Copy)]
+pub struct ClipboardPolicy {
+    pub host_to_client: bool,
+    pub client_to_host: bool,
+    pub max_bytes:      usize,
+}
+
+impl ClipboardPolicy {
+    fn from_env() -> Self {
+        let (host_to_client, client_to_host) = match std::env::var("LOCALBRIDGE_CLIPBOARD").as_deref() {
+            Ok("off") | Ok("none") | Ok("0") => (false, false),
+            Ok("host-to-client") => (true, false),
+            Ok("client-to-host") => (false, true),
+            _ => (true, true),
+        };
+        let max_bytes = std::env::var("LOCALBRIDGE_CLIPBOARD_MAX_BYTES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_MAX_BYTES);
+        Self { host_to_client, client_to_host, max_bytes }
+    }
+}
+
+/// Access to the host clipboard. One implementation per platform.
+pub trait ClipboardBackend {
+    /// A value that changes whenever the clipboard does, if the platform has one.
+    /// Backends returning 'None' are polled by reading the content.
+    fn change_count(&mut self) -> Option<u64>;
+    fn read(&mut self) -> Result<ClipboardContent>;
+    fn write(&mut self, content: &ClipboardContent) -> Result<()>;
+}
+
+#[cfg(windows)]
+fn default_backend() -> Result<Box<dyn ClipboardBackend>> {
+    Ok(Box::new(windows_backend::ArboardBackend::new()?))
+}
+
+#[cfg(target_os = "linux")]
+fn default_backend() -> Result<Box<dyn ClipboardBackend>> {
+    Ok(Box::new(x11_backend::X11Backend::new()?))
+}
+
+enum ClipboardCommand {
+    Subscribe { session: Arc<str>, tx: async_mpsc::UnboundedSender<ClipboardContent> },
+    SetEnabled { session: Arc<str>, enabled: bool },
+    Write { session: Arc<str>, content: ClipboardContent },
+    Unsubscribe { session: Arc<str> },
+}
+
+/// 'ClipboardHandle' is the cloneable entry point to the clipboard thread.
+#[derive(Clone)]
+pub struct ClipboardHandle {
+    tx:     mpsc::Sender<ClipboardCommand>,
+    policy: ClipboardPolicy,
+}
+
+/// Starts the clipboard thread, which owns the backend and watches for host changes.
+pub fn spawn() -> ClipboardHandle {
+    let policy = ClipboardPolicy::from_env();
+    info!(
+        "Clipboard sync: host->client={}, client->host={}, max {} bytes",
+        policy.host_to_client, policy.client_to_host, policy.max_bytes
+    );
+    let (tx, rx) = mpsc::channel();
+    std::thread::Builder::new()
+        .name("clipboard".to_owned())
+        .spawn(move || ClipboardThread::new(policy).run(rx))
+        .expect("failed to spawn clipboard thread");
+    ClipboardHandle { tx, policy }
+}
+
+impl ClipboardHandle {
+    /// Whether sync is allowed in at least one direction.
+    pub fn is_enabled(&self) -> bool {
+        self.policy.host_to_client || self.policy.client_to_host
+    }
+
+    pub fn session(&self, id: String) -> Arc<ClipboardSession> {
+        Arc::new(ClipboardSession { id: id.into(), tx: self.tx.clone(), policy: self.policy })
+    }
+}
+
+/// 'ClipboardSession' is the clipboard side of one connected peer.
+pub struct ClipboardSession {
+    id:     Arc<str>,
+    tx:     mpsc::Sender<ClipboardCommand>,
+    policy: ClipboardPolicy,
+}
+
+impl ClipboardSession {
+    fn send(&self, cmd: ClipboardCommand) {
+        let _ = self.tx.send(cmd);
+    }
+
+    fn unsubscribe(&self) {
+        self.send(ClipboardCommand::Unsubscribe { session: Arc::clone(&self.id) });
+    }
+}
+
+impl Drop for ClipboardSession {
+    fn drop(&mut self) {
+        self.unsubscribe();
+    }
+}
+
+struct Subscriber {
+    tx:      async_mpsc::UnboundedSender<ClipboardContent>,
+    enabled: bool,
+}
+
+struct ClipboardThread {
+    policy:      ClipboardPolicy,
+    backend:     Option<Box<dyn ClipboardBackend>>,
+    subscribers: HashMap<Arc<str>, Subscriber>,
+    // What the host clipboard held when we last looked or wrote, to detect changes
+    // and to avoid sending a client's own paste back to it.
+    last:        ClipboardContent,
+    last_count:  Option<u64>,
+    last_poll:   Instant,
+}
+
+impl ClipboardThread {
+    fn new(policy: ClipboardPolicy) -> Self {
+        let backend = if policy.host_to_client || policy.client_to_host {
+            match default_backend() {
+                Ok(backend) => Some(backend),
+                Err(e) => {
+                    warn!("Clipboard unavailable, sync disabled: {e}");
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let mut thread = Self {
+            policy,
+            backend,
+            subscribers: HashMap::new(),
+            last:        ClipboardContent::default(),
+            last_count:  None,
+            last_poll:   Instant::now(),
+        };
+        // Whatever is on the clipboard at startup isn't a change worth sending.
+        if let Some(backend) = thread.backend.as_mut() {
+            thread.last_count = backend.change_count();
+            thread.last = backend.read().unwrap_or_default();
+        }
+        thread
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<ClipboardCommand>) {
+        loop {
+            match rx.recv_timeout(POLL_INTERVAL) {
+                Ok(cmd) => self.execute(cmd),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            if self.last_poll.elapsed() >= POLL_INTERVAL {
+                self.poll();
+                self.last_poll = Instant::now();
+            }
+        }
+        debug!("Clipboard thread stopped");
+    }
+
+    fn execute(&mut self, cmd: ClipboardCommand) {
+        match cmd {
+            ClipboardCommand::Subscribe { session, tx } => {
+                self.subscribers.insert(session, Subscriber { tx, enabled: true });
+            }
+            ClipboardCommand::SetEnabled { session, enabled } => {
+                if let Some(sub) = self.subscribers.get_mut(&session) {
+                    info!("Session {session}: clipboard sync {}", if enabled { "enabled" } else { "disabled" });
+                    sub.enabled = enabled;
+                }
+            }
+            ClipboardCommand::Write { session, content } => self.write(session, content),
+            ClipboardCommand::Unsubscribe { session } => {
+                self.subscribers.remove(&session);
+            }
+        }
+    }
+
+    /// Puts a client's clipboard item on the host and passes it on to the other sessions.
+    fn write(&mut self, session: Arc<str>, content: ClipboardContent) {
+        let enabled = self.subscribers.get(&session).is_some_and(|s| s.enabled);
+        if !self.policy.client_to_host || !enabled {
+            debug!("Session {session}: clipboard write ignored (sync disabled)");
+            return;
+        }
+        if content.is_empty() || content.size() > self.policy.max_bytes {
+            warn!("Session {session}: clipboard item of {} bytes rejected", content.size());
+            return;
+        }
+        let Some(backend) = self.backend.as_mut() else { return };
+        if let Err(e) = backend.write(&content) {
+            warn!("Clipboard write failed: {e}");
+            return;
+        }
+        info!("Session {session}: clipboard updated ({} bytes)", content.size());
+        self.last_count = backend.change_count();
+        // Read back what the backend actually stored, so the next poll compares like with like.
+        self.last = backend.read().unwrap_or_else(|_| content.clone());
+        self.broadcast(&content, Some(&session));
+    }
+
+    /// Sends host clipboard changes to every enabled session.
+    fn poll(&mut self) {
+        let Some(backend) = self.backend.as_mut() else { return };
+        let count = backend.change_count();
+        if count.is_some() && count == self.last_count {
+            return;
+        }
+        self.last_count = count;
+        let content = match backend.read() {
+            Ok(content) => content,
+            Err(e) => {
+                debug!("Clipboard read failed: {e}");
+                return;
+            }
+        };
+        if content == self.last || content.is_empty() {
+            return;
+        }
+        self.last = content.clone();
+        if content.size() > self.policy.max_bytes {
+            warn!("Host clipboard item of {} bytes is over the limit; not sent", content.size());
+            return;
+        }
+        self.broadcast(&content, None);
+    }
+
+    fn broadcast(&self, content: &ClipboardContent, except: Option<&Arc<str>>) {
+        if !self.policy.host_to_client {
+            return;
+        }
+        for (session, sub) in &self.subscribers {
+            if sub.enabled && Some(session) != except {
+                let _ = sub.tx.send(content.clone());
+            }
+        }
+    }
+}
+
+/// Sets up the "clipboard" data channel of one peer.
+/// 'input' holds the peer's role: view-only sessions still receive the host
+/// clipboard but can't write it.
+pub async fn handle_data_channel(dc: Arc<RTCDataChannel>, session: Arc<ClipboardSession>, input: Arc<InputSession>) {
+    let dc_open = Arc::clone(&dc);
+    let session_open = Arc::clone(&session);
+    dc.on_open(Box::new(move || {
+        Box::pin(async move {
+            let policy = session_open.policy;
+            let hello = ClipboardMessage::Policy {
+                host_to_client: policy.host_to_client,
+                client_to_host: policy.client_to_host,
+                max_bytes:      policy.max_bytes,
+            };
+            if send_message(&dc_open, &hello).await.is_err() {
+                return;
+            }
+
+            let (tx, mut rx) = async_mpsc::unbounded_channel();
+            session_open.send(ClipboardCommand::Subscribe { session: Arc::clone(&session_open.id), tx });
+            tokio::spawn(async move {
+                let mut next_id = 0u32;
+                while let Some(content) = rx.recv().await {
+                    next_id = next_id.wrapping_add(1);
+                    if send_content(&dc_open, next_id, &content).await.is_err() {
+                        break;
+                    }
+                }
+            });
+        })
+    }));
+
+    let session_close = Arc::clone(&session);
+    dc.on_close(Box::new(move || {
+        session_close.unsubscribe();
+        Box::pin(async {})
+    }));
+
+    // The item being received, as (id, serialized content so far).
+    let incoming: Arc<Mutex<Option<(u32, String)>>> = Arc::new(Mutex::new(None));
+    dc.on_message(Box::new(move |msg| {
+        let session = Arc::clone(&session);
+        let incoming = Arc::clone(&incoming);
+        let input = Arc::clone(&input);
+        Box::pin(async move {
+            let message = match std::str::from_utf8(&msg.data).map(serde_json::from_str::<ClipboardMessage>) {
+                Ok(Ok(message)) => message,
+                _ => {
+                    warn!("Bad clipboard message");
+                    return;
+                }
+            };
+            let max_bytes = session.policy.max_bytes;
+            let mut incoming = incoming.lock().unwrap();
+            match message {
+                ClipboardMessage::Enable { enabled } => {
+                    session.send(ClipboardCommand::SetEnabled { session: Arc::clone(&session.id), enabled });
+                }
+                ClipboardMessage::Start { id, size } => {
+                    if !may_write(&input) {
+                        *incoming = None;
+                        return;
+                    }
+                    *incoming = (size <= max_bytes).then(|| (id, String::with_capacity(size)));
+                    if incoming.is_none() {
+                        warn!("Clipboard item of {size} bytes rejected (limit {max_bytes})");
+                    }
+                }
+                ClipboardMessage::Chunk { id, data } => {
+                    if let Some((current, buf)) = incoming.as_mut() {
+                        if *current == id {
+                            buf.push_str(&data);
+                            if buf.len() > max_bytes {
+                                warn!("Clipboard item exceeded {max_bytes} bytes; dropped");
+                                *incoming = None;
+                            }
+                        }
+                    }
+                }
+                ClipboardMessage::End { id } => {
+                    if let Some((current, buf)) = incoming.take() {
+                        // The session may have been made view-only since the start.
+                        if current != id || !may_write(&input) {
+                            return;
+                        }
+                        match serde_json::from_str::<ClipboardContent>(&buf) {
+                            Ok(content) => session.send(ClipboardCommand::Write {
+                                session: Arc::clone(&session.id),
+                                content,
+                            }),
+                            Err(e) => warn!("Bad clipboard item: {e}"),
+                        }
+                    }
+                }
+                ClipboardMessage::Policy { .. } => {}
+            }
+        })
+    }));
+}
+
+/// Whether the session may write the host clipboard; tells the client when not.
+fn may_write(input: &InputSession) -> bool {
+    if !input.has_control() {
+        input.report_error(ErrorCode::ViewOnly, "view-only sessions can't write the host clipboard");
+        return false;
+    }
+    true
+}
+
+async fn send_message(dc: &RTCDataChannel, message: &ClipboardMessage) -> Result<()> {
+    dc.send_text(serde_json::to_string(message)?).await?;
+    Ok(())
+}
+
+/// Sends one clipboard item as start / chunk... / end.
+async fn send_content(dc: &RTCDataChannel, id: u32, content: &ClipboardContent) -> Result<()> {
+    let json = serde_json::to_string(content)?;
+    send_message(dc, &ClipboardMessage::Start { id, size: json.len() }).await?;
+    let mut rest = json.as_str();
+    while !rest.is_empty() {
+        // Split on a character boundary; each chunk has to be valid UTF-8 on its own.
+        let mut end = rest.len().min(CHUNK_SIZE);
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        let (chunk, tail) = rest.split_at(end);
+        send_message(dc, &ClipboardMessage::Chunk { id, data: chunk.to_owned() }).await?;
+        rest = tail;
+    }
+    send_message(dc, &ClipboardMessage::End { id }).await
+}
+
+/// PNG <-> RGBA8 conversion for backends that exchange raw pixels.
+#[cfg_attr(not(windows), allow(dead_code))]
+pub(crate) mod png_image {
+    use anyhow::{bail, Result};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+
+    pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Result<String> {
+        let mut png = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut png, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header()?;
+            writer.write_image_data(rgba)?;
+        }
+        Ok(STANDARD.encode(png))
+    }
+
+    /// Returns (width, height, RGBA8 pixels).
+    pub fn decode(base64: &str) -> Result<(usize, usize, Vec<u8>)> {
+        let png = STANDARD.decode(base64)?;
+        let mut decoder = png::Decoder::new(png.as_slice());
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        let mut reader = decoder.read_info()?;
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut buf)?;
+        buf.truncate(info.buffer_size());
+        let rgba = match info.color_type {
+            png::ColorType::Rgba => buf,
+            png::ColorType::Rgb => buf.chunks_exact(3).flat_map(|p| [p[0], p[1], p[2], 255]).collect(),
+            png::ColorType::GrayscaleAlpha => buf.chunks_exact(2).flat_map(|p| [p[0], p[0], p[0], p[1]]).collect(),
+            png::ColorType::Grayscale => buf.iter().flat_map(|&g| [g, g, g, 255]).collect(),
+            other => bail!("unsupported PNG color type {other:?}"),
+        };
+        Ok((info.width as usize, info.height as usize, rgba))
+    }
+}
+
+/// Windows backend on top of 'arboard'.
+#[cfg(windows)]
+mod windows_backend {
+    use std::borrow::Cow;
+    use anyhow::Result;
+    use super::{png_image, ClipboardBackend, ClipboardContent};
+
+    extern "system" {
+        // Incremented by Windows on every clipboard change.
+        fn GetClipboardSequenceNumber() -> u32;
+    }
+
+    pub struct ArboardBackend {
+        clipboard: arboard::Clipboard,
+    }
+
+    impl ArboardBackend {
+        pub fn new() -> Result<Self> {
+            Ok(Self { clipboard: arboard::Clipboard::new()? })
+        }
+    }
+
+    impl ClipboardBackend for ArboardBackend {
+        fn change_count(&mut self) -> Option<u64> {
+            Some(unsafe { GetClipboardSequenceNumber() } as u64)
+        }
+
+        fn read(&mut self) -> Result<ClipboardContent> {
+            let text = self.clipboard.get_text().ok();
+            let html = self.clipboard.get().html().ok();
+            let image = match self.clipboard.get_image() {
+                Ok(image) => Some(png_image::encode(image.width, image.height, &image.bytes)?),
+                Err(_) => None,
+            };
+            Ok(ClipboardContent { text, html, image })
+        }
+
+        fn write(&mut self, content: &ClipboardContent) -> Result<()> {
+            if let Some(image) = &content.image {
+                let (width, height, rgba) = png_image::decode(image)?;
+                self.clipboard.set_image(arboard::ImageData { width, height, bytes: Cow::Owned(rgba) })?;
+            } else if let Some(html) = &content.html {
+                self.clipboard.set_html(html, content.text.as_deref())?;
+            } else if let Some(text) = &content.text {
+                self.clipboard.set_text(text)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Linux backend that talks to the X11 'CLIPBOARD' selection directly. It also
+/// runs against a virtual X server (Xvfb), so sync can be exercised headlessly:
+/// 'xvfb-run cargo test -- --ignored' runs the tests below.
+#[cfg(target_os = "linux")]
+mod x11_backend {
+    use std::{
+        collections::HashMap,
+        sync::{Arc, Mutex},
+        thread,
+        time::Duration,
+    };
+    use anyhow::{bail, Result};
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use tracing::warn;
+    use x11_clipboard::Clipboard;
+    use x11rb::{
+        connection::Connection,
+        protocol::{
+            xproto::{
+                Atom, AtomEnum, ChangeWindowAttributesAux, ConnectionExt as _, CreateWindowAux, EventMask,
+                PropMode, Property, SelectionNotifyEvent, SelectionRequestEvent, Window, WindowClass,
+                SELECTION_NOTIFY_EVENT,
+            },
+            Event,
+        },
+        rust_connection::RustConnection,
+        wrapper::ConnectionExt as _,
+        COPY_DEPTH_FROM_PARENT, CURRENT_TIME, NONE,
+    };
+    use super::{ClipboardBackend, ClipboardContent};
+
+    // How long to wait for the selection owner to answer.
+    const LOAD_TIMEOUT: Duration = Duration::from_millis(500);
+
+    pub struct X11Backend {
+        // Reads whatever application owns the selection.
+        clipboard: Clipboard,
+        // Owns it for what clients copy.
+        owner:     Owner,
+    }
+
+    impl X11Backend {
+        pub fn new() -> Result<Self> {
+            Ok(Self { clipboard: Clipboard::new()?, owner: Owner::new()? })
+        }
+
+        fn load(&self, target: Atom) -> Option<Vec<u8>> {
+            let atoms = &self.clipboard.getter.atoms;
+            self.clipboard
+                .load(atoms.clipboard, target, atoms.property, LOAD_TIMEOUT)
+                .ok()
+                .filter(|data| !data.is_empty())
+        }
+    }
+
+    impl ClipboardBackend for X11Backend {
+        fn change_count(&mut self) -> Option<u64> {
+            None
+        }
+
+        fn read(&mut self) -> Result<ClipboardContent> {
+            let atoms = self.owner.atoms;
+            let text = self.load(atoms.utf8_string).and_then(|d| String::from_utf8(d).ok());
+            let html = self.load(atoms.html).and_then(|d| String::from_utf8(d).ok());
+            let image = self.load(atoms.png).map(|d| STANDARD.encode(d));
+            Ok(ClipboardContent { text, html, image })
+        }
+
+        // Every format of the item is offered, so pasting plain text still works
+        // after the client copied HTML or an image with a text fallback.
+        fn write(&mut self, content: &ClipboardContent) -> Result<()> {
+            let atoms = self.owner.atoms;
+            let mut formats = Vec::new();
+            if let Some(image) = &content.image {
+                formats.push((atoms.png, STANDARD.decode(image)?));
+            }
+            if let Some(html) = &content.html {
+                formats.push((atoms.html, html.as_bytes().to_vec()));
+            }
+            if let Some(text) = &content.text {
+                formats.push((atoms.utf8_string, text.as_bytes().to_vec()));
+                formats.push((atoms.text_plain, text.as_bytes().to_vec()));
+            }
+            if formats.is_empty() {
+                return Ok(());
+            }
+            self.owner.offer(formats)
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    struct Atoms {
+        clipboard:   Atom,
+        targets:     Atom,
+        incr:        Atom,
+        utf8_string: Atom,
+        text_plain:  Atom,
+        html:        Atom,
+        png:         Atom,
+    }
+
+    /// 'Owner' holds the 'CLIPBOARD' selection with a hidden window and answers
+    /// other applications' requests for it on the "x11 clipboard" thread.
+    struct Owner {
+        conn:   Arc<RustConnection>,
+        window: Window,
+        atoms:  Atoms,
+        // The item being offered: one entry per format (target).
+        offer:  Arc<Mutex<Vec<(Atom, Vec<u8>)>>>,
+    }
+
+    impl Owner {
+        fn new() -> Result<Self> {
+            let (conn, screen) = RustConnection::connect(None)?;
+            let root = conn.setup().roots[screen].root;
+            let window = conn.generate_id()?;
+            conn.create_window(
+                COPY_DEPTH_FROM_PARENT, window, root, 0, 0, 1, 1, 0,
+                WindowClass::INPUT_OUTPUT, 0, &CreateWindowAux::new(),
+            )?;
+            let intern = |name: &str| -> Result<Atom> { Ok(conn.intern_atom(false, name.as_bytes())?.reply()?.atom) };
+            let atoms = Atoms {
+                clipboard:   intern("CLIPBOARD")?,
+                targets:     intern("TARGETS")?,
+                incr:        intern("INCR")?,
+                utf8_string: intern("UTF8_STRING")?,
+                text_plain:  intern("text/plain;charset=utf-8")?,
+                html:        intern("text/html")?,
+                png:         intern("image/png")?,
+            };
+            conn.flush()?;
+
+            let conn = Arc::new(conn);
+            let offer = Arc::new(Mutex::new(Vec::new()));
+            let (serve_conn, serve_offer) = (Arc::clone(&conn), Arc::clone(&offer));
+            thread::Builder::new().name("x11 clipboard".into()).spawn(move || {
+                if let Err(e) = serve(&serve_conn, atoms, &serve_offer) {
+                    warn!("X11 clipboard owner stopped: {e}");
+                }
+            })?;
+            Ok(Self { conn, window, atoms, offer })
+        }
+
+        /// Replaces the offered item and takes the selection.
+        fn offer(&self, formats: Vec<(Atom, Vec<u8>)>) -> Result<()> {
+            *self.offer.lock().unwrap() = formats;
+            self.conn.set_selection_owner(self.window, self.atoms.clipboard, CURRENT_TIME)?;
+            if self.conn.get_selection_owner(self.atoms.clipboard)?.reply()?.owner != self.window {
+                bail!("could not take the X11 clipboard selection");
+            }
+            Ok(())
+        }
+    }
+
+    // An incremental transfer: the format, its data and how much was sent.
+    type Transfer = (Atom, Vec<u8>, usize);
+
+    /// Answers requests for the offered item until the connection fails. Items too
+    /// large for one request are sent in chunks (the ICCCM 'INCR' protocol): the
+    /// requestor deletes the property after reading each one.
+    fn serve(conn: &RustConnection, atoms: Atoms, offer: &Mutex<Vec<(Atom, Vec<u8>)>>) -> Result<()> {
+        // Leave plenty of room for the request header.
+        let chunk_size = (conn.maximum_request_bytes() / 4).max(4096);
+        let mut transfers: HashMap<(Window, Atom), Transfer> = HashMap::new();
+        loop {
+            match conn.wait_for_event()? {
+                Event::SelectionRequest(request) => {
+                    let property = answer(conn, atoms, offer, &request, chunk_size, &mut transfers)?;
+                    let notify = SelectionNotifyEvent {
+                        response_type: SELECTION_NOTIFY_EVENT,
+                        sequence:      0,
+                        time:          request.time,
+                        requestor:     request.requestor,
+                        selection:     request.selection,
+                        target:        request.target,
+                        property,
+                    };
+                    conn.send_event(false, request.requestor, EventMask::NO_EVENT, notify)?;
+                    conn.flush()?;
+                }
+                Event::PropertyNotify(event) if event.state == Property::DELETE => {
+                    let key = (event.window, event.atom);
+                    let Some((target, data, sent)) = transfers.get_mut(&key) else { continue };
+                    let end = (*sent + chunk_size).min(data.len());
+                    conn.change_property8(PropMode::REPLACE, event.window, event.atom, *target, &data[*sent..end])?;
+                    // The empty chunk after the last one ends the transfer.
+                    if end == *sent {
+                        transfers.remove(&key);
+                    } else {
+                        *sent = end;
+                    }
+                    conn.flush()?;
+                }
+                // Another application copied something.
+                Event::SelectionClear(_) => offer.lock().unwrap().clear(),
+                _ => {}
+            }
+        }
+    }
+
+    /// Puts the requested format on the requestor's property and returns that
+    /// property, or 'NONE' to refuse the request.
+    fn answer(
+        conn:       &RustConnection,
+        atoms:      Atoms,
+        offer:      &Mutex<Vec<(Atom, Vec<u8>)>>,
+        request:    &SelectionRequestEvent,
+        chunk_size: usize,
+        transfers:  &mut HashMap<(Window, Atom), Transfer>,
+    ) -> Result<Atom> {
+        if request.selection != atoms.clipboard {
+            return Ok(NONE);
+        }
+        // Obsolete clients leave the property out and expect the target's name.
+        let property = if request.property == NONE { request.target } else { request.property };
+        let offer = offer.lock().unwrap();
+
+        if request.target == atoms.targets {
+            let mut targets = vec![atoms.targets];
+            targets.extend(offer.iter().map(|(target, _)| *target));
+            conn.change_property32(PropMode::REPLACE, request.requestor, property, AtomEnum::ATOM, &targets)?;
+            return Ok(property);
+        }
+        let Some((_, data)) = offer.iter().find(|(target, _)| *target == request.target) else {
+            return Ok(NONE);
+        };
+        if data.len() <= chunk_size {
+            conn.change_property8(PropMode::REPLACE, request.requestor, property, request.target, data)?;
+        } else {
+            // Announce the size; the chunks follow as the requestor deletes the property.
+            conn.change_window_attributes(
+                request.requestor,
+                &ChangeWindowAttributesAux::new().event_mask(EventMask::PROPERTY_CHANGE),
+            )?;
+            conn.change_property32(PropMode::REPLACE, request.requestor, property, atoms.incr, &[data.len() as u32])?;
+            transfers.insert((request.requestor, property), (request.target, data.clone(), 0));
+        }
+        Ok(property)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        fn content(text: Option<&str>, html: Option<&str>, image: Option<Vec<u8>>) -> ClipboardContent {
+            ClipboardContent {
+                text:  text.map(str::to_owned),
+                html:  html.map(str::to_owned),
+                image: image.map(|png| STANDARD.encode(png)),
+            }
+        }
+
+        /// Writes 'item' as the host does for a client, then reads it back through
+        /// a second X connection, like an application pasting on the host.
+        fn round_trip(item: &ClipboardContent) -> ClipboardContent {
+            let mut host = X11Backend::new().expect("no X server; run under xvfb-run");
+            host.write(item).unwrap();
+            let mut application = X11Backend::new().unwrap();
+            let pasted = application.read().unwrap();
+            drop(host);
+            pasted
+        }
+
+        #[test]
+        #[ignore = "needs an X server: xvfb-run cargo test -- --ignored"]
+        fn html_keeps_its_plain_text() {
+            let item = content(Some("bold move"), Some("<b>bold</b> move"), None);
+            assert_eq!(round_trip(&item), item);
+        }
+
+        #[test]
+        #[ignore = "needs an X server: xvfb-run cargo test -- --ignored"]
+        fn text_only() {
+            let item = content(Some("plain"), None, None);
+            assert_eq!(round_trip(&item), item);
+        }
+
+        #[test]
+        #[ignore = "needs an X server: xvfb-run cargo test -- --ignored"]
+        fn large_image_is_sent_incrementally() {
+            // Far larger than one X request, so it has to go through INCR.
+            let png: Vec<u8> = (0..4 * 1024 * 1024u32).map(|i| (i * 7 + i / 251) as u8).collect();
+            let item = content(Some("fallback"), None, Some(png));
+            assert_eq!(round_trip(&item), item);
+        }
+    }
+}
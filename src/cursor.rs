@@ -0,0 +1,501 @@
+use std::{collections::VecDeque, ffi::c_void, time::Duration};
+use anyhow::{bail, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::clipboard::png_image;
+
+// Watches the host cursor's shape and position so clients can be told about them.
+// Applications change the cursor without any notification we could subscribe to, so
+// the 'cursor' thread polls 'GetCursorInfo' and only converts a cursor the first time
+// it is seen (see 'ShapeCache').
+
+// Once per frame at 60 FPS; positions are coalesced to this rate anyway.
+const POLL_INTERVAL: Duration = Duration::from_millis(16);
+// Cursors are at most 256x256 even at high DPI; anything larger is treated as broken.
+const MAX_CURSOR_SIZE: u32 = 256;
+// Converted shapes kept around. Applications that animate or generate their cursors
+// go through new handles all the time; the oldest are dropped beyond this.
+const MAX_CACHED_SHAPES: usize = 64;
+
+/// 'CursorShape' is the look of the host cursor, sent to clients as a 'cursor' message.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct CursorShape {
+    // False while an application hides the cursor; the other fields are then empty.
+    pub visible:   bool,
+    pub width:     u32,
+    pub height:    u32,
+    // The pixel of the image that sits at the pointer position.
+    pub hotspot_x: u32,
+    pub hotspot_y: u32,
+    // PNG, base64-encoded.
+    pub image:     String,
+}
+
+/// 'CursorMode' says where the cursor is drawn, set with 'LOCALBRIDGE_CURSOR'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorMode {
+    /// Captured into the video (the default); works with every client.
+    Video,
+    /// Left out of the video; clients draw it from 'cursor' and 'cursor_position'
+    /// messages, so it moves at input rate instead of a frame behind.
+    Client,
+}
+
+impl CursorMode {
+    pub fn from_env() -> Self {
+        match std::env::var("LOCALBRIDGE_CURSOR").as_deref() {
+            Ok("client") => CursorMode::Client,
+            _ => CursorMode::Video,
+        }
+    }
+}
+
+/// 'CursorFeed' is what the cursor thread publishes.
+#[derive(Clone)]
+pub struct CursorFeed {
+    pub shape:    watch::Receiver<CursorShape>,
+    // Virtual-desktop pixel the pointer is at.
+    pub position: watch::Receiver<(i32, i32)>,
+}
+
+/// Starts the cursor thread. The receivers always hold the current state.
+pub fn spawn() -> CursorFeed {
+    let (shape_tx, shape) = watch::channel(CursorShape::default());
+    let (position_tx, position) = watch::channel((0, 0));
+    std::thread::Builder::new()
+        .name("cursor".to_owned())
+        .spawn(move || run(shape_tx, position_tx))
+        .expect("failed to spawn cursor thread");
+    CursorFeed { shape, position }
+}
+
+fn run(tx: watch::Sender<CursorShape>, position: watch::Sender<(i32, i32)>) {
+    let mut shapes = ShapeCache::default();
+    let mut current: Option<isize> = None;
+    let mut failed = false;
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let mut info = CursorInfo { cb_size: std::mem::size_of::<CursorInfo>() as u32, ..Default::default() };
+        if unsafe { GetCursorInfo(&mut info) } == 0 {
+            continue;
+        }
+        let point = (info.pt_screen_pos.x, info.pt_screen_pos.y);
+        position.send_if_modified(|current| {
+            let changed = *current != point;
+            *current = point;
+            changed
+        });
+        // No handle while hidden; 0 can't be a real cursor.
+        let handle = if info.flags & CURSOR_SHOWING != 0 { info.h_cursor as isize } else { 0 };
+        if current == Some(handle) {
+            continue;
+        }
+
+        let shape = if handle == 0 {
+            CursorShape::default()
+        } else {
+            let shape = Icon::of(info.h_cursor)
+                .and_then(|icon| shapes.get(handle, icon.signature()?, || icon.read_shape()));
+            match shape {
+                Ok(shape) => {
+                    failed = false;
+                    shape
+                }
+                // 'current' keeps the previous handle, so this one is read again
+                // on the next poll.
+                Err(e) => {
+                    if !failed {
+                        warn!("Could not read the cursor image: {e}");
+                        failed = true;
+                    }
+                    continue;
+                }
+            }
+        };
+        current = Some(handle);
+        tx.send_if_modified(|old| {
+            if *old == shape {
+                return false;
+            }
+            *old = shape;
+            true
+        });
+    }
+}
+
+/// What can be learned about a cursor without reading its pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Signature {
+    width:     u32,
+    height:    u32,
+    hotspot_x: u32,
+    hotspot_y: u32,
+}
+
+/// 'ShapeCache' holds the converted shapes of recently seen cursors by handle, least
+/// recently used first. A handle names a shape only while that cursor is loaded: once
+/// it is destroyed, Windows may hand the same value to another cursor. An entry is
+/// therefore only reused while the cursor's size and hotspot still match it.
+#[derive(Default)]
+struct ShapeCache {
+    entries: VecDeque<(isize, Signature, CursorShape)>,
+}
+
+impl ShapeCache {
+    /// The shape of 'handle', converted with 'read' unless cached under the same signature.
+    fn get(
+        &mut self,
+        handle:    isize,
+        signature: Signature,
+        read:      impl FnOnce() -> Result<CursorShape>,
+    ) -> Result<CursorShape> {
+        if let Some(i) = self.entries.iter().position(|(h, _, _)| *h == handle) {
+            let (_, cached, shape) = self.entries.remove(i).expect("index from position");
+            if cached == signature {
+                self.entries.push_back((handle, signature, shape.clone()));
+                return Ok(shape);
+            }
+            debug!("Cursor handle {handle:#x} was reused for another shape");
+        }
+        let shape = read()?;
+        debug!("New cursor shape {}x{} (hotspot {}, {})", shape.width, shape.height, shape.hotspot_x, shape.hotspot_y);
+        if self.entries.len() == MAX_CACHED_SHAPES {
+            self.entries.pop_front();
+        }
+        self.entries.push_back((handle, signature, shape.clone()));
+        Ok(shape)
+    }
+}
+
+/// 'Icon' is what 'GetIconInfo' says about a cursor, including the copies of its
+/// bitmaps that we have to delete.
+struct Icon(IconInfo);
+
+impl Icon {
+    fn of(cursor: *mut c_void) -> Result<Self> {
+        let mut icon = IconInfo::default();
+        if unsafe { GetIconInfo(cursor, &mut icon) } == 0 {
+            bail!("GetIconInfo failed");
+        }
+        Ok(Self(icon))
+    }
+
+    /// Size and hotspot, from the bitmap headers alone.
+    fn signature(&self) -> Result<Signature> {
+        let icon = &self.0;
+        let (width, height) = if icon.hbm_color.is_null() {
+            // Monochrome: the mask holds the AND and XOR masks on top of each other.
+            let (width, height) = bitmap_size(icon.hbm_mask)?;
+            (width, height / 2)
+        } else {
+            bitmap_size(icon.hbm_color)?
+        };
+        Ok(Signature { width, height, hotspot_x: icon.x_hotspot, hotspot_y: icon.y_hotspot })
+    }
+
+    /// Converts the cursor into RGBA pixels and encodes them as PNG.
+    fn read_shape(&self) -> Result<CursorShape> {
+        let icon = &self.0;
+        let mask = read_bitmap(icon.hbm_mask)?;
+        let (width, height, rgba) = if icon.hbm_color.is_null() {
+            // Monochrome cursor: the mask holds the AND mask on top of the XOR mask.
+            let (width, height) = (mask.width, mask.height / 2);
+            (width, height, monochrome_to_rgba(&mask, width, height))
+        } else {
+            let color = read_bitmap(icon.hbm_color)?;
+            (color.width, color.height, color_to_rgba(&color, &mask))
+        };
+        if width == 0 || height == 0 || width > MAX_CURSOR_SIZE || height > MAX_CURSOR_SIZE {
+            bail!("unexpected cursor size {width}x{height}");
+        }
+        Ok(CursorShape {
+            visible:   true,
+            width,
+            height,
+            hotspot_x: icon.x_hotspot.min(width - 1),
+            hotspot_y: icon.y_hotspot.min(height - 1),
+            image:     png_image::encode(width as usize, height as usize, &rgba)?,
+        })
+    }
+}
+
+impl Drop for Icon {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.0.hbm_mask.is_null() {
+                DeleteObject(self.0.hbm_mask);
+            }
+            if !self.0.hbm_color.is_null() {
+                DeleteObject(self.0.hbm_color);
+            }
+        }
+    }
+}
+
+/// A bitmap read back as top-down 32-bit BGRA.
+struct Pixels {
+    width:  u32,
+    height: u32,
+    bgra:   Vec<u8>,
+}
+
+impl Pixels {
+    fn at(&self, x: u32, y: u32) -> &[u8] {
+        let i = ((y * self.width + x) * 4) as usize;
+        &self.bgra[i..i + 4]
+    }
+}
+
+fn bitmap_size(bitmap: *mut c_void) -> Result<(u32, u32)> {
+    let mut info = Bitmap::default();
+    let size = std::mem::size_of::<Bitmap>() as i32;
+    if unsafe { GetObjectW(bitmap, size, &mut info as *mut Bitmap as *mut c_void) } == 0 {
+        bail!("GetObjectW failed");
+    }
+    Ok((info.bm_width.max(0) as u32, info.bm_height.max(0) as u32))
+}
+
+fn read_bitmap(bitmap: *mut c_void) -> Result<Pixels> {
+    let (width, height) = bitmap_size(bitmap)?;
+    if width == 0 || height == 0 || width > MAX_CURSOR_SIZE || height > MAX_CURSOR_SIZE * 2 {
+        bail!("unexpected bitmap size {width}x{height}");
+    }
+
+    let mut header = BitmapInfoHeader {
+        bi_size:     std::mem::size_of::<BitmapInfoHeader>() as u32,
+        bi_width:    width as i32,
+        // Negative height asks for top-down rows.
+        bi_height:   -(height as i32),
+        bi_planes:   1,
+        bi_bit_count: 32,
+        ..Default::default()
+    };
+    let mut bgra = vec![0u8; (width * height * 4) as usize];
+    let lines = unsafe {
+        let dc = GetDC(std::ptr::null_mut());
+        let lines = GetDIBits(
+            dc,
+            bitmap,
+            0,
+            height,
+            bgra.as_mut_ptr() as *mut c_void,
+            &mut header as *mut BitmapInfoHeader as *mut c_void,
+            DIB_RGB_COLORS,
+        );
+        ReleaseDC(std::ptr::null_mut(), dc);
+        lines
+    };
+    if lines != height as i32 {
+        bail!("GetDIBits returned {lines} of {height} lines");
+    }
+    Ok(Pixels { width, height, bgra })
+}
+
+/// Color cursors carry their own alpha; old ones without it use the AND mask instead.
+fn color_to_rgba(color: &Pixels, mask: &Pixels) -> Vec<u8> {
+    let has_alpha = color.bgra.chunks_exact(4).any(|p| p[3] != 0);
+    let mut rgba = Vec::with_capacity(color.bgra.len());
+    for y in 0..color.height {
+        for x in 0..color.width {
+            let p = color.at(x, y);
+            let alpha = if has_alpha {
+                p[3]
+            } else if y < mask.height && x < mask.width && mask.at(x, y)[0] != 0 {
+                0
+            } else {
+                255
+            };
+            rgba.extend_from_slice(&[p[2], p[1], p[0], alpha]);
+        }
+    }
+    rgba
+}
+
+/// Monochrome cursors combine an AND and an XOR mask:
+/// AND 1 / XOR 0 is transparent, AND 0 gives black or white, and AND 1 / XOR 1
+/// inverts the screen, which an image can't express; we draw those pixels black
+/// so the I-beam stays visible on light backgrounds.
+fn monochrome_to_rgba(mask: &Pixels, width: u32, height: u32) -> Vec<u8> {
+    let mut rgba = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let and = mask.at(x, y)[0] != 0;
+            let xor = mask.at(x, y + height)[0] != 0;
+            let pixel = match (and, xor) {
+                (true, false)  => [0, 0, 0, 0],
+                (false, false) => [0, 0, 0, 255],
+                (false, true)  => [255, 255, 255, 255],
+                (true, true)   => [0, 0, 0, 255],
+            };
+            rgba.extend_from_slice(&pixel);
+        }
+    }
+    rgba
+}
+
+const CURSOR_SHOWING: u32 = 0x0000_0001;
+const DIB_RGB_COLORS: u32 = 0;
+
+#[repr(C)]
+#[derive(Default)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[repr(C)]
+struct CursorInfo {
+    cb_size:        u32,
+    flags:          u32,
+    h_cursor:       *mut c_void,
+    pt_screen_pos:  Point,
+}
+
+impl Default for CursorInfo {
+    fn default() -> Self {
+        Self { cb_size: 0, flags: 0, h_cursor: std::ptr::null_mut(), pt_screen_pos: Point::default() }
+    }
+}
+
+#[repr(C)]
+struct IconInfo {
+    f_icon:    i32,
+    x_hotspot: u32,
+    y_hotspot: u32,
+    hbm_mask:  *mut c_void,
+    hbm_color: *mut c_void,
+}
+
+impl Default for IconInfo {
+    fn default() -> Self {
+        Self {
+            f_icon:    0,
+            x_hotspot: 0,
+            y_hotspot: 0,
+            hbm_mask:  std::ptr::null_mut(),
+            hbm_color: std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+struct Bitmap {
+    bm_type:       i32,
+    bm_width:      i32,
+    bm_height:     i32,
+    bm_width_bytes: i32,
+    bm_planes:     u16,
+    bm_bits_pixel: u16,
+    bm_bits:       *mut c_void,
+}
+
+impl Default for Bitmap {
+    fn default() -> Self {
+        Self {
+            bm_type:        0,
+            bm_width:       0,
+            bm_height:      0,
+            bm_width_bytes: 0,
+            bm_planes:      0,
+            bm_bits_pixel:  0,
+            bm_bits:        std::ptr::null_mut(),
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BitmapInfoHeader {
+    bi_size:            u32,
+    bi_width:           i32,
+    bi_height:          i32,
+    bi_planes:          u16,
+    bi_bit_count:       u16,
+    bi_compression:     u32,
+    bi_size_image:      u32,
+    bi_x_pels_per_meter: i32,
+    bi_y_pels_per_meter: i32,
+    bi_clr_used:        u32,
+    bi_clr_important:   u32,
+}
+
+extern "system" {
+    fn GetCursorInfo(pci: *mut CursorInfo) -> i32;
+    fn GetIconInfo(hicon: *mut c_void, piconinfo: *mut IconInfo) -> i32;
+    fn GetDC(hwnd: *mut c_void) -> *mut c_void;
+    fn ReleaseDC(hwnd: *mut c_void, hdc: *mut c_void) -> i32;
+}
+
+#[link(name = "gdi32")]
+extern "system" {
+    fn GetObjectW(h: *mut c_void, c: i32, pv: *mut c_void) -> i32;
+    fn GetDIBits(
+        hdc: *mut c_void,
+        hbm: *mut c_void,
+        start: u32,
+        lines: u32,
+        bits: *mut c_void,
+        bmi: *mut c_void,
+        usage: u32,
+    ) -> i32;
+    fn DeleteObject(ho: *mut c_void) -> i32;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signature(width: u32, hotspot_x: u32) -> Signature {
+        Signature { width, height: 32, hotspot_x, hotspot_y: 0 }
+    }
+
+    fn shape(width: u32) -> CursorShape {
+        CursorShape { visible: true, width, height: 32, ..Default::default() }
+    }
+
+    #[test]
+    fn a_known_handle_is_not_converted_again() {
+        let mut cache = ShapeCache::default();
+        assert_eq!(cache.get(1, signature(32, 0), || Ok(shape(32))).unwrap(), shape(32));
+        let cached = cache.get(1, signature(32, 0), || panic!("converted twice"));
+        assert_eq!(cached.unwrap(), shape(32));
+    }
+
+    #[test]
+    fn a_reused_handle_is_converted_again() {
+        let mut cache = ShapeCache::default();
+        cache.get(1, signature(32, 0), || Ok(shape(32))).unwrap();
+        // Same handle, now a different size, then a different hotspot.
+        assert_eq!(cache.get(1, signature(48, 0), || Ok(shape(48))).unwrap(), shape(48));
+        let mut moved = shape(48);
+        moved.hotspot_x = 4;
+        assert_eq!(cache.get(1, signature(48, 4), || Ok(moved.clone())).unwrap(), moved);
+        assert_eq!(cache.entries.len(), 1);
+    }
+
+    #[test]
+    fn least_recently_used_shapes_are_dropped() {
+        let mut cache = ShapeCache::default();
+        for handle in 0..MAX_CACHED_SHAPES as isize {
+            cache.get(handle, signature(32, 0), || Ok(shape(32))).unwrap();
+        }
+        // Using handle 0 again makes handle 1 the oldest.
+        cache.get(0, signature(32, 0), || panic!("handle 0 was dropped")).unwrap();
+        cache.get(1000, signature(32, 0), || Ok(shape(32))).unwrap();
+        assert_eq!(cache.entries.len(), MAX_CACHED_SHAPES);
+        cache.get(0, signature(32, 0), || panic!("handle 0 was dropped")).unwrap();
+        let mut converted = false;
+        cache.get(1, signature(32, 0), || { converted = true; Ok(shape(32)) }).unwrap();
+        assert!(converted);
+    }
+
+    #[test]
+    fn failed_conversions_are_not_cached() {
+        let mut cache = ShapeCache::default();
+        assert!(cache.get(1, signature(32, 0), || bail!("GetDIBits failed")).is_err());
+        assert!(cache.entries.is_empty());
+    }
+}
@@ -0,0 +1,188 @@
+use std::ffi::c_void;
+use schemars::JsonSchema;
+use serde::Serialize;
+use tracing::warn;
+use windows_capture::monitor::Monitor;
+
+/// 'DisplayGeometry' describes the area of the host desktop that is being streamed.
+/// The capture loop is the only writer; it publishes a new value through a
+/// 'tokio::sync::watch' channel whenever the captured monitor changes size, position
+/// or scaling, and every reader (input injection, data channel notifications)
+/// sees the whole struct at once.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct DisplayGeometry {
+    // Top-left corner of the captured monitor on the virtual desktop, in physical pixels.
+    // Monitors left of or above the primary one have negative coordinates.
+    pub x:      i32,
+    pub y:      i32,
+    // Size of the captured frames in physical pixels.
+    pub width:  u32,
+    pub height: u32,
+    // Effective DPI of the captured monitor (96 = 100% scaling).
+    pub dpi:    u32,
+}
+
+impl Default for DisplayGeometry {
+    fn default() -> Self {
+        Self { x: 0, y: 0, width: 0, height: 0, dpi: USER_DEFAULT_SCREEN_DPI }
+    }
+}
+
+impl DisplayGeometry {
+    /// Maps normalized (0.0 - 1.0) client coordinates to a virtual-desktop pixel
+    /// on the captured monitor.
+    /// Out-of-range values are clamped to the monitor edge so a click at x=1.0 can't
+    /// spill onto the neighbouring screen. Returns 'None' for non-finite input or
+    /// before the capture loop has reported a size.
+    pub fn to_desktop(self, x: f64, y: f64) -> Option<(i32, i32)> {
+        if self.width == 0 || self.height == 0 || !x.is_finite() || !y.is_finite() {
+            return None;
+        }
+        let px = (x * self.width as f64).floor().clamp(0.0, (self.width - 1) as f64);
+        let py = (y * self.height as f64).floor().clamp(0.0, (self.height - 1) as f64);
+        Some((self.x + px as i32, self.y + py as i32))
+    }
+
+    /// The monitor's scaling factor (1.0 = 100%, 1.5 = 150%, ...).
+    pub fn scale_factor(&self) -> f64 {
+        self.dpi as f64 / USER_DEFAULT_SCREEN_DPI as f64
+    }
+}
+
+/// Reads the position and DPI of 'monitor' and combines them with the frame size.
+/// The frame size wins over the monitor rectangle because it is what the client sees.
+pub fn query_geometry(monitor: &Monitor, width: u32, height: u32) -> DisplayGeometry {
+    let hmonitor = monitor.as_raw_hmonitor();
+
+    let mut info = MonitorInfo {
+        cb_size:    std::mem::size_of::<MonitorInfo>() as u32,
+        rc_monitor: Rect::default(),
+        rc_work:    Rect::default(),
+        dw_flags:   0,
+    };
+    let (x, y) = if unsafe { GetMonitorInfoW(hmonitor, &mut info) } != 0 {
+        (info.rc_monitor.left, info.rc_monitor.top)
+    } else {
+        warn!("GetMonitorInfoW failed; assuming the captured monitor is at (0, 0)");
+        (0, 0)
+    };
+
+    let (mut dpi_x, mut dpi_y) = (0u32, 0u32);
+    let dpi = if unsafe { GetDpiForMonitor(hmonitor, MDT_EFFECTIVE_DPI, &mut dpi_x, &mut dpi_y) } == 0 {
+        dpi_x
+    } else {
+        USER_DEFAULT_SCREEN_DPI
+    };
+
+    DisplayGeometry { x, y, width, height, dpi }
+}
+
+// Win32 definitions (WinUser.h / ShellScalingApi.h).
+const USER_DEFAULT_SCREEN_DPI: u32 = 96;
+const MDT_EFFECTIVE_DPI: u32 = 0;
+const DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2: isize = -4;
+
+#[repr(C)]
+#[derive(Default)]
+struct Rect {
+    left:   i32,
+    top:    i32,
+    right:  i32,
+    bottom: i32,
+}
+
+#[repr(C)]
+struct MonitorInfo {
+    cb_size:    u32,
+    rc_monitor: Rect,
+    rc_work:    Rect,
+    dw_flags:   u32,
+}
+
+extern "system" {
+    fn SetProcessDpiAwarenessContext(value: isize) -> i32;
+    fn GetMonitorInfoW(monitor: *mut c_void, info: *mut MonitorInfo) -> i32;
+}
+
+#[link(name = "shcore")]
+extern "system" {
+    fn GetDpiForMonitor(monitor: *mut c_void, dpi_type: u32, dpi_x: *mut u32, dpi_y: *mut u32) -> i32;
+}
+
+/// Opts the process into per-monitor DPI awareness.
+/// Without this, Windows virtualizes cursor coordinates for "DPI unaware" programs,
+/// so after a scaling change 'SetCursorPos' no longer lines up with the captured pixels.
+pub fn enable_dpi_awareness() {
+    if unsafe { SetProcessDpiAwarenessContext(DPI_AWARENESS_CONTEXT_PER_MONITOR_AWARE_V2) } == 0 {
+        // Fails if awareness was already set (e.g. by a manifest); that's fine.
+        warn!("SetProcessDpiAwarenessContext failed; cursor mapping may drift on scaled displays");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn monitor(x: i32, y: i32, width: u32, height: u32, dpi: u32) -> DisplayGeometry {
+        DisplayGeometry { x, y, width, height, dpi }
+    }
+
+    #[test]
+    fn primary_monitor_edges() {
+        let primary = monitor(0, 0, 1920, 1080, 96);
+        assert_eq!(primary.to_desktop(0.0, 0.0), Some((0, 0)));
+        assert_eq!(primary.to_desktop(0.5, 0.5), Some((960, 540)));
+        // 1.0 is the far edge, which still has to land on this monitor.
+        assert_eq!(primary.to_desktop(1.0, 1.0), Some((1919, 1079)));
+    }
+
+    #[test]
+    fn secondary_left_of_primary() {
+        let left = monitor(-2560, 0, 2560, 1440, 96);
+        assert_eq!(left.to_desktop(0.0, 0.0), Some((-2560, 0)));
+        assert_eq!(left.to_desktop(0.5, 0.5), Some((-1280, 720)));
+        assert_eq!(left.to_desktop(1.0, 1.0), Some((-1, 1439)));
+    }
+
+    #[test]
+    fn secondary_above_primary() {
+        let above = monitor(0, -1080, 1920, 1080, 96);
+        assert_eq!(above.to_desktop(0.0, 0.0), Some((0, -1080)));
+        assert_eq!(above.to_desktop(1.0, 1.0), Some((1919, -1)));
+    }
+
+    #[test]
+    fn secondary_above_and_left_of_primary() {
+        let corner = monitor(-1280, -1024, 1280, 1024, 96);
+        assert_eq!(corner.to_desktop(0.0, 0.0), Some((-1280, -1024)));
+        assert_eq!(corner.to_desktop(0.25, 0.75), Some((-960, -256)));
+        assert_eq!(corner.to_desktop(1.0, 1.0), Some((-1, -1)));
+    }
+
+    #[test]
+    fn mixed_dpi_maps_in_physical_pixels() {
+        // A 150% monitor right of a 100% primary: frames and coordinates are both
+        // physical pixels, so the scaling doesn't change where a point lands.
+        let scaled = monitor(1920, 0, 3840, 2160, 144);
+        let unscaled = monitor(1920, 0, 3840, 2160, 96);
+        assert_eq!(scaled.scale_factor(), 1.5);
+        assert_eq!(scaled.to_desktop(0.5, 0.5), Some((3840, 1080)));
+        assert_eq!(scaled.to_desktop(0.5, 0.5), unscaled.to_desktop(0.5, 0.5));
+        assert_eq!(scaled.to_desktop(1.0, 1.0), Some((5759, 2159)));
+    }
+
+    #[test]
+    fn out_of_range_is_clamped_to_the_monitor() {
+        let left = monitor(-2560, 0, 2560, 1440, 96);
+        assert_eq!(left.to_desktop(-0.5, 2.0), Some((-2560, 1439)));
+        assert_eq!(left.to_desktop(1.5, -1.0), Some((-1, 0)));
+    }
+
+    #[test]
+    fn no_position_before_a_size_or_for_non_finite_input() {
+        assert_eq!(DisplayGeometry::default().to_desktop(0.5, 0.5), None);
+        let primary = monitor(0, 0, 1920, 1080, 96);
+        assert_eq!(primary.to_desktop(f64::NAN, 0.5), None);
+        assert_eq!(primary.to_desktop(0.5, f64::INFINITY), None);
+    }
+}
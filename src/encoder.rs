@@ -1,49 +1,124 @@
-use anyhow::Result;
+use std::{
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::{Duration, Instant},
+};
+use anyhow::{anyhow, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
 use openh264::{
     encoder::{Encoder, EncoderConfig},
     formats::YUVBuffer,
     OpenH264API,
 };
 
+// Target bitrate, tuned for LAN streaming.
+const BITRATE_BPS: u32 = 8_000_000;
+// Frames between forced keyframes.
+const KEYFRAME_INTERVAL: u64 = 60;
+
 /// 'H264Encoder' handles converting raw images into compressed video.
 pub struct H264Encoder {
     inner:  Encoder,
     width:  usize,
     height: usize,
+    fps:    u32,
+    bitrate_bps: u32,
     frame_index: u64,
+    // Set by 'request_keyframe'; the next frame is encoded as a keyframe.
+    keyframe_requested: bool,
+}
+
+/// 'EncoderSettings' describes the video stream as it is currently encoded.
+/// The capture loop publishes it whenever the encoder is (re)built, and clients
+/// receive it on the control channel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct EncoderSettings {
+    pub codec:             VideoCodec,
+    pub width:             u32,
+    pub height:            u32,
+    pub fps:               u32,
+    pub bitrate_bps:       u32,
+    // Frames between keyframes; a late joiner waits at most this long for a picture.
+    pub keyframe_interval: u32,
 }
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum VideoCodec {
+    #[default]
+    H264,
+}
+
+/// 'EncodedFrame' is one access unit plus what it cost to produce.
+pub struct EncodedFrame {
+    // Annex B NAL units; empty when the encoder skipped the frame.
+    pub data:         Vec<u8>,
+    pub keyframe:     bool,
+    pub convert_time: Duration,
+    pub encode_time:  Duration,
+}
 
 impl H264Encoder {
     /// Creates a new encoder.
-    pub fn new(width: usize, height: usize, _fps: u32) -> Result<Self> {
+    pub fn new(width: usize, height: usize, fps: u32, bitrate_bps: u32) -> Result<Self> {
         // We use Cisco's OpenH264 library. 'from_source' will compile/link it for us.
         let api = OpenH264API::from_source();
         let config = EncoderConfig::new()
-            .set_bitrate_bps(8_000_000)
-            .max_frame_rate(_fps as f32);
+            .set_bitrate_bps(bitrate_bps)
+            .max_frame_rate(fps as f32);
         Ok(Self {
             inner: Encoder::with_api_config(api, config)?,
             width,
             height,
+            fps,
+            bitrate_bps,
             frame_index: 0,
+            keyframe_requested: false,
         })
     }
 
+    /// Encodes the next frame as a keyframe.
+    pub fn request_keyframe(&mut self) {
+        self.keyframe_requested = true;
+    }
+
+    /// Returns the frame size (width, height) this encoder was created for. The
+    /// encoded picture is rounded down to an even size; see 'settings'.
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn settings(&self) -> EncoderSettings {
+        EncoderSettings {
+            codec:             VideoCodec::H264,
+            width:             even(self.width) as u32,
+            height:            even(self.height) as u32,
+            fps:               self.fps,
+            bitrate_bps:       self.bitrate_bps,
+            keyframe_interval: KEYFRAME_INTERVAL as u32,
+        }
+    }
+
     /// Takes a raw BGRA buffer and returns a compressed H.264 bitstream.
-    pub fn encode_bgra(&mut self, bgra: &[u8]) -> Result<Vec<u8>> {
+    pub fn encode_bgra(&mut self, bgra: &[u8]) -> Result<EncodedFrame> {
         self.frame_index += 1;
         // Ensure late-joining peers quickly receive a decodable frame.
         // At 30 FPS, interval 60 ~= every 2 seconds.
-        if self.frame_index == 1 || self.frame_index % 60 == 0 {
+        let keyframe = self.frame_index == 1
+            || self.frame_index % KEYFRAME_INTERVAL == 0
+            || std::mem::take(&mut self.keyframe_requested);
+        if keyframe {
             self.inner.force_intra_frame();
         }
 
         // H.264 encoders usually don't accept BGRA (Red, Green, Blue, Alpha).
         // They require YUV420 format (Luminance and Chrominance).
-        let yuv = bgra_to_yuv420(bgra, self.width, self.height);
+        let start = Instant::now();
+        let yuv = bgra_to_yuv420(bgra, self.width, self.height)?;
+        let convert_time = start.elapsed();
         
         // The actual compression happens here.
+        let start = Instant::now();
         let bitstream = self.inner.encode(&yuv)?;
 
         // Extract the NAL units (Network Abstraction Layer) from the encoded bitstream.
@@ -58,7 +133,49 @@ impl H264Encoder {
             }
             i += 1;
         }
-        Ok(out)
+        Ok(EncodedFrame {
+            data: out,
+            keyframe,
+            convert_time,
+            encode_time: start.elapsed(),
+        })
+    }
+}
+
+/// 'EncoderControl' lets the rest of the host steer the running encoder. The capture
+/// loop checks it before every frame.
+pub struct EncoderControl {
+    keyframe:    AtomicBool,
+    bitrate_bps: AtomicU32,
+}
+
+impl Default for EncoderControl {
+    fn default() -> Self {
+        Self {
+            keyframe:    AtomicBool::new(false),
+            bitrate_bps: AtomicU32::new(BITRATE_BPS),
+        }
+    }
+}
+
+impl EncoderControl {
+    /// Asks for a keyframe, e.g. so a client can recover from a broken picture.
+    pub fn request_keyframe(&self) {
+        self.keyframe.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether a keyframe was requested since the last call.
+    pub fn take_keyframe_request(&self) -> bool {
+        self.keyframe.swap(false, Ordering::Relaxed)
+    }
+
+    /// Sets the target bitrate. The encoder is rebuilt with it before the next frame.
+    pub fn set_bitrate(&self, bitrate_bps: u32) {
+        self.bitrate_bps.store(bitrate_bps, Ordering::Relaxed);
+    }
+
+    pub fn bitrate(&self) -> u32 {
+        self.bitrate_bps.load(Ordering::Relaxed)
     }
 }
 
@@ -66,7 +183,13 @@ impl H264Encoder {
 /// Y = Brightness (Luma)
 /// U/V = Color (Chroma)
 /// 420 means we keep full resolution for Brightness, but half resolution for Color.
-fn bgra_to_yuv420(bgra: &[u8], w: usize, h: usize) -> YUVBuffer {
+/// One chroma sample covers 2x2 pixels, so for an odd width or height (scaled or
+/// rotated displays report those) the last column or row is left out.
+fn bgra_to_yuv420(bgra: &[u8], w: usize, h: usize) -> Result<YUVBuffer> {
+    let (stride, w, h) = (w * 4, even(w), even(h));
+    if w == 0 || h == 0 {
+        return Err(anyhow!("frame of {w}x{h} pixels is too small to encode"));
+    }
     let pixels = w * h;
     let mut data = vec![0u8; pixels + pixels / 2]; 
 
@@ -84,14 +207,44 @@ fn bgra_to_yuv420(bgra: &[u8], w: usize, h: usize) -> YUVBuffer {
         height: h as u32,
     };
 
+    // The source rows keep their full length, so the stride skips an odd last column.
     yuv::bgra_to_yuv420(
         &mut planar,
         bgra,
-        (w * 4) as u32,
+        stride as u32,
         yuv::YuvRange::Limited,
         yuv::YuvStandardMatrix::Bt601,
         yuv::YuvConversionMode::Balanced,
-    ).expect("YUV conversion failed");
+    ).map_err(|e| anyhow!("YUV conversion failed: {e:?}"))?;
 
-    YUVBuffer::from_vec(data, w, h)
+    Ok(YUVBuffer::from_vec(data, w, h))
+}
+
+/// Rounds a frame dimension down to the even size the encoded stream has.
+fn even(size: usize) -> usize {
+    size & !1
+}
+
+#[cfg(test)]
+mod tests {
+    use openh264::formats::YUVSource;
+
+    use super::*;
+
+    #[test]
+    fn odd_sizes_drop_the_last_row_and_column() {
+        let (w, h) = (1366, 767);
+        let yuv = bgra_to_yuv420(&vec![0x80; w * h * 4], w, h).unwrap();
+        assert_eq!(yuv.dimensions(), (1366, 766));
+
+        let (w, h) = (1081, 1921);
+        let yuv = bgra_to_yuv420(&vec![0x80; w * h * 4], w, h).unwrap();
+        assert_eq!(yuv.dimensions(), (1080, 1920));
+    }
+
+    #[test]
+    fn short_buffers_and_tiny_frames_are_errors() {
+        assert!(bgra_to_yuv420(&[0; 64], 16, 16).is_err());
+        assert!(bgra_to_yuv420(&[0; 4], 1, 1).is_err());
+    }
 }
@@ -0,0 +1,126 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+// The input events clients send on the "input" data channel, decoded from JSON or
+// from the binary encoding in wire.rs. Nothing here depends on the rest of the
+// host, so fuzz/ can build this file and wire.rs on their own.
+
+/// 'InputEvent' represents the different types of mouse and keyboard actions
+/// that can be sent from the browser.
+#[derive(Debug, Clone, PartialEq, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InputEvent {
+    MouseMove { x: f64, y: f64 },
+    // Raw pointer deltas in pixels, sent while the client holds pointer lock.
+    MouseMoveRelative { dx: f64, dy: f64 },
+    // Switches the session between absolute and relative (pointer lock) mode.
+    SetPointerMode { mode: PointerMode },
+    MouseDown { x: f64, y: f64, button: u8 },
+    MouseUp { x: f64, y: f64, button: u8 },
+    // Raw 'WheelEvent.deltaX/deltaY'; 'mode' is the event's 'deltaMode'.
+    MouseScroll {
+        dx: f64,
+        dy: f64,
+        #[serde(default)]
+        mode: DeltaMode,
+    },
+    // A touch or pen contact, identified by the browser's 'pointerId'.
+    Pointer {
+        id:    u32,
+        kind:  PointerKind,
+        phase: PointerPhase,
+        #[serde(flatten)]
+        contact: PointerContact,
+    },
+    // Full state of the browser gamepad at 'index' ('Gamepad.buttons[].value' and
+    // 'Gamepad.axes', standard mapping). Triggers are the analog buttons 6 and 7.
+    Gamepad { index: u8, buttons: Vec<f64>, axes: Vec<f64> },
+    GamepadDisconnected { index: u8 },
+    KeyDown { code: String },
+    KeyUp { code: String },
+    // Already-composed text (IME output, "type clipboard"), injected as Unicode.
+    TextInput { text: String },
+    // IME composition lifecycle. Only the final 'CompositionEnd' text is typed on
+    // the host; start/update let the host log what the client is composing.
+    CompositionStart,
+    CompositionUpdate { text: String },
+    CompositionEnd { text: String },
+    // Sent by the client when it loses focus, so nothing stays pressed on the host.
+    ReleaseAll,
+}
+
+/// 'PointerMode' says how a session drives the host cursor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PointerMode {
+    /// Normalized positions over the video; the cursor follows the client's pointer.
+    #[default]
+    Absolute,
+    /// Pointer lock: only deltas are sent and click positions are ignored, so games
+    /// and 3D apps that warp or confine the cursor keep working.
+    Relative,
+}
+
+/// 'DeltaMode' is the unit of a browser wheel event ('WheelEvent.deltaMode').
+// 'Serialize' lets the schema show the default.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DeltaMode {
+    #[default]
+    Pixel,
+    Line,
+    Page,
+}
+
+/// 'PointerContact' is the state of one touch or pen contact as sent by the browser
+/// (mirrors the fields of a DOM 'PointerEvent').
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, JsonSchema)]
+pub struct PointerContact {
+    // Normalized (0.0 - 1.0) position over the video, like mouse events.
+    pub x: f64,
+    pub y: f64,
+    // 0.0 - 1.0; browsers report 0.5 while pressed when the hardware has no sensor.
+    #[serde(default = "default_pressure")]
+    pub pressure: f64,
+    // Pen tilt in degrees (-90 - 90) and rotation in degrees (0 - 359).
+    #[serde(default)]
+    pub tilt_x: f64,
+    #[serde(default)]
+    pub tilt_y: f64,
+    #[serde(default)]
+    pub twist: f64,
+    // Contact size, normalized like the position. Zero if unknown.
+    #[serde(default)]
+    pub width: f64,
+    #[serde(default)]
+    pub height: f64,
+    // Pen buttons: the eraser end and the barrel (side) button.
+    #[serde(default)]
+    pub eraser: bool,
+    #[serde(default)]
+    pub barrel: bool,
+}
+
+fn default_pressure() -> f64 {
+    0.5
+}
+
+/// 'PointerKind' is the DOM 'pointerType' of a non-mouse pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PointerKind {
+    Touch,
+    Pen,
+}
+
+/// 'PointerPhase' follows the DOM pointer event that produced the contact update.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum PointerPhase {
+    Down,
+    Move,
+    Up,
+    Cancel,
+    // The pen left the hover range of the digitizer.
+    Leave,
+}
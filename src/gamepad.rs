@@ -0,0 +1,342 @@
+#[cfg(windows)]
+use std::sync::Arc;
+use anyhow::Result;
+use tracing::{info, warn};
+
+// Virtual game controllers for gamepads connected to the browser.
+// The browser reports the W3C "standard" gamepad layout; we translate it to an
+// Xbox-style report, which is what virtually every game understands. Windows hosts
+// plug it into ViGEmBus, Linux hosts create a uinput device.
+
+/// XInput allows at most four controllers, so that is how many slots we hand out.
+pub const MAX_GAMEPADS: usize = 4;
+// Values the browser may send per gamepad; the standard layout uses 17 buttons and 4 axes.
+pub const MAX_GAMEPAD_BUTTONS: usize = 32;
+pub const MAX_GAMEPAD_AXES: usize = 16;
+
+/// Called by a backend when a game changes a controller's rumble motors,
+/// with the strong (low-frequency) and weak (high-frequency) speeds from 0.0 to 1.0.
+pub type RumbleCallback = Box<dyn Fn(f64, f64) + Send + 'static>;
+
+/// A source of virtual controllers. Implement this to support another driver.
+pub trait GamepadBackend {
+    /// Plugs in a new virtual controller.
+    fn connect(&mut self, rumble: RumbleCallback) -> Result<Box<dyn VirtualGamepad>>;
+}
+
+/// One plugged-in virtual controller. Dropping it unplugs the controller.
+pub trait VirtualGamepad {
+    fn update(&mut self, report: &GamepadReport) -> Result<()>;
+}
+
+/// 'GamepadReport' is the full state of an Xbox-style controller.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GamepadReport {
+    // XInput 'XINPUT_GAMEPAD_*' button flags.
+    pub buttons:       u16,
+    pub left_trigger:  u8,
+    pub right_trigger: u8,
+    // Stick positions; positive Y is up, like XInput.
+    pub thumb_lx: i16,
+    pub thumb_ly: i16,
+    pub thumb_rx: i16,
+    pub thumb_ry: i16,
+}
+
+// Standard-layout button index -> XInput flag. Indices 6 and 7 are the triggers.
+const STANDARD_BUTTONS: [(usize, u16); 15] = [
+    (0,  0x1000), // A
+    (1,  0x2000), // B
+    (2,  0x4000), // X
+    (3,  0x8000), // Y
+    (4,  0x0100), // Left bumper
+    (5,  0x0200), // Right bumper
+    (8,  0x0020), // Back / View
+    (9,  0x0010), // Start / Menu
+    (10, 0x0040), // Left stick press
+    (11, 0x0080), // Right stick press
+    (12, 0x0001), // D-pad up
+    (13, 0x0002), // D-pad down
+    (14, 0x0004), // D-pad left
+    (15, 0x0008), // D-pad right
+    (16, 0x0400), // Guide
+];
+
+impl GamepadReport {
+    /// Translates browser 'Gamepad.buttons[].value' and 'Gamepad.axes' (standard mapping).
+    /// Missing or non-finite values count as released / centered.
+    pub fn from_standard(buttons: &[f64], axes: &[f64]) -> Self {
+        let button = |i: usize| buttons.get(i).copied().filter(|v| v.is_finite()).unwrap_or(0.0);
+        let axis = |i: usize| axes.get(i).copied().filter(|v| v.is_finite()).unwrap_or(0.0).clamp(-1.0, 1.0);
+        let stick = |v: f64| (v * i16::MAX as f64).round() as i16;
+        let trigger = |v: f64| (v.clamp(0.0, 1.0) * u8::MAX as f64).round() as u8;
+
+        let mut report = GamepadReport::default();
+        for (index, flag) in STANDARD_BUTTONS {
+            if button(index) >= 0.5 {
+                report.buttons |= flag;
+            }
+        }
+        report.left_trigger = trigger(button(6));
+        report.right_trigger = trigger(button(7));
+        // Browser axes point down for "stick pushed up"; XInput points up.
+        report.thumb_lx = stick(axis(0));
+        report.thumb_ly = stick(-axis(1));
+        report.thumb_rx = stick(axis(2));
+        report.thumb_ry = stick(-axis(3));
+        report
+    }
+}
+
+/// 'Gamepads' hands out the host's controller slots to sessions.
+pub struct Gamepads {
+    backend: Option<Box<dyn GamepadBackend>>,
+    slots:   [Option<Box<dyn VirtualGamepad>>; MAX_GAMEPADS],
+}
+
+impl Gamepads {
+    /// Picks the backend from 'LOCALBRIDGE_GAMEPAD': "off" disables forwarding,
+    /// anything else uses the platform's driver (ViGEm on Windows, uinput on Linux).
+    pub fn from_env() -> Self {
+        let backend: Option<Box<dyn GamepadBackend>> =
+            match std::env::var("LOCALBRIDGE_GAMEPAD").as_deref() {
+                Ok("off") | Ok("none") | Ok("0") => {
+                    info!("Gamepad forwarding disabled");
+                    None
+                }
+                _ => Some(default_backend()),
+            };
+        Self { backend, slots: Default::default() }
+    }
+
+    /// Plugs in a controller and returns its slot, or 'None' if every slot is in use
+    /// or the backend is unavailable.
+    pub fn connect(&mut self, rumble: RumbleCallback) -> Option<usize> {
+        let backend = self.backend.as_mut()?;
+        let Some(slot) = self.slots.iter().position(Option::is_none) else {
+            warn!("Gamepad dropped: all {MAX_GAMEPADS} controller slots are in use");
+            return None;
+        };
+        match backend.connect(rumble) {
+            Ok(pad) => {
+                info!("Virtual controller plugged into slot {slot}");
+                self.slots[slot] = Some(pad);
+                Some(slot)
+            }
+            Err(e) => {
+                // Most likely the driver isn't installed; don't retry for every report.
+                warn!("Could not create a virtual controller, gamepad forwarding disabled: {e}");
+                self.backend = None;
+                None
+            }
+        }
+    }
+
+    pub fn update(&mut self, slot: usize, report: &GamepadReport) {
+        if let Some(pad) = self.slots.get_mut(slot).and_then(Option::as_mut) {
+            if let Err(e) = pad.update(report) {
+                warn!("Gamepad slot {slot} update failed: {e}");
+            }
+        }
+    }
+
+    /// Unplugs the controller in 'slot' and frees the slot.
+    pub fn disconnect(&mut self, slot: usize) {
+        if self.slots.get_mut(slot).and_then(Option::take).is_some() {
+            info!("Virtual controller in slot {slot} unplugged");
+        }
+    }
+}
+
+#[cfg(windows)]
+fn default_backend() -> Box<dyn GamepadBackend> {
+    Box::new(ViGEmBackend::default())
+}
+
+#[cfg(target_os = "linux")]
+fn default_backend() -> Box<dyn GamepadBackend> {
+    Box::new(uinput_backend::UinputBackend)
+}
+
+/// Backend for the ViGEmBus driver (https://github.com/nefarius/ViGEmBus),
+/// which emulates wired Xbox 360 controllers.
+#[cfg(windows)]
+#[derive(Default)]
+struct ViGEmBackend {
+    // Connected lazily, so hosts without the driver only hear about it when a
+    // client actually sends gamepad input.
+    client: Option<Arc<vigem_client::Client>>,
+}
+
+#[cfg(windows)]
+impl GamepadBackend for ViGEmBackend {
+    fn connect(&mut self, rumble: RumbleCallback) -> Result<Box<dyn VirtualGamepad>> {
+        let client = match &self.client {
+            Some(client) => Arc::clone(client),
+            None => {
+                let client = Arc::new(vigem_client::Client::connect()?);
+                self.client = Some(Arc::clone(&client));
+                client
+            }
+        };
+
+        let mut target = vigem_client::Xbox360Wired::new(client, vigem_client::TargetId::XBOX360_WIRED);
+        target.plugin()?;
+        target.wait_ready()?;
+
+        // Games set the motors through XInput; ViGEm reports them on its own thread.
+        target.request_notification()?.spawn_thread(move |_, data| {
+            rumble(data.large_motor as f64 / 255.0, data.small_motor as f64 / 255.0);
+        });
+
+        Ok(Box::new(ViGEmGamepad { target }))
+    }
+}
+
+#[cfg(windows)]
+struct ViGEmGamepad {
+    target: vigem_client::Xbox360Wired<Arc<vigem_client::Client>>,
+}
+
+#[cfg(windows)]
+impl VirtualGamepad for ViGEmGamepad {
+    fn update(&mut self, report: &GamepadReport) -> Result<()> {
+        self.target.update(&vigem_client::XGamepad {
+            buttons:       vigem_client::XButtons { raw: report.buttons },
+            left_trigger:  report.left_trigger,
+            right_trigger: report.right_trigger,
+            thumb_lx:      report.thumb_lx,
+            thumb_ly:      report.thumb_ly,
+            thumb_rx:      report.thumb_rx,
+            thumb_ry:      report.thumb_ry,
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(windows)]
+impl Drop for ViGEmGamepad {
+    fn drop(&mut self) {
+        let _ = self.target.unplug();
+    }
+}
+
+/// Linux backend: uinput controllers that present themselves like a wired Xbox 360
+/// pad under the kernel's 'xpad' driver (same IDs, buttons and axes), so SDL, Steam
+/// and games map them without configuration. Rumble comes back as force feedback.
+#[cfg(target_os = "linux")]
+mod uinput_backend {
+    use std::{sync::Arc, thread, time::Duration};
+    use anyhow::Result;
+    use crate::uinput::{Device, DeviceBuilder, BUS_USB, EV_ABS, EV_KEY, FF_RUMBLE};
+    use super::{GamepadBackend, GamepadReport, RumbleCallback, VirtualGamepad};
+
+    // How often each controller checks for rumble requests from games.
+    const RUMBLE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+    // Microsoft's USB IDs for the wired Xbox 360 controller.
+    const XBOX360_VENDOR:  u16 = 0x045e;
+    const XBOX360_PRODUCT: u16 = 0x028e;
+
+    // XInput button flag -> evdev key, in the order 'xpad' reports them.
+    const BUTTONS: [(u16, u16); 11] = [
+        (0x1000, BTN_A),
+        (0x2000, BTN_B),
+        (0x4000, BTN_X),
+        (0x8000, BTN_Y),
+        (0x0100, BTN_TL),
+        (0x0200, BTN_TR),
+        (0x0020, BTN_SELECT),
+        (0x0010, BTN_START),
+        (0x0400, BTN_MODE),
+        (0x0040, BTN_THUMBL),
+        (0x0080, BTN_THUMBR),
+    ];
+    const DPAD_UP:    u16 = 0x0001;
+    const DPAD_DOWN:  u16 = 0x0002;
+    const DPAD_LEFT:  u16 = 0x0004;
+    const DPAD_RIGHT: u16 = 0x0008;
+
+    pub struct UinputBackend;
+
+    impl GamepadBackend for UinputBackend {
+        fn connect(&mut self, rumble: RumbleCallback) -> Result<Box<dyn VirtualGamepad>> {
+            let keys: Vec<u16> = BUTTONS.iter().map(|&(_, key)| key).collect();
+            let device = DeviceBuilder::new("LocalBridge Xbox 360 Controller")?
+                .keys(&keys)?
+                .absolute(ABS_X, i16::MIN.into(), i16::MAX.into(), 0)?
+                .absolute(ABS_Y, i16::MIN.into(), i16::MAX.into(), 0)?
+                .absolute(ABS_RX, i16::MIN.into(), i16::MAX.into(), 0)?
+                .absolute(ABS_RY, i16::MIN.into(), i16::MAX.into(), 0)?
+                .absolute(ABS_Z, 0, u8::MAX.into(), 0)?
+                .absolute(ABS_RZ, 0, u8::MAX.into(), 0)?
+                .absolute(ABS_HAT0X, -1, 1, 0)?
+                .absolute(ABS_HAT0Y, -1, 1, 0)?
+                .force_feedback(&[FF_RUMBLE], 16)?
+                .create(BUS_USB, XBOX360_VENDOR, XBOX360_PRODUCT)?;
+            let device = Arc::new(device);
+
+            // Games upload and start rumble effects on the device; this thread answers
+            // them until the controller is unplugged.
+            let polled = Arc::downgrade(&device);
+            thread::Builder::new().name("gamepad rumble".into()).spawn(move || {
+                while let Some(device) = polled.upgrade() {
+                    if let Some((strong, weak)) = device.poll_rumble() {
+                        rumble(strong, weak);
+                    }
+                    drop(device);
+                    thread::sleep(RUMBLE_POLL_INTERVAL);
+                }
+            })?;
+
+            Ok(Box::new(UinputGamepad { device }))
+        }
+    }
+
+    struct UinputGamepad {
+        device: Arc<Device>,
+    }
+
+    impl VirtualGamepad for UinputGamepad {
+        // The kernel drops values that didn't change, so every report is sent whole.
+        fn update(&mut self, report: &GamepadReport) -> Result<()> {
+            let pressed = |flag: u16| report.buttons & flag != 0;
+            let mut events: Vec<(u16, u16, i32)> = BUTTONS.iter()
+                .map(|&(flag, key)| (EV_KEY, key, pressed(flag) as i32))
+                .collect();
+            events.extend([
+                (EV_ABS, ABS_HAT0X, pressed(DPAD_RIGHT) as i32 - pressed(DPAD_LEFT) as i32),
+                (EV_ABS, ABS_HAT0Y, pressed(DPAD_DOWN) as i32 - pressed(DPAD_UP) as i32),
+                (EV_ABS, ABS_X, report.thumb_lx.into()),
+                // evdev's Y axes point down, XInput's up ('xpad' flips them the same way).
+                (EV_ABS, ABS_Y, (!report.thumb_ly).into()),
+                (EV_ABS, ABS_RX, report.thumb_rx.into()),
+                (EV_ABS, ABS_RY, (!report.thumb_ry).into()),
+                (EV_ABS, ABS_Z, report.left_trigger.into()),
+                (EV_ABS, ABS_RZ, report.right_trigger.into()),
+            ]);
+            self.device.emit(&events)
+        }
+    }
+
+    // linux/input-event-codes.h
+    const BTN_A:      u16 = 0x130;
+    const BTN_B:      u16 = 0x131;
+    const BTN_X:      u16 = 0x133;
+    const BTN_Y:      u16 = 0x134;
+    const BTN_TL:     u16 = 0x136;
+    const BTN_TR:     u16 = 0x137;
+    const BTN_SELECT: u16 = 0x13a;
+    const BTN_START:  u16 = 0x13b;
+    const BTN_MODE:   u16 = 0x13c;
+    const BTN_THUMBL: u16 = 0x13d;
+    const BTN_THUMBR: u16 = 0x13e;
+
+    const ABS_X:     u16 = 0x00;
+    const ABS_Y:     u16 = 0x01;
+    const ABS_Z:     u16 = 0x02;
+    const ABS_RX:    u16 = 0x03;
+    const ABS_RY:    u16 = 0x04;
+    const ABS_RZ:    u16 = 0x05;
+    const ABS_HAT0X: u16 = 0x10;
+    const ABS_HAT0Y: u16 = 0x11;
+}
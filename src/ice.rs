@@ -0,0 +1,110 @@
+use serde::Serialize;
+use tracing::{info, warn};
+use webrtc::ice_transport::ice_server::RTCIceServer;
+
+// STUN and TURN servers for sessions beyond the LAN. Without any, peers only offer
+// host candidates, which is all a LAN needs. Both the host's peer connections and
+// the served client use the same list; the client fetches it from '/ice-servers'.
+//
+//   LOCALBRIDGE_STUN_SERVERS   comma-separated, e.g. "stun:stun.example.com:3478"
+//   LOCALBRIDGE_TURN_SERVERS   comma-separated, e.g. "turn:turn.example.com:3478?transport=udp,
+//                              turn:turn.example.com:3478?transport=tcp,turns:turn.example.com:5349"
+//   LOCALBRIDGE_TURN_USERNAME  and LOCALBRIDGE_TURN_CREDENTIAL, shared by every TURN server
+//
+// The TURN credentials are handed to every client that loads the page, so use an
+// account made for LocalBridge.
+//
+// The host's WebRTC stack only speaks TURN over UDP, so it relays through 'turn:'
+// URLs with UDP transport and leaves TCP and TLS ones ('?transport=tcp', 'turns:')
+// to browsers. A browser behind a firewall that blocks UDP can still relay that way;
+// its relay address on the TURN server is reached by the host over UDP.
+
+/// One entry of the list, in the browser's 'RTCIceServer' shape.
+#[derive(Debug, Clone, Serialize)]
+pub struct IceServer {
+    pub urls:       Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username:   Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+}
+
+/// 'IceConfig' is the STUN/TURN server list, read once at startup.
+#[derive(Debug, Default)]
+pub struct IceConfig {
+    pub servers: Vec<IceServer>,
+}
+
+impl IceConfig {
+    pub fn from_env() -> Self {
+        let mut servers = Vec::new();
+
+        let stun = urls_from_env("LOCALBRIDGE_STUN_SERVERS", &["stun:", "stuns:"]);
+        if !stun.is_empty() {
+            servers.push(IceServer { urls: stun, username: None, credential: None });
+        }
+
+        let turn = urls_from_env("LOCALBRIDGE_TURN_SERVERS", &["turn:", "turns:"]);
+        if !turn.is_empty() {
+            let username = std::env::var("LOCALBRIDGE_TURN_USERNAME").ok().filter(|u| !u.is_empty());
+            let credential = std::env::var("LOCALBRIDGE_TURN_CREDENTIAL").ok().filter(|c| !c.is_empty());
+            if username.is_some() && credential.is_some() {
+                servers.push(IceServer { urls: turn, username, credential });
+            } else {
+                // Browsers refuse the whole configuration over a TURN server without them.
+                warn!("LOCALBRIDGE_TURN_SERVERS needs LOCALBRIDGE_TURN_USERNAME and LOCALBRIDGE_TURN_CREDENTIAL; TURN disabled");
+            }
+        }
+
+        if servers.is_empty() {
+            info!("No STUN/TURN servers; sessions use host candidates only");
+        }
+        for url in servers.iter().flat_map(|server| &server.urls).filter(|url| !host_can_use(url)) {
+            warn!("The host can't relay through {url} (TURN over TCP and TLS isn't supported); only browsers use it");
+        }
+        for server in &servers {
+            info!("ICE servers: {}", server.urls.join(", "));
+        }
+        Self { servers }
+    }
+
+    /// The list in the form the host's peer connections take, without the TURN URLs
+    /// they can't use.
+    pub fn rtc_ice_servers(&self) -> Vec<RTCIceServer> {
+        self.servers.iter()
+            .map(|server| RTCIceServer {
+                urls:       server.urls.iter().filter(|url| host_can_use(url)).cloned().collect(),
+                username:   server.username.clone().unwrap_or_default(),
+                credential: server.credential.clone().unwrap_or_default(),
+            })
+            .filter(|server| !server.urls.is_empty())
+            .collect()
+    }
+}
+
+/// Whether the host's ICE agent can use a STUN/TURN URL: everything but TURN over
+/// TCP or TLS.
+fn host_can_use(url: &str) -> bool {
+    let tcp = url.split_once('?').is_some_and(|(_, query)| {
+        query.split('&').any(|param| param.eq_ignore_ascii_case("transport=tcp"))
+    });
+    !(url.starts_with("turns:") || (url.starts_with("turn:") && tcp))
+}
+
+/// Splits a comma-separated list of URLs, dropping (with a warning) any that
+/// don't start with one of 'schemes'.
+fn urls_from_env(name: &str, schemes: &[&str]) -> Vec<String> {
+    let Ok(value) = std::env::var(name) else { return Vec::new() };
+    value.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .filter(|url| {
+            let valid = schemes.iter().any(|scheme| url.starts_with(scheme));
+            if !valid {
+                warn!("{name}: ignoring '{url}', expected a {} URL", schemes.join(" or "));
+            }
+            valid
+        })
+        .map(str::to_owned)
+        .collect()
+}
@@ -1,37 +1,603 @@
-use std::sync::Arc;
-use serde::Deserialize;
-use tracing::{debug, warn};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+use tokio::sync::{mpsc as async_mpsc, watch};
+use tracing::{debug, info, warn};
 use webrtc::data_channel::RTCDataChannel;
-use enigo::{Enigo, KeyboardControllable, MouseControllable, MouseButton, Key};
+use enigo::{Enigo, KeyboardControllable, MouseControllable, MouseButton};
 
-/// 'InputEvent' represents the different types of mouse and keyboard actions
-/// that can be sent from the browser.
-#[derive(Debug, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum InputEvent {
-    MouseMove { x: f64, y: f64 },
-    MouseDown { x: f64, y: f64, button: u8 },
-    MouseUp { x: f64, y: f64, button: u8 },
-    MouseScroll { dx: f64, dy: f64 },
-    KeyDown { code: String },
-    KeyUp { code: String },
+use crate::{
+    display::DisplayGeometry,
+    gamepad::{GamepadReport, Gamepads, MAX_GAMEPAD_AXES, MAX_GAMEPAD_BUTTONS},
+    keymap::{map_key, KeyMode, KeyTarget},
+    metrics::Metrics,
+    pointer::{PointerContact, PointerInjector, PointerKind, PointerPhase, PointerSlot},
+    protocol::{ErrorCode, HostMessage, NoticeLevel},
+    sendinput,
+    wire,
+};
+
+pub use crate::events::{DeltaMode, InputEvent, PointerMode};
+
+impl DeltaMode {
+    /// How many 'WHEEL_DELTA' units one unit of this mode is worth. Browsers scroll
+    /// 100 px or 3 lines per notch; a page counts as ten notches.
+    fn wheel_units(self) -> f64 {
+        match self {
+            DeltaMode::Pixel => WHEEL_DELTA / 100.0,
+            DeltaMode::Line  => WHEEL_DELTA / 3.0,
+            DeltaMode::Page  => WHEEL_DELTA * 10.0,
+        }
+    }
+}
+
+// One wheel notch: Windows' 'WHEEL_DELTA', and the unit of Linux's 'REL_WHEEL_HI_RES'.
+const WHEEL_DELTA: f64 = 120.0;
+// Largest scroll injected for a single event (50 notches), so a bogus delta
+// can't send the host to the end of a document.
+const MAX_WHEEL_UNITS: f64 = WHEEL_DELTA * 50.0;
+
+// Upper bound for a single text injection so a huge paste can't flood the host.
+const MAX_TEXT_INPUT_CHARS: usize = 10_000;
+
+// How long keys or buttons may stay held with no sign of the client before the
+// watchdog releases them. Input events and every control channel message count,
+// and the client pings on the control channel every 2 s, so this only fires for a
+// client that stopped talking without closing its channels.
+const HELD_INPUT_TIMEOUT: Duration = Duration::from_secs(15);
+// How often the input thread wakes up to run the watchdog when idle.
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(1);
+// Maximum number of queued commands handled (and coalesced) in one batch.
+const MAX_BATCH: usize = 256;
+// Messages for one client that may wait for its control channel; more are dropped.
+const HOST_MESSAGE_QUEUE: usize = 64;
+
+/// 'HeldInput' remembers which keys and mouse buttons a session currently holds down,
+/// so they can be released if the client disappears mid-press.
+#[derive(Default)]
+struct HeldInput {
+    // Browser key code -> what we pressed for it, so the release matches the press.
+    keys:     HashMap<String, KeyTarget>,
+    // Browser button number -> what we pressed for it.
+    buttons:  HashMap<u8, ButtonTarget>,
+    // Browser pointer id -> the touch slot or pen it is down on.
+    pointers: HashMap<u32, PointerSlot>,
+}
+
+impl HeldInput {
+    fn is_empty(&self) -> bool {
+        self.keys.is_empty() && self.buttons.is_empty() && self.pointers.is_empty()
+    }
+}
+
+/// What a browser mouse button is injected as.
+#[derive(Debug, Clone, Copy)]
+enum ButtonTarget {
+    Mouse(MouseButton),
+    // 'XBUTTON1' or 'XBUTTON2'.
+    X(u16),
+    // Buttons past "forward" have no Windows equivalent; they can be mapped to keys.
+    Key(KeyTarget),
+}
+
+/// 'InputConfig' holds the injection settings read from the environment at startup.
+struct InputConfig {
+    key_mode:      KeyMode,
+    // Browser button number (5 and up) -> key, from 'LOCALBRIDGE_EXTRA_BUTTONS'.
+    extra_buttons: HashMap<u8, KeyTarget>,
+}
+
+impl InputConfig {
+    /// 'LOCALBRIDGE_EXTRA_BUTTONS' is a comma-separated list of 'button=KeyCode' pairs,
+    /// e.g. "5=AudioVolumeUp,6=AudioVolumeDown", using the same key codes as the client.
+    fn from_env() -> Self {
+        let key_mode = KeyMode::from_env();
+        let mut extra_buttons = HashMap::new();
+        let spec = std::env::var("LOCALBRIDGE_EXTRA_BUTTONS").unwrap_or_default();
+        for entry in spec.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mapped = entry.split_once('=').and_then(|(button, code)| {
+                let button = button.trim().parse::<u8>().ok().filter(|b| *b >= 5)?;
+                Some((button, map_key(code.trim(), key_mode)?))
+            });
+            match mapped {
+                Some((button, target)) => {
+                    info!("Mouse button {button} -> {target:?}");
+                    extra_buttons.insert(button, target);
+                }
+                None => warn!("Ignoring LOCALBRIDGE_EXTRA_BUTTONS entry '{entry}' (expected 'button=KeyCode' with button >= 5)"),
+            }
+        }
+        Self { key_mode, extra_buttons }
+    }
+
+    fn map_button(&self, button: u8) -> Option<ButtonTarget> {
+        match button {
+            0 => Some(ButtonTarget::Mouse(MouseButton::Left)),
+            1 => Some(ButtonTarget::Mouse(MouseButton::Middle)),
+            2 => Some(ButtonTarget::Mouse(MouseButton::Right)),
+            3 => Some(ButtonTarget::X(XBUTTON1)),
+            4 => Some(ButtonTarget::X(XBUTTON2)),
+            _ => self.extra_buttons.get(&button).copied().map(ButtonTarget::Key),
+        }
+    }
+}
+
+// Browser buttons 3 and 4 ("back" and "forward") are the Windows side buttons.
+const XBUTTON1: u16 = 1;
+const XBUTTON2: u16 = 2;
+
+/// 'InputCommand' is what the async side sends to the input thread.
+enum InputCommand {
+    StartSession { session: Arc<str>, events: HostMessageQueue },
+    Event { session: Arc<str>, event: InputEvent, received: Instant },
+    ReleaseAll { session: Arc<str>, reason: String },
+    // The client is still there; keeps its held input from being released.
+    Keepalive { session: Arc<str> },
+    EndSession { session: Arc<str> },
+}
+
+/// 'InputHandle' is the cheap, cloneable entry point to the input thread.
+#[derive(Clone)]
+pub struct InputHandle {
+    tx:      mpsc::Sender<InputCommand>,
+    display: watch::Receiver<DisplayGeometry>,
+}
+
+/// Starts the input thread. All injection happens there, so async callbacks only
+/// push onto a channel and never block a Tokio worker on the injector.
+/// 'capture_cursor' is set to 'false' while a session is in relative mode (and
+/// 'LOCALBRIDGE_HIDE_CURSOR_IN_RELATIVE' isn't "0"), telling the capture loop to
+/// leave the cursor out of the video. Without 'cursor_in_video' (clients draw the
+/// cursor themselves) it is always 'false'.
+pub fn spawn(
+    display:         watch::Receiver<DisplayGeometry>,
+    capture_cursor:  watch::Sender<bool>,
+    cursor_in_video: bool,
+    metrics:         Arc<Metrics>,
+) -> InputHandle {
+    let (tx, rx) = mpsc::channel();
+    let thread_display = display.clone();
+    std::thread::Builder::new()
+        .name("input".to_owned())
+        .spawn(move || InputThread::new(thread_display, capture_cursor, cursor_in_video, metrics).run(rx))
+        .expect("failed to spawn input thread");
+    InputHandle { tx, display }
+}
+
+impl InputHandle {
+    /// Registers a new peer with the input thread.
+    pub fn session(&self, id: String) -> Arc<InputSession> {
+        let id: Arc<str> = id.into();
+        // Messages raised for the client (rumble, rejected input) go back through this.
+        let (events, events_rx) = HostMessageQueue::new();
+        let _ = self.tx.send(InputCommand::StartSession { session: Arc::clone(&id), events: events.clone() });
+        Arc::new(InputSession {
+            id,
+            tx:            self.tx.clone(),
+            display:       self.display.clone(),
+            events,
+            host_messages: Mutex::new(Some(events_rx)),
+            binary_input:  AtomicBool::new(false),
+            control:       AtomicBool::new(true),
+        })
+    }
+}
+
+/// 'InputSession' is the input side of one connected peer.
+/// Dropping it tells the input thread to release and forget the peer's held input.
+pub struct InputSession {
+    id:            Arc<str>,
+    tx:            mpsc::Sender<InputCommand>,
+    display:       watch::Receiver<DisplayGeometry>,
+    events:        HostMessageQueue,
+    // Taken by the control channel once the client says hello, which forwards them.
+    host_messages: Mutex<Option<async_mpsc::Receiver<HostMessage>>>,
+    // Set once the hello agrees on 'binary_input'.
+    binary_input:  AtomicBool,
+    // Cleared for view-only sessions, whose input is dropped.
+    control:       AtomicBool,
+}
+
+impl InputSession {
+    /// Queues one event from the client for injection.
+    fn handle(&self, event: InputEvent) {
+        if !self.has_control() {
+            return;
+        }
+        let _ = self.tx.send(InputCommand::Event {
+            session:  Arc::clone(&self.id),
+            event,
+            received: Instant::now(),
+        });
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Tells the client that one of its messages was rejected.
+    pub fn report_error(&self, code: ErrorCode, message: impl Into<String>) {
+        self.events.push(HostMessage::error(code, message));
+    }
+
+    /// Sends a message to the client on its control channel.
+    pub fn notify(&self, message: HostMessage) {
+        self.events.push(message);
+    }
+
+    pub fn has_control(&self) -> bool {
+        self.control.load(Ordering::Relaxed)
+    }
+
+    /// Gives the session control of the host, or makes it view-only. Whatever it
+    /// holds is released when control is taken away.
+    pub fn set_control(&self, control: bool) {
+        let had_control = self.control.swap(control, Ordering::Relaxed);
+        if had_control && !control {
+            self.release_all("control revoked");
+        }
+    }
+
+    /// Accepts binary input messages from now on.
+    pub fn enable_binary_input(&self) {
+        self.binary_input.store(true, Ordering::Relaxed);
+    }
+
+    /// The display as it is right now.
+    pub fn display(&self) -> DisplayGeometry {
+        *self.display.borrow()
+    }
+
+    pub fn display_changes(&self) -> watch::Receiver<DisplayGeometry> {
+        self.display.clone()
+    }
+
+    /// The messages raised for this client. Only the first caller gets them, and
+    /// messages are only queued from then on.
+    pub fn take_host_messages(&self) -> Option<async_mpsc::Receiver<HostMessage>> {
+        let receiver = self.host_messages.lock().unwrap().take();
+        if receiver.is_some() {
+            self.events.listening.store(true, Ordering::Relaxed);
+        }
+        receiver
+    }
+
+    /// Tells the watchdog the client is still there, so a button held through a
+    /// drag or a modifier held while the user reads isn't released.
+    pub fn keepalive(&self) {
+        let _ = self.tx.send(InputCommand::Keepalive { session: Arc::clone(&self.id) });
+    }
+
+    /// Releases every key and button this session still holds.
+    pub fn release_all(&self, reason: &str) {
+        let _ = self.tx.send(InputCommand::ReleaseAll {
+            session: Arc::clone(&self.id),
+            reason:  reason.to_owned(),
+        });
+    }
+}
+
+impl Drop for InputSession {
+    fn drop(&mut self) {
+        let _ = self.tx.send(InputCommand::EndSession { session: Arc::clone(&self.id) });
+    }
+}
+
+/// 'HostMessageQueue' holds the messages raised for one client until its control
+/// channel sends them. Until the client says hello nobody reads them, so they are
+/// dropped; after that at most 'HOST_MESSAGE_QUEUE' wait, and the rest are dropped
+/// too. A client that sends malformed input, or a game that keeps rumbling, can't
+/// grow the host's memory this way.
+#[derive(Clone)]
+struct HostMessageQueue {
+    tx:        async_mpsc::Sender<HostMessage>,
+    // Set by 'take_host_messages' once the control channel forwards them.
+    listening: Arc<AtomicBool>,
+}
+
+impl HostMessageQueue {
+    fn new() -> (Self, async_mpsc::Receiver<HostMessage>) {
+        let (tx, rx) = async_mpsc::channel(HOST_MESSAGE_QUEUE);
+        (Self { tx, listening: Arc::new(AtomicBool::new(false)) }, rx)
+    }
+
+    fn push(&self, message: HostMessage) {
+        if self.listening.load(Ordering::Relaxed) {
+            let _ = self.tx.try_send(message);
+        }
+    }
+}
+
+struct SessionInput {
+    held:          HeldInput,
+    last_activity: Instant,
+    pointer_mode:  PointerMode,
+    // Sub-pixel remainder of relative moves, carried into the next move.
+    relative_rest: (f64, f64),
+    // Fractional wheel units not injected yet (horizontal, vertical).
+    scroll_rest:   (f64, f64),
+    // Browser gamepad index -> host controller slot.
+    gamepads:      HashMap<u8, usize>,
+    // Gamepads that got no slot, and the user was told so.
+    refused_gamepads: HashSet<u8>,
+    events:        HostMessageQueue,
+}
+
+impl SessionInput {
+    fn new(events: HostMessageQueue) -> Self {
+        Self {
+            held:          HeldInput::default(),
+            last_activity: Instant::now(),
+            pointer_mode:  PointerMode::Absolute,
+            relative_rest: (0.0, 0.0),
+            scroll_rest:   (0.0, 0.0),
+            gamepads:      HashMap::new(),
+            refused_gamepads: HashSet::new(),
+            events,
+        }
+    }
+}
+
+/// Per-second injection statistics, logged like the capture FPS line.
+#[derive(Default)]
+struct InjectStats {
+    injected:      u32,
+    coalesced:     u32,
+    total_latency: Duration,
+    max_latency:   Duration,
+}
+
+/// 'InputThread' owns the injectors and the held-input state of every session.
+struct InputThread {
+    enigo:         Enigo,
+    pointers:      PointerInjector,
+    gamepads:      Gamepads,
+    config:        InputConfig,
+    display:       watch::Receiver<DisplayGeometry>,
+    sessions:      HashMap<Arc<str>, SessionInput>,
+    stats:         InjectStats,
+    last_stats:    Instant,
+    last_watchdog: Instant,
+    metrics:       Arc<Metrics>,
+    capture_cursor:          watch::Sender<bool>,
+    cursor_in_video:         bool,
+    hide_cursor_in_relative: bool,
+}
+
+impl InputThread {
+    fn new(
+        display:         watch::Receiver<DisplayGeometry>,
+        capture_cursor:  watch::Sender<bool>,
+        cursor_in_video: bool,
+        metrics:         Arc<Metrics>,
+    ) -> Self {
+        Self {
+            enigo:         Enigo::new(),
+            pointers:      PointerInjector::new(),
+            gamepads:      Gamepads::from_env(),
+            config:        InputConfig::from_env(),
+            display,
+            sessions:      HashMap::new(),
+            stats:         InjectStats::default(),
+            last_stats:    Instant::now(),
+            last_watchdog: Instant::now(),
+            metrics,
+            capture_cursor,
+            cursor_in_video,
+            hide_cursor_in_relative: std::env::var("LOCALBRIDGE_HIDE_CURSOR_IN_RELATIVE")
+                .map(|v| v != "0")
+                .unwrap_or(true),
+        }
+    }
+
+    fn run(mut self, rx: mpsc::Receiver<InputCommand>) {
+        let mut batch = Vec::with_capacity(MAX_BATCH);
+        loop {
+            match rx.recv_timeout(WATCHDOG_INTERVAL) {
+                Ok(cmd) => batch.push(cmd),
+                Err(mpsc::RecvTimeoutError::Timeout) => {}
+                Err(mpsc::RecvTimeoutError::Disconnected) => break,
+            }
+            // Drain whatever else queued up while we were busy injecting.
+            while batch.len() < MAX_BATCH {
+                match rx.try_recv() {
+                    Ok(cmd) => batch.push(cmd),
+                    Err(_) => break,
+                }
+            }
+
+            self.stats.coalesced += coalesce_moves(&mut batch);
+            for cmd in batch.drain(..) {
+                self.execute(cmd);
+            }
+
+            if self.last_watchdog.elapsed() >= WATCHDOG_INTERVAL {
+                self.release_idle();
+                self.last_watchdog = Instant::now();
+            }
+            self.log_stats();
+        }
+        debug!("Input thread stopped");
+    }
+
+    fn execute(&mut self, cmd: InputCommand) {
+        match cmd {
+            InputCommand::StartSession { session, events } => {
+                self.sessions.insert(session, SessionInput::new(events));
+            }
+            InputCommand::Event { session, event, received } => {
+                // Copy the geometry once per event so a resize can't tear the mapping.
+                let geometry = *self.display.borrow();
+                let mode_change = matches!(event, InputEvent::SetPointerMode { .. });
+                let Some(state) = self.sessions.get_mut(&session) else { return };
+                state.last_activity = Instant::now();
+                inject(&mut self.enigo, &mut self.pointers, &mut self.gamepads, state, &geometry, &self.config, event);
+                if mode_change {
+                    self.update_cursor_capture();
+                }
+
+                let latency = received.elapsed();
+                self.stats.injected += 1;
+                self.metrics.input_events.inc();
+                self.stats.total_latency += latency;
+                self.stats.max_latency = self.stats.max_latency.max(latency);
+            }
+            InputCommand::ReleaseAll { session, reason } => {
+                if let Some(state) = self.sessions.get_mut(&session) {
+                    if !state.held.is_empty() {
+                        info!("Session {session}: releasing held input ({reason})");
+                        let geometry = *self.display.borrow();
+                        release_held(&mut self.enigo, &mut self.pointers, &geometry, &mut state.held);
+                    }
+                    neutral_gamepads(&mut self.gamepads, state);
+                }
+            }
+            InputCommand::Keepalive { session } => {
+                if let Some(state) = self.sessions.get_mut(&session) {
+                    state.last_activity = Instant::now();
+                }
+            }
+            InputCommand::EndSession { session } => {
+                if let Some(mut state) = self.sessions.remove(&session) {
+                    let geometry = *self.display.borrow();
+                    release_held(&mut self.enigo, &mut self.pointers, &geometry, &mut state.held);
+                    for (_, slot) in state.gamepads.drain() {
+                        self.gamepads.disconnect(slot);
+                    }
+                    self.update_cursor_capture();
+                }
+            }
+        }
+    }
+
+    /// Hides the cursor from the video while any session is in relative mode.
+    fn update_cursor_capture(&self) {
+        let any_relative = self.sessions.values().any(|s| s.pointer_mode == PointerMode::Relative);
+        let with_cursor = self.cursor_in_video && !(any_relative && self.hide_cursor_in_relative);
+        self.capture_cursor.send_if_modified(|current| {
+            let changed = *current != with_cursor;
+            *current = with_cursor;
+            changed
+        });
+    }
+
+    fn release_idle(&mut self) {
+        let geometry = *self.display.borrow();
+        for (session, state) in self.sessions.iter_mut() {
+            if !state.held.is_empty() && state.last_activity.elapsed() >= HELD_INPUT_TIMEOUT {
+                info!("Session {session}: releasing input held for {:?} without activity", HELD_INPUT_TIMEOUT);
+                release_held(&mut self.enigo, &mut self.pointers, &geometry, &mut state.held);
+            }
+        }
+    }
+
+    fn log_stats(&mut self) {
+        if self.last_stats.elapsed() < Duration::from_secs(1) {
+            return;
+        }
+        let stats = std::mem::take(&mut self.stats);
+        self.metrics.input_events_per_second.set(stats.injected as f64);
+        if stats.injected > 0 {
+            info!(
+                "Input Status -> {} events/s injected, {} moves coalesced (avg latency: {:.2?}, max: {:.2?})",
+                stats.injected,
+                stats.coalesced,
+                stats.total_latency / stats.injected,
+                stats.max_latency
+            );
+        }
+        self.last_stats = Instant::now();
+    }
+}
+
+/// Merges runs of mouse moves from the same session: for absolute moves only the
+/// latest position matters, relative moves are summed. Gamepad reports for the
+/// same controller are merged like absolute moves as long as their buttons don't
+/// change. Clicks, keys and everything else keep their order. Returns the number
+/// of moves dropped.
+fn coalesce_moves(batch: &mut Vec<InputCommand>) -> u32 {
+    let before = batch.len();
+    let mut out: Vec<InputCommand> = Vec::with_capacity(before);
+    for mut cmd in batch.drain(..) {
+        match (out.last(), &mut cmd) {
+            (
+                Some(InputCommand::Event { session: prev, event: InputEvent::MouseMove { .. }, .. }),
+                InputCommand::Event { session, event: InputEvent::MouseMove { .. }, .. },
+            ) if *prev == *session => {
+                out.pop();
+            }
+            (
+                Some(InputCommand::Event {
+                    session: prev,
+                    event: InputEvent::MouseMoveRelative { dx: prev_dx, dy: prev_dy },
+                    ..
+                }),
+                InputCommand::Event { session, event: InputEvent::MouseMoveRelative { dx, dy }, .. },
+            ) if *prev == *session => {
+                *dx += *prev_dx;
+                *dy += *prev_dy;
+                out.pop();
+            }
+            // Each gamepad report is a full state, so a report that only moved sticks or
+            // triggers replaces the one before it. Button changes are all kept, or a
+            // press and release within one batch would never reach the host.
+            (
+                Some(InputCommand::Event {
+                    session: prev,
+                    event: InputEvent::Gamepad { index: prev_index, buttons: prev_buttons, .. },
+                    ..
+                }),
+                InputCommand::Event { session, event: InputEvent::Gamepad { index, buttons, .. }, .. },
+            ) if *prev == *session && *prev_index == *index && same_buttons(prev_buttons, buttons) => {
+                out.pop();
+            }
+            _ => {}
+        }
+        out.push(cmd);
+    }
+    *batch = out;
+    (before - batch.len()) as u32
+}
+
+/// Whether two gamepad reports press the same digital buttons.
+fn same_buttons(a: &[f64], b: &[f64]) -> bool {
+    GamepadReport::from_standard(a, &[]).buttons == GamepadReport::from_standard(b, &[]).buttons
 }
 
 /// Sets up the handler for messages arriving on the WebRTC data channel.
-pub async fn handle_data_channel(dc: Arc<RTCDataChannel>) {
-    let enigo = Arc::new(std::sync::Mutex::new(Enigo::new()));
+/// Replies (display changes, rejected input) go out on the "control" channel.
+pub async fn handle_data_channel(dc: Arc<RTCDataChannel>, session: Arc<InputSession>) {
+    // Nothing can release a key after the channel is gone, so do it now.
+    let session_close = Arc::clone(&session);
+    dc.on_close(Box::new(move || {
+        session_close.release_all("data channel closed");
+        Box::pin(async {})
+    }));
 
     dc.on_message(Box::new(move |msg| {
-        let enigo = Arc::clone(&enigo);
+        let session = Arc::clone(&session);
         Box::pin(async move {
-            if let Ok(text) = std::str::from_utf8(&msg.data) {
-                match serde_json::from_str::<InputEvent>(text) {
-                    Ok(ev) => {
-                        if let Ok(mut enigo_guard) = enigo.lock() {
-                            inject(&mut *enigo_guard, ev);
-                        }
-                    }
-                    Err(e) => warn!("Bad input: {e}"),
+            // Text messages are JSON; binary ones use the compact encoding in wire.rs,
+            // which the client only sends after negotiating it.
+            let event = if msg.is_string {
+                std::str::from_utf8(&msg.data)
+                    .map_err(anyhow::Error::from)
+                    .and_then(|text| Ok(serde_json::from_str::<InputEvent>(text)?))
+            } else if session.binary_input.load(Ordering::Relaxed) {
+                wire::decode(&msg.data)
+            } else {
+                Err(anyhow::anyhow!("binary input was not negotiated"))
+            };
+            match event {
+                Ok(ev) => session.handle(ev),
+                Err(e) => {
+                    warn!("Bad input: {e}");
+                    session.report_error(ErrorCode::MalformedMessage, e.to_string());
                 }
             }
         })
@@ -39,175 +605,377 @@ pub async fn handle_data_channel(dc: Arc<RTCDataChannel>) {
 }
 
 extern "system" {
+    // Takes virtual-desktop coordinates, so monitors left of or above the primary
+    // one are reached with negative values.
     fn SetCursorPos(x: i32, y: i32) -> i32;
 }
 
-/// 'inject' simulates mouse and keyboard events on the host computer.
-fn inject(enigo: &mut Enigo, event: InputEvent) {
+/// 'inject' simulates mouse, keyboard, touch and pen events on the host computer.
+fn inject(
+    enigo:    &mut Enigo,
+    pointers: &mut PointerInjector,
+    gamepads: &mut Gamepads,
+    session:  &mut SessionInput,
+    geometry: &DisplayGeometry,
+    config:   &InputConfig,
+    event:    InputEvent,
+) {
+    let held = &mut session.held;
+    // In relative mode the host cursor is wherever the game put it; click
+    // positions from the client are meaningless and must not warp it.
+    let absolute = session.pointer_mode == PointerMode::Absolute;
     match event {
         InputEvent::MouseMove { x, y } => {
-            if let Some((w, h)) = get_monitor_dimensions() {
-                let abs_x = (x * w as f64).round() as i32;
-                let abs_y = (y * h as f64).round() as i32;
+            if let Some((abs_x, abs_y)) = geometry.to_desktop(x, y) {
                 debug!("MouseMove -> x={} y={}", abs_x, abs_y);
                 unsafe {
                     SetCursorPos(abs_x, abs_y);
                 }
             }
         }
+        InputEvent::MouseMoveRelative { dx, dy } => {
+            if !dx.is_finite() || !dy.is_finite() {
+                return;
+            }
+            let (rest_x, rest_y) = session.relative_rest;
+            let (total_x, total_y) = (dx + rest_x, dy + rest_y);
+            let (move_x, move_y) = (total_x.trunc(), total_y.trunc());
+            session.relative_rest = (total_x - move_x, total_y - move_y);
+            if move_x != 0.0 || move_y != 0.0 {
+                debug!("MouseMoveRelative -> dx={} dy={}", move_x, move_y);
+                sendinput::mouse_relative(move_x as i32, move_y as i32);
+            }
+        }
+        InputEvent::SetPointerMode { mode } => {
+            debug!("SetPointerMode -> {:?}", mode);
+            session.pointer_mode = mode;
+            session.relative_rest = (0.0, 0.0);
+        }
         InputEvent::MouseDown { x, y, button } => {
-            if let Some((w, h)) = get_monitor_dimensions() {
-                let abs_x = (x * w as f64).round() as i32;
-                let abs_y = (y * h as f64).round() as i32;
+            if !is_normalized(x, y) {
+                warn!("MouseDown with invalid position ({x}, {y}) ignored");
+                session.events.push(HostMessage::error(
+                    ErrorCode::InvalidInput,
+                    format!("mouse_down at ({x}, {y}) is outside the video"),
+                ));
+                return;
+            }
+            if let Some((abs_x, abs_y)) = geometry.to_desktop(x, y).filter(|_| absolute) {
                 unsafe {
                     SetCursorPos(abs_x, abs_y);
                 }
             }
-            if let Some(btn) = map_button(button) {
-                debug!("MouseDown -> button={:?}", btn);
-                enigo.mouse_down(btn);
+            match config.map_button(button) {
+                Some(target) => {
+                    debug!("MouseDown -> button={} target={:?}", button, target);
+                    press_button(enigo, target, true);
+                    held.buttons.insert(button, target);
+                }
+                None => debug!("MouseDown -> button={} not forwarded", button),
             }
         }
         InputEvent::MouseUp { x, y, button } => {
-            if let Some((w, h)) = get_monitor_dimensions() {
-                let abs_x = (x * w as f64).round() as i32;
-                let abs_y = (y * h as f64).round() as i32;
-                unsafe {
-                    SetCursorPos(abs_x, abs_y);
+            // An up without a matching down (e.g. the press started outside the
+            // video) would release a button some other program is holding.
+            let Some(target) = held.buttons.remove(&button) else {
+                debug!("MouseUp -> button={} not held, ignored", button);
+                return;
+            };
+            // A bad position must not keep the button held, so only skip the move.
+            if is_normalized(x, y) {
+                if let Some((abs_x, abs_y)) = geometry.to_desktop(x, y).filter(|_| absolute) {
+                    unsafe {
+                        SetCursorPos(abs_x, abs_y);
+                    }
                 }
+            } else {
+                warn!("MouseUp with invalid position ({x}, {y}); releasing in place");
             }
-            if let Some(btn) = map_button(button) {
-                debug!("MouseUp -> button={:?}", btn);
-                enigo.mouse_up(btn);
-            }
+            debug!("MouseUp -> button={} target={:?}", button, target);
+            press_button(enigo, target, false);
         }
-        InputEvent::MouseScroll { dx, dy } => {
-            if dx != 0.0 {
-                let ticks = dx.round() as i32;
-                debug!("MouseScroll X -> {}", ticks);
-                enigo.mouse_scroll_x(ticks);
+        InputEvent::MouseScroll { dx, dy, mode } => {
+            if !dx.is_finite() || !dy.is_finite() {
+                return;
             }
-            if dy != 0.0 {
-                let ticks = dy.round() as i32;
-                debug!("MouseScroll Y -> {}", ticks);
-                enigo.mouse_scroll_y(ticks);
+            let units = mode.wheel_units();
+            let (rest_x, rest_y) = session.scroll_rest;
+            // Browsers scroll down for positive 'deltaY'; a positive wheel scrolls up.
+            // Both platforms take the fraction of a notch, so trackpads scroll smoothly.
+            let total_x = (dx * units).clamp(-MAX_WHEEL_UNITS, MAX_WHEEL_UNITS) + rest_x;
+            let total_y = (-dy * units).clamp(-MAX_WHEEL_UNITS, MAX_WHEEL_UNITS) + rest_y;
+            let (wheel_x, wheel_y) = (total_x.trunc(), total_y.trunc());
+            session.scroll_rest = (total_x - wheel_x, total_y - wheel_y);
+            if wheel_x != 0.0 {
+                debug!("MouseScroll X -> {} units", wheel_x);
+                mouse_wheel(wheel_x as i32, true);
+            }
+            if wheel_y != 0.0 {
+                debug!("MouseScroll Y -> {} units", wheel_y);
+                mouse_wheel(wheel_y as i32, false);
+            }
+        }
+        InputEvent::Pointer { id, kind, phase, contact } => {
+            debug!("Pointer -> id={} {:?} {:?} x={:.3} y={:.3}", id, kind, phase, contact.x, contact.y);
+            inject_pointer(pointers, held, geometry, id, kind, phase, contact);
+        }
+        InputEvent::Gamepad { index, buttons, axes } => {
+            let report = GamepadReport::from_standard(
+                &buttons[..buttons.len().min(MAX_GAMEPAD_BUTTONS)],
+                &axes[..axes.len().min(MAX_GAMEPAD_AXES)],
+            );
+            let slot = match session.gamepads.get(&index) {
+                Some(slot) => *slot,
+                None => {
+                    let events = session.events.clone();
+                    let rumble = Box::new(move |strong, weak| {
+                        events.push(HostMessage::Rumble { index, strong, weak });
+                    });
+                    let Some(slot) = gamepads.connect(rumble) else {
+                        // Reports keep coming, so only tell the user once per gamepad.
+                        if session.refused_gamepads.insert(index) {
+                            session.events.push(HostMessage::notice(
+                                NoticeLevel::Warning,
+                                format!("Gamepad {index} isn't forwarded: no virtual controller is available on the host"),
+                            ));
+                        }
+                        return;
+                    };
+                    session.refused_gamepads.remove(&index);
+                    session.gamepads.insert(index, slot);
+                    slot
+                }
+            };
+            debug!("Gamepad -> index={} slot={} {:?}", index, slot, report);
+            gamepads.update(slot, &report);
+        }
+        InputEvent::GamepadDisconnected { index } => {
+            debug!("GamepadDisconnected -> index={}", index);
+            session.refused_gamepads.remove(&index);
+            if let Some(slot) = session.gamepads.remove(&index) {
+                gamepads.disconnect(slot);
             }
         }
         InputEvent::KeyDown { code } => {
-            if let Some(key) = map_key(&code) {
-                debug!("KeyDown -> code={} key={:?}", code, key);
-                enigo.key_down(key);
+            match map_key(&code, config.key_mode) {
+                Some(target) => {
+                    debug!("KeyDown -> code={} target={:?}", code, target);
+                    press_key(enigo, target, true);
+                    held.keys.insert(code, target);
+                }
+                None => debug!("KeyDown -> code={} not forwarded", code),
             }
         }
         InputEvent::KeyUp { code } => {
-            if let Some(key) = map_key(&code) {
-                debug!("KeyUp -> code={} key={:?}", code, key);
-                enigo.key_up(key);
-            }
-        }
-    }
-}
-
-fn get_monitor_dimensions() -> Option<(usize, usize)> {
-    if let Ok(mon) = windows_capture::monitor::Monitor::primary() {
-        if let (Ok(w), Ok(h)) = (mon.width(), mon.height()) {
-            return Some((w as usize, h as usize));
-        }
-    }
-    None
-}
-
-fn map_button(button: u8) -> Option<MouseButton> {
-    match button {
-        0 => Some(MouseButton::Left),
-        1 => Some(MouseButton::Middle),
-        2 => Some(MouseButton::Right),
-        _ => None,
-    }
-}
-
-fn map_key(code: &str) -> Option<Key> {
-    match code {
-        "KeyA" => Some(Key::Layout('a')),
-        "KeyB" => Some(Key::Layout('b')),
-        "KeyC" => Some(Key::Layout('c')),
-        "KeyD" => Some(Key::Layout('d')),
-        "KeyE" => Some(Key::Layout('e')),
-        "KeyF" => Some(Key::Layout('f')),
-        "KeyG" => Some(Key::Layout('g')),
-        "KeyH" => Some(Key::Layout('h')),
-        "KeyI" => Some(Key::Layout('i')),
-        "KeyJ" => Some(Key::Layout('j')),
-        "KeyK" => Some(Key::Layout('k')),
-        "KeyL" => Some(Key::Layout('l')),
-        "KeyM" => Some(Key::Layout('m')),
-        "KeyN" => Some(Key::Layout('n')),
-        "KeyO" => Some(Key::Layout('o')),
-        "KeyP" => Some(Key::Layout('p')),
-        "KeyQ" => Some(Key::Layout('q')),
-        "KeyR" => Some(Key::Layout('r')),
-        "KeyS" => Some(Key::Layout('s')),
-        "KeyT" => Some(Key::Layout('t')),
-        "KeyU" => Some(Key::Layout('u')),
-        "KeyV" => Some(Key::Layout('v')),
-        "KeyW" => Some(Key::Layout('w')),
-        "KeyX" => Some(Key::Layout('x')),
-        "KeyY" => Some(Key::Layout('y')),
-        "KeyZ" => Some(Key::Layout('z')),
-
-        "Digit0" => Some(Key::Layout('0')),
-        "Digit1" => Some(Key::Layout('1')),
-        "Digit2" => Some(Key::Layout('2')),
-        "Digit3" => Some(Key::Layout('3')),
-        "Digit4" => Some(Key::Layout('4')),
-        "Digit5" => Some(Key::Layout('5')),
-        "Digit6" => Some(Key::Layout('6')),
-        "Digit7" => Some(Key::Layout('7')),
-        "Digit8" => Some(Key::Layout('8')),
-        "Digit9" => Some(Key::Layout('9')),
-
-        "Enter" => Some(Key::Return),
-        "Space" => Some(Key::Space),
-        "Backspace" => Some(Key::Backspace),
-        "Tab" => Some(Key::Tab),
-        "Escape" => Some(Key::Escape),
-        
-        "ShiftLeft" | "ShiftRight" => Some(Key::Shift),
-        "ControlLeft" | "ControlRight" => Some(Key::Control),
-        "AltLeft" | "AltRight" => Some(Key::Alt),
-        "MetaLeft" | "MetaRight" => Some(Key::Meta),
-
-        "ArrowLeft" => Some(Key::LeftArrow),
-        "ArrowRight" => Some(Key::RightArrow),
-        "ArrowUp" => Some(Key::UpArrow),
-        "ArrowDown" => Some(Key::DownArrow),
-
-        "Delete" => Some(Key::Delete),
-        "Home" => Some(Key::Home),
-        "End" => Some(Key::End),
-        "PageUp" => Some(Key::PageUp),
-        "PageDown" => Some(Key::PageDown),
-        
-        "Minus" => Some(Key::Layout('-')),
-        "Equal" => Some(Key::Layout('=')),
-        "BracketLeft" => Some(Key::Layout('[')),
-        "BracketRight" => Some(Key::Layout(']')),
-        "Backslash" => Some(Key::Layout('\\')),
-        "Semicolon" => Some(Key::Layout(';')),
-        "Quote" => Some(Key::Layout('\'')),
-        "Comma" => Some(Key::Layout(',')),
-        "Period" => Some(Key::Layout('.')),
-        "Slash" => Some(Key::Layout('/')),
-        "Backquote" => Some(Key::Layout('`')),
-
-        _ => {
-            if code.starts_with("Key") && code.len() == 4 {
-                code.chars().nth(3).map(|c| Key::Layout(c.to_ascii_lowercase()))
-            } else if code.starts_with("Digit") && code.len() == 6 {
-                code.chars().nth(5).map(Key::Layout)
-            } else {
-                None
+            // Release exactly what was pressed, even if the mapping has changed since.
+            let target = held.keys.remove(&code).or_else(|| map_key(&code, config.key_mode));
+            if let Some(target) = target {
+                debug!("KeyUp -> code={} target={:?}", code, target);
+                press_key(enigo, target, false);
+            }
+        }
+        InputEvent::TextInput { text } => {
+            debug!("TextInput -> {} char(s)", text.chars().count());
+            type_text(&text);
+        }
+        InputEvent::CompositionStart => debug!("CompositionStart"),
+        InputEvent::CompositionUpdate { text } => debug!("CompositionUpdate -> {:?}", text),
+        InputEvent::CompositionEnd { text } => {
+            debug!("CompositionEnd -> {:?}", text);
+            type_text(&text);
+        }
+        InputEvent::ReleaseAll => {
+            debug!("ReleaseAll");
+            release_held(enigo, pointers, geometry, held);
+            neutral_gamepads(gamepads, session);
+        }
+    }
+}
+
+/// Routes a touch or pen update to the synthetic pointer devices and keeps track
+/// of which contacts the session has down.
+fn inject_pointer(
+    pointers: &mut PointerInjector,
+    held:     &mut HeldInput,
+    geometry: &DisplayGeometry,
+    id:       u32,
+    kind:     PointerKind,
+    phase:    PointerPhase,
+    contact:  PointerContact,
+) {
+    match (kind, phase) {
+        (PointerKind::Touch, PointerPhase::Down) => {
+            // A repeated down for the same id means we missed its up.
+            if let Some(PointerSlot::Touch(slot)) = held.pointers.remove(&id) {
+                pointers.touch_up(slot, geometry, true);
+            }
+            if let Some(slot) = pointers.touch_down(geometry, contact) {
+                held.pointers.insert(id, PointerSlot::Touch(slot));
+            }
+        }
+        (PointerKind::Touch, PointerPhase::Move) => {
+            if let Some(PointerSlot::Touch(slot)) = held.pointers.get(&id) {
+                pointers.touch_move(*slot, geometry, contact);
             }
         }
+        (PointerKind::Touch, _) => {
+            if let Some(PointerSlot::Touch(slot)) = held.pointers.remove(&id) {
+                pointers.touch_up(slot, geometry, phase == PointerPhase::Cancel);
+            }
+        }
+        (PointerKind::Pen, _) => {
+            match phase {
+                PointerPhase::Down => {
+                    held.pointers.insert(id, PointerSlot::Pen);
+                }
+                PointerPhase::Move => {}
+                _ => {
+                    held.pointers.remove(&id);
+                }
+            }
+            pointers.pen(phase, geometry, contact);
+        }
+    }
+}
+
+/// Releases every held key, button and touch or pen contact and forgets them.
+fn release_held(
+    enigo:    &mut Enigo,
+    pointers: &mut PointerInjector,
+    geometry: &DisplayGeometry,
+    held:     &mut HeldInput,
+) {
+    for (code, target) in held.keys.drain() {
+        debug!("Release -> code={} target={:?}", code, target);
+        press_key(enigo, target, false);
+    }
+    for (button, target) in held.buttons.drain() {
+        debug!("Release -> button={} target={:?}", button, target);
+        press_button(enigo, target, false);
+    }
+    for (id, slot) in held.pointers.drain() {
+        debug!("Release -> pointer={} slot={:?}", id, slot);
+        match slot {
+            PointerSlot::Touch(slot) => pointers.touch_up(slot, geometry, true),
+            PointerSlot::Pen         => pointers.pen_release(geometry),
+        }
+    }
+}
+
+/// Puts every controller of the session back to rest (nothing pressed, sticks centered).
+/// The controllers stay plugged in so games don't pause on a focus change.
+fn neutral_gamepads(gamepads: &mut Gamepads, session: &SessionInput) {
+    for slot in session.gamepads.values() {
+        gamepads.update(*slot, &GamepadReport::default());
+    }
+}
+
+/// Types 'text' on the host, truncated to 'MAX_TEXT_INPUT_CHARS'.
+fn type_text(text: &str) {
+    let count = text.chars().count();
+    if count > MAX_TEXT_INPUT_CHARS {
+        warn!("TextInput of {count} chars truncated to {MAX_TEXT_INPUT_CHARS}");
+        let end = text.char_indices().nth(MAX_TEXT_INPUT_CHARS).map_or(text.len(), |(i, _)| i);
+        sendinput::type_text(&text[..end]);
+    } else {
+        sendinput::type_text(text);
+    }
+}
+
+fn press_key(enigo: &mut Enigo, target: KeyTarget, down: bool) {
+    match (target, down) {
+        (KeyTarget::Scancode(scancode), _) => sendinput::key_scancode(scancode, !down),
+        (KeyTarget::Key(key), true)        => enigo.key_down(key),
+        (KeyTarget::Key(key), false)       => enigo.key_up(key),
+    }
+}
+
+fn press_button(enigo: &mut Enigo, target: ButtonTarget, down: bool) {
+    match (target, down) {
+        (ButtonTarget::Mouse(btn), true)  => enigo.mouse_down(btn),
+        (ButtonTarget::Mouse(btn), false) => enigo.mouse_up(btn),
+        (ButtonTarget::X(xbutton), _)     => sendinput::mouse_xbutton(xbutton, !down),
+        (ButtonTarget::Key(key), _)       => press_key(enigo, key, down),
+    }
+}
+
+/// Scrolls by 'units' ('WHEEL_DELTA' per notch, positive is up or right).
+fn mouse_wheel(units: i32, horizontal: bool) {
+    #[cfg(windows)]
+    sendinput::mouse_wheel(units, horizontal);
+    #[cfg(target_os = "linux")]
+    crate::uinput::mouse_wheel(units, horizontal);
+}
+
+/// Whether a client position is inside the video (0.0 - 1.0 on both axes, not NaN).
+fn is_normalized(x: f64, y: f64) -> bool {
+    (0.0..=1.0).contains(&x) && (0.0..=1.0).contains(&y)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gamepad(session: &Arc<str>, a: f64, stick_x: f64) -> InputCommand {
+        InputCommand::Event {
+            session:  Arc::clone(session),
+            event:    InputEvent::Gamepad { index: 0, buttons: vec![a], axes: vec![stick_x, 0.0] },
+            received: Instant::now(),
+        }
     }
-}
\ No newline at end of file
+
+    fn gamepad_states(batch: &[InputCommand]) -> Vec<(f64, f64)> {
+        batch.iter()
+            .map(|cmd| match cmd {
+                InputCommand::Event { event: InputEvent::Gamepad { buttons, axes, .. }, .. } => (buttons[0], axes[0]),
+                _ => panic!("not a gamepad report"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn host_messages_wait_for_hello_and_are_capped() {
+        let (queue, mut rx) = HostMessageQueue::new();
+        queue.push(HostMessage::error(ErrorCode::MalformedMessage, "before hello"));
+        assert!(rx.try_recv().is_err());
+
+        queue.listening.store(true, Ordering::Relaxed);
+        for _ in 0..HOST_MESSAGE_QUEUE * 2 {
+            queue.push(HostMessage::Rumble { index: 0, strong: 1.0, weak: 0.0 });
+        }
+        let mut received = 0;
+        while rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(received, HOST_MESSAGE_QUEUE);
+    }
+
+    #[test]
+    fn gamepad_axis_reports_are_merged() {
+        let session: Arc<str> = Arc::from("peer");
+        let mut batch = vec![gamepad(&session, 0.0, 0.1), gamepad(&session, 0.0, 0.2), gamepad(&session, 0.0, 0.3)];
+        assert_eq!(coalesce_moves(&mut batch), 2);
+        assert_eq!(gamepad_states(&batch), [(0.0, 0.3)]);
+    }
+
+    #[test]
+    fn gamepad_button_changes_are_kept() {
+        let session: Arc<str> = Arc::from("peer");
+        // A tap of A within one batch, with the stick moving around it.
+        let mut batch = vec![
+            gamepad(&session, 0.0, 0.1),
+            gamepad(&session, 1.0, 0.2),
+            gamepad(&session, 1.0, 0.3),
+            gamepad(&session, 0.0, 0.4),
+        ];
+        assert_eq!(coalesce_moves(&mut batch), 1);
+        assert_eq!(gamepad_states(&batch), [(0.0, 0.1), (1.0, 0.3), (0.0, 0.4)]);
+    }
+
+    #[test]
+    fn other_sessions_gamepads_are_not_merged() {
+        let (first, second): (Arc<str>, Arc<str>) = (Arc::from("a"), Arc::from("b"));
+        let mut batch = vec![gamepad(&first, 0.0, 0.1), gamepad(&second, 0.0, 0.2)];
+        assert_eq!(coalesce_moves(&mut batch), 0);
+    }
+}
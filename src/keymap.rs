@@ -0,0 +1,384 @@
+use enigo::Key;
+use tracing::warn;
+
+/// The W3C 'KeyboardEvent.code' table (https://www.w3.org/TR/uievents-code/).
+/// Every code the browser can send is either listed here with the key we inject on
+/// the host, or in 'UNSUPPORTED'.
+/// Character keys use 'Key::Layout' like before; everything else uses the Windows
+/// virtual-key code through 'Key::Raw'.
+/// The last column is the hardware (set 1) scancode used in 'KeyMode::Scancode';
+/// extended keys carry the 0xE0 prefix and 0x0000 means "no scancode, use the key".
+pub const KEYMAP: &[(&str, Key, u16)] = &[
+    // Writing system keys.
+    ("Backquote",      Key::Layout('`'),                    0x0029),
+    ("Backslash",      Key::Layout('\\'),                   0x002B),
+    ("BracketLeft",    Key::Layout('['),                    0x001A),
+    ("BracketRight",   Key::Layout(']'),                    0x001B),
+    ("Comma",          Key::Layout(','),                    0x0033),
+    ("Digit0",         Key::Layout('0'),                    0x000B),
+    ("Digit1",         Key::Layout('1'),                    0x0002),
+    ("Digit2",         Key::Layout('2'),                    0x0003),
+    ("Digit3",         Key::Layout('3'),                    0x0004),
+    ("Digit4",         Key::Layout('4'),                    0x0005),
+    ("Digit5",         Key::Layout('5'),                    0x0006),
+    ("Digit6",         Key::Layout('6'),                    0x0007),
+    ("Digit7",         Key::Layout('7'),                    0x0008),
+    ("Digit8",         Key::Layout('8'),                    0x0009),
+    ("Digit9",         Key::Layout('9'),                    0x000A),
+    ("Equal",          Key::Layout('='),                    0x000D),
+    ("IntlBackslash",  Key::Raw(VK_OEM_102),                0x0056),
+    ("IntlRo",         Key::Raw(VK_ABNT_C1),                0x0073),
+    ("IntlYen",        Key::Raw(VK_OEM_5),                  0x007D),
+    ("KeyA",           Key::Layout('a'),                    0x001E),
+    ("KeyB",           Key::Layout('b'),                    0x0030),
+    ("KeyC",           Key::Layout('c'),                    0x002E),
+    ("KeyD",           Key::Layout('d'),                    0x0020),
+    ("KeyE",           Key::Layout('e'),                    0x0012),
+    ("KeyF",           Key::Layout('f'),                    0x0021),
+    ("KeyG",           Key::Layout('g'),                    0x0022),
+    ("KeyH",           Key::Layout('h'),                    0x0023),
+    ("KeyI",           Key::Layout('i'),                    0x0017),
+    ("KeyJ",           Key::Layout('j'),                    0x0024),
+    ("KeyK",           Key::Layout('k'),                    0x0025),
+    ("KeyL",           Key::Layout('l'),                    0x0026),
+    ("KeyM",           Key::Layout('m'),                    0x0032),
+    ("KeyN",           Key::Layout('n'),                    0x0031),
+    ("KeyO",           Key::Layout('o'),                    0x0018),
+    ("KeyP",           Key::Layout('p'),                    0x0019),
+    ("KeyQ",           Key::Layout('q'),                    0x0010),
+    ("KeyR",           Key::Layout('r'),                    0x0013),
+    ("KeyS",           Key::Layout('s'),                    0x001F),
+    ("KeyT",           Key::Layout('t'),                    0x0014),
+    ("KeyU",           Key::Layout('u'),                    0x0016),
+    ("KeyV",           Key::Layout('v'),                    0x002F),
+    ("KeyW",           Key::Layout('w'),                    0x0011),
+    ("KeyX",           Key::Layout('x'),                    0x002D),
+    ("KeyY",           Key::Layout('y'),                    0x0015),
+    ("KeyZ",           Key::Layout('z'),                    0x002C),
+    ("Minus",          Key::Layout('-'),                    0x000C),
+    ("Period",         Key::Layout('.'),                    0x0034),
+    ("Quote",          Key::Layout('\''),                   0x0028),
+    ("Semicolon",      Key::Layout(';'),                    0x0027),
+    ("Slash",          Key::Layout('/'),                    0x0035),
+
+    // Functional keys.
+    ("AltLeft",        Key::Alt,                            0x0038),
+    ("AltRight",       Key::Alt,                            0xE038),
+    ("Backspace",      Key::Backspace,                      0x000E),
+    ("CapsLock",       Key::Raw(VK_CAPITAL),                0x003A),
+    ("ContextMenu",    Key::Raw(VK_APPS),                   0xE05D),
+    ("ControlLeft",    Key::Control,                        0x001D),
+    ("ControlRight",   Key::Control,                        0xE01D),
+    ("Enter",          Key::Return,                         0x001C),
+    ("MetaLeft",       Key::Meta,                           0xE05B),
+    ("MetaRight",      Key::Meta,                           0xE05C),
+    ("ShiftLeft",      Key::Shift,                          0x002A),
+    ("ShiftRight",     Key::Shift,                          0x0036),
+    ("Space",          Key::Space,                          0x0039),
+    ("Tab",            Key::Tab,                            0x000F),
+    ("Convert",        Key::Raw(VK_CONVERT),                0x0079),
+    ("KanaMode",       Key::Raw(VK_KANA),                   0x0070),
+    ("Lang1",          Key::Raw(VK_KANA),                   0x0072), // Hangul/English toggle shares the Kana VK.
+    ("Lang2",          Key::Raw(VK_HANJA),                  0x0071),
+    ("NonConvert",     Key::Raw(VK_NONCONVERT),             0x007B),
+
+    // Control pad.
+    ("Delete",         Key::Delete,                         0xE053),
+    ("End",            Key::End,                            0xE04F),
+    ("Help",           Key::Raw(VK_HELP),                   0x0000),
+    ("Home",           Key::Home,                           0xE047),
+    ("Insert",         Key::Raw(VK_INSERT),                 0xE052),
+    ("PageDown",       Key::PageDown,                       0xE051),
+    ("PageUp",         Key::PageUp,                         0xE049),
+
+    // Arrow pad.
+    ("ArrowDown",      Key::DownArrow,                      0xE050),
+    ("ArrowLeft",      Key::LeftArrow,                      0xE04B),
+    ("ArrowRight",     Key::RightArrow,                     0xE04D),
+    ("ArrowUp",        Key::UpArrow,                        0xE048),
+
+    // Numpad.
+    ("NumLock",              Key::Raw(VK_NUMLOCK),                0xE045),
+    ("Numpad0",              Key::Raw(VK_NUMPAD0),                0x0052),
+    ("Numpad1",              Key::Raw(VK_NUMPAD0 + 1),            0x004F),
+    ("Numpad2",              Key::Raw(VK_NUMPAD0 + 2),            0x0050),
+    ("Numpad3",              Key::Raw(VK_NUMPAD0 + 3),            0x0051),
+    ("Numpad4",              Key::Raw(VK_NUMPAD0 + 4),            0x004B),
+    ("Numpad5",              Key::Raw(VK_NUMPAD0 + 5),            0x004C),
+    ("Numpad6",              Key::Raw(VK_NUMPAD0 + 6),            0x004D),
+    ("Numpad7",              Key::Raw(VK_NUMPAD0 + 7),            0x0047),
+    ("Numpad8",              Key::Raw(VK_NUMPAD0 + 8),            0x0048),
+    ("Numpad9",              Key::Raw(VK_NUMPAD0 + 9),            0x0049),
+    ("NumpadAdd",            Key::Raw(VK_ADD),                    0x004E),
+    ("NumpadClear",          Key::Raw(VK_CLEAR),                  0x0000),
+    ("NumpadComma",          Key::Raw(VK_ABNT_C2),                0x007E),
+    ("NumpadDecimal",        Key::Raw(VK_DECIMAL),                0x0053),
+    ("NumpadDivide",         Key::Raw(VK_DIVIDE),                 0xE035),
+    ("NumpadEnter",          Key::Return,                         0xE01C),
+    ("NumpadEqual",          Key::Raw(VK_OEM_NEC_EQUAL),          0x0059),
+    ("NumpadMultiply",       Key::Raw(VK_MULTIPLY),               0x0037),
+    ("NumpadSubtract",       Key::Raw(VK_SUBTRACT),               0x004A),
+
+    // Function section.
+    ("Escape",         Key::Escape,                         0x0001),
+    ("F1",             Key::Raw(VK_F1),                     0x003B),
+    ("F2",             Key::Raw(VK_F1 + 1),                 0x003C),
+    ("F3",             Key::Raw(VK_F1 + 2),                 0x003D),
+    ("F4",             Key::Raw(VK_F1 + 3),                 0x003E),
+    ("F5",             Key::Raw(VK_F1 + 4),                 0x003F),
+    ("F6",             Key::Raw(VK_F1 + 5),                 0x0040),
+    ("F7",             Key::Raw(VK_F1 + 6),                 0x0041),
+    ("F8",             Key::Raw(VK_F1 + 7),                 0x0042),
+    ("F9",             Key::Raw(VK_F1 + 8),                 0x0043),
+    ("F10",            Key::Raw(VK_F1 + 9),                 0x0044),
+    ("F11",            Key::Raw(VK_F1 + 10),                0x0057),
+    ("F12",            Key::Raw(VK_F1 + 11),                0x0058),
+    ("F13",            Key::Raw(VK_F1 + 12),                0x0064),
+    ("F14",            Key::Raw(VK_F1 + 13),                0x0065),
+    ("F15",            Key::Raw(VK_F1 + 14),                0x0066),
+    ("F16",            Key::Raw(VK_F1 + 15),                0x0067),
+    ("F17",            Key::Raw(VK_F1 + 16),                0x0068),
+    ("F18",            Key::Raw(VK_F1 + 17),                0x0069),
+    ("F19",            Key::Raw(VK_F1 + 18),                0x006A),
+    ("F20",            Key::Raw(VK_F1 + 19),                0x006B),
+    ("F21",            Key::Raw(VK_F1 + 20),                0x006C),
+    ("F22",            Key::Raw(VK_F1 + 21),                0x006D),
+    ("F23",            Key::Raw(VK_F1 + 22),                0x006E),
+    ("F24",            Key::Raw(VK_F1 + 23),                0x0076),
+    ("PrintScreen",    Key::Raw(VK_SNAPSHOT),               0xE037),
+    ("ScrollLock",     Key::Raw(VK_SCROLL),                 0x0046),
+    ("Pause",          Key::Raw(VK_PAUSE),                  0x0045),
+
+    // Media keys.
+    ("BrowserBack",        Key::Raw(VK_BROWSER_BACK),           0xE06A),
+    ("BrowserFavorites",   Key::Raw(VK_BROWSER_FAVORITES),       0xE066),
+    ("BrowserForward",     Key::Raw(VK_BROWSER_FORWARD),        0xE069),
+    ("BrowserHome",        Key::Raw(VK_BROWSER_HOME),           0xE032),
+    ("BrowserRefresh",     Key::Raw(VK_BROWSER_REFRESH),        0xE067),
+    ("BrowserSearch",      Key::Raw(VK_BROWSER_SEARCH),         0xE065),
+    ("BrowserStop",        Key::Raw(VK_BROWSER_STOP),           0xE068),
+    ("LaunchApp1",         Key::Raw(VK_LAUNCH_APP1),            0xE06B),
+    ("LaunchApp2",         Key::Raw(VK_LAUNCH_APP2),            0xE021),
+    ("LaunchMail",         Key::Raw(VK_LAUNCH_MAIL),            0xE06C),
+    ("MediaPlayPause",     Key::Raw(VK_MEDIA_PLAY_PAUSE),       0xE022),
+    ("MediaSelect",        Key::Raw(VK_LAUNCH_MEDIA_SELECT),       0xE06D),
+    ("MediaStop",          Key::Raw(VK_MEDIA_STOP),             0xE024),
+    ("MediaTrackNext",     Key::Raw(VK_MEDIA_NEXT_TRACK),       0xE019),
+    ("MediaTrackPrevious", Key::Raw(VK_MEDIA_PREV_TRACK),       0xE010),
+    ("AudioVolumeDown",    Key::Raw(VK_VOLUME_DOWN),            0xE02E),
+    ("AudioVolumeMute",    Key::Raw(VK_VOLUME_MUTE),            0xE020),
+    ("AudioVolumeUp",      Key::Raw(VK_VOLUME_UP),              0xE030),
+
+    // Legacy, non-standard and special keys.
+    ("Super",          Key::Meta,                           0xE05B),
+    ("Select",         Key::Raw(VK_SELECT),                 0x0000),
+    ("OSLeft",         Key::Meta,                           0xE05B), // Older Firefox name for MetaLeft.
+    ("OSRight",        Key::Meta,                           0xE05C),
+];
+
+/// Codes from the same table that we deliberately don't forward: no Windows
+/// equivalent, or a key like Sleep/Power that would knock the host off the network.
+pub const UNSUPPORTED: &[&str] = &[
+    "Lang3",
+    "Lang4",
+    "Lang5",
+    "NumpadBackspace",
+    "NumpadClearEntry",
+    "NumpadHash",
+    "NumpadMemoryAdd",
+    "NumpadMemoryClear",
+    "NumpadMemoryRecall",
+    "NumpadMemoryStore",
+    "NumpadMemorySubtract",
+    "NumpadParenLeft",
+    "NumpadParenRight",
+    "NumpadStar",
+    "Fn",                    // Handled by keyboard firmware, never seen by the OS.
+    "FnLock",
+    "Eject",
+    "Power",                 // Would shut the host down mid-session.
+    "Sleep",                 // Would suspend the host mid-session.
+    "WakeUp",
+    "Hyper",
+    "Turbo",
+    "Abort",
+    "Resume",
+    "Suspend",
+    "Again",
+    "Copy",
+    "Cut",
+    "Find",
+    "Open",
+    "Paste",
+    "Props",
+    "Undo",
+    "Hiragana",
+    "Katakana",
+    "Unidentified",
+];
+
+/// How key events are injected on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyMode {
+    /// Send the physical key's scancode and let the host's keyboard layout decide
+    /// what it means. Correct for AZERTY, QWERTZ and other non-US host layouts.
+    Scancode,
+    /// Send the US-layout character (or virtual key) for the physical key.
+    /// This was the only mode before scancode injection existed.
+    Character,
+}
+
+impl KeyMode {
+    /// Reads 'LOCALBRIDGE_KEY_MODE' ("scancode" or "char"); scancode is the default.
+    pub fn from_env() -> Self {
+        match std::env::var("LOCALBRIDGE_KEY_MODE").as_deref() {
+            Ok("char") | Ok("character") => KeyMode::Character,
+            Ok("scancode") | Err(_) => KeyMode::Scancode,
+            Ok(other) => {
+                warn!("Unknown LOCALBRIDGE_KEY_MODE '{other}'; using scancode");
+                KeyMode::Scancode
+            }
+        }
+    }
+}
+
+/// What to inject for one browser key code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyTarget {
+    Scancode(u16),
+    Key(Key),
+}
+
+/// Looks up a browser 'KeyboardEvent.code' in 'KEYMAP' for the given mode.
+/// Keys without a scancode fall back to their enigo key in scancode mode.
+/// Returns 'None' both for codes marked unsupported and for codes that aren't in the table.
+pub fn map_key(code: &str, mode: KeyMode) -> Option<KeyTarget> {
+    let (_, key, scancode) = KEYMAP.iter().find(|(c, _, _)| *c == code)?;
+    match mode {
+        KeyMode::Scancode if *scancode != 0 => Some(KeyTarget::Scancode(*scancode)),
+        _ => Some(KeyTarget::Key(*key)),
+    }
+}
+
+// Windows virtual-key codes (WinUser.h) that enigo has no named variant for.
+const VK_CLEAR: u16 = 0x0C;
+const VK_PAUSE: u16 = 0x13;
+const VK_CAPITAL: u16 = 0x14;
+const VK_KANA: u16 = 0x15;
+const VK_HANJA: u16 = 0x19;
+const VK_CONVERT: u16 = 0x1C;
+const VK_NONCONVERT: u16 = 0x1D;
+const VK_SELECT: u16 = 0x29;
+const VK_SNAPSHOT: u16 = 0x2C;
+const VK_INSERT: u16 = 0x2D;
+const VK_HELP: u16 = 0x2F;
+const VK_APPS: u16 = 0x5D;
+const VK_NUMPAD0: u16 = 0x60;
+const VK_MULTIPLY: u16 = 0x6A;
+const VK_ADD: u16 = 0x6B;
+const VK_SUBTRACT: u16 = 0x6D;
+const VK_DECIMAL: u16 = 0x6E;
+const VK_DIVIDE: u16 = 0x6F;
+const VK_F1: u16 = 0x70;
+const VK_NUMLOCK: u16 = 0x90;
+const VK_SCROLL: u16 = 0x91;
+const VK_OEM_NEC_EQUAL: u16 = 0x92;
+const VK_BROWSER_BACK: u16 = 0xA6;
+const VK_BROWSER_FORWARD: u16 = 0xA7;
+const VK_BROWSER_REFRESH: u16 = 0xA8;
+const VK_BROWSER_STOP: u16 = 0xA9;
+const VK_BROWSER_SEARCH: u16 = 0xAA;
+const VK_BROWSER_FAVORITES: u16 = 0xAB;
+const VK_BROWSER_HOME: u16 = 0xAC;
+const VK_VOLUME_MUTE: u16 = 0xAD;
+const VK_VOLUME_DOWN: u16 = 0xAE;
+const VK_VOLUME_UP: u16 = 0xAF;
+const VK_MEDIA_NEXT_TRACK: u16 = 0xB0;
+const VK_MEDIA_PREV_TRACK: u16 = 0xB1;
+const VK_MEDIA_STOP: u16 = 0xB2;
+const VK_MEDIA_PLAY_PAUSE: u16 = 0xB3;
+const VK_LAUNCH_MAIL: u16 = 0xB4;
+const VK_LAUNCH_MEDIA_SELECT: u16 = 0xB5;
+const VK_LAUNCH_APP1: u16 = 0xB6;
+const VK_LAUNCH_APP2: u16 = 0xB7;
+const VK_ABNT_C1: u16 = 0xC1;
+const VK_ABNT_C2: u16 = 0xC2;
+const VK_OEM_5: u16 = 0xDC;
+const VK_OEM_102: u16 = 0xE2;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Every 'KeyboardEvent.code' from the W3C table, plus the F13-F24 and
+    /// 'OSLeft'/'OSRight' codes browsers send outside of it.
+    fn browser_codes() -> Vec<String> {
+        let mut codes: Vec<String> = [
+            "Backquote", "Backslash", "BracketLeft", "BracketRight", "Comma", "Equal",
+            "IntlBackslash", "IntlRo", "IntlYen", "Minus", "Period", "Quote", "Semicolon", "Slash",
+            "AltLeft", "AltRight", "Backspace", "CapsLock", "ContextMenu", "ControlLeft",
+            "ControlRight", "Enter", "MetaLeft", "MetaRight", "ShiftLeft", "ShiftRight", "Space",
+            "Tab", "Convert", "KanaMode", "Lang1", "Lang2", "Lang3", "Lang4", "Lang5", "NonConvert",
+            "Delete", "End", "Help", "Home", "Insert", "PageDown", "PageUp",
+            "ArrowDown", "ArrowLeft", "ArrowRight", "ArrowUp",
+            "NumLock", "NumpadAdd", "NumpadBackspace", "NumpadClear", "NumpadClearEntry",
+            "NumpadComma", "NumpadDecimal", "NumpadDivide", "NumpadEnter", "NumpadEqual",
+            "NumpadHash", "NumpadMemoryAdd", "NumpadMemoryClear", "NumpadMemoryRecall",
+            "NumpadMemoryStore", "NumpadMemorySubtract", "NumpadMultiply", "NumpadParenLeft",
+            "NumpadParenRight", "NumpadStar", "NumpadSubtract",
+            "Escape", "Fn", "FnLock", "PrintScreen", "ScrollLock", "Pause",
+            "BrowserBack", "BrowserFavorites", "BrowserForward", "BrowserHome", "BrowserRefresh",
+            "BrowserSearch", "BrowserStop", "Eject", "LaunchApp1", "LaunchApp2", "LaunchMail",
+            "MediaPlayPause", "MediaSelect", "MediaStop", "MediaTrackNext", "MediaTrackPrevious",
+            "Power", "Sleep", "AudioVolumeDown", "AudioVolumeMute", "AudioVolumeUp", "WakeUp",
+            "Hyper", "Super", "Turbo", "Abort", "Resume", "Suspend", "Again", "Copy", "Cut",
+            "Find", "Open", "Paste", "Props", "Select", "Undo", "Hiragana", "Katakana",
+            "OSLeft", "OSRight", "Unidentified",
+        ].iter().map(|code| code.to_string()).collect();
+        codes.extend((0..10).map(|n| format!("Digit{n}")));
+        codes.extend((0..10).map(|n| format!("Numpad{n}")));
+        codes.extend(('A'..='Z').map(|c| format!("Key{c}")));
+        codes.extend((1..=24).map(|n| format!("F{n}")));
+        codes
+    }
+
+    #[test]
+    fn every_code_is_mapped_or_unsupported() {
+        let mapped: HashSet<&str> = KEYMAP.iter().map(|(code, _, _)| *code).collect();
+        let unsupported: HashSet<&str> = UNSUPPORTED.iter().copied().collect();
+        for code in browser_codes() {
+            match (mapped.contains(code.as_str()), unsupported.contains(code.as_str())) {
+                (true, false) => {
+                    assert!(map_key(&code, KeyMode::Scancode).is_some(), "{code}");
+                    assert!(map_key(&code, KeyMode::Character).is_some(), "{code}");
+                }
+                (false, true) => {
+                    assert_eq!(map_key(&code, KeyMode::Scancode), None, "{code}");
+                    assert_eq!(map_key(&code, KeyMode::Character), None, "{code}");
+                }
+                (true, true) => panic!("{code} is both mapped and unsupported"),
+                (false, false) => panic!("{code} is neither mapped nor listed as unsupported"),
+            }
+        }
+    }
+
+    #[test]
+    fn tables_only_hold_browser_codes_once() {
+        let known: HashSet<String> = browser_codes().into_iter().collect();
+        let mut seen = HashSet::new();
+        for code in KEYMAP.iter().map(|(code, _, _)| *code).chain(UNSUPPORTED.iter().copied()) {
+            assert!(known.contains(code), "{code} is not a KeyboardEvent.code");
+            assert!(seen.insert(code), "{code} is listed twice");
+        }
+    }
+
+    #[test]
+    fn scancode_mode_falls_back_to_the_key() {
+        assert_eq!(map_key("KeyA", KeyMode::Scancode), Some(KeyTarget::Scancode(0x001E)));
+        assert_eq!(map_key("KeyA", KeyMode::Character), Some(KeyTarget::Key(Key::Layout('a'))));
+        // No scancode, so both modes inject the virtual key.
+        assert_eq!(map_key("Help", KeyMode::Scancode), Some(KeyTarget::Key(Key::Raw(VK_HELP))));
+        assert_eq!(map_key("NotAKey", KeyMode::Scancode), None);
+    }
+}
@@ -0,0 +1,94 @@
+use std::{sync::OnceLock, time::Instant};
+
+// End-to-end latency measurement.
+// Every encoded frame carries an H.264 SEI "user data unregistered" message with the
+// host time it was captured and how long encoding took. Browsers skip SEI they don't
+// know, so this costs nothing for clients that don't look. Clients that do read it
+// (through encoded insertable streams) sync their clock to the host's with 'ping' /
+// 'pong' on the control channel, work out how long each stage took and report the
+// averages back once per second, which the host logs.
+
+/// Identifies our SEI payload among any others in the stream.
+pub const SEI_UUID: [u8; 16] = *b"LocalBridgeStamp";
+
+/// 'FrameStamp' is what the host knows about a frame when it leaves the encoder.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameStamp {
+    pub frame:      u64,
+    // Host clock ('host_clock_us') when the frame was captured.
+    pub capture_us: u64,
+    // Capture -> encoded, including the BGRA -> YUV conversion.
+    pub encode_us:  u64,
+}
+
+/// Microseconds on the host clock, which starts with the process. Clients map it to
+/// their own clock with 'ping', so its epoch doesn't matter.
+pub fn host_clock_us() -> u64 {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_micros() as u64
+}
+
+/// The host clock in milliseconds, the unit browsers use for 'performance.now()'.
+pub fn host_clock_ms() -> f64 {
+    host_clock_us() as f64 / 1000.0
+}
+
+/// Inserts the SEI NAL unit for 'stamp' in front of the first picture slice of an
+/// Annex B access unit (after any SPS/PPS, where decoders expect it).
+pub fn insert_sei(access_unit: Vec<u8>, stamp: FrameStamp) -> Vec<u8> {
+    let sei = sei_nal(stamp);
+    let at = first_slice(&access_unit).unwrap_or(0);
+    let mut out = Vec::with_capacity(access_unit.len() + sei.len());
+    out.extend_from_slice(&access_unit[..at]);
+    out.extend_from_slice(&sei);
+    out.extend_from_slice(&access_unit[at..]);
+    out
+}
+
+/// Builds the SEI NAL unit (with start code): payload type 5, our UUID, then frame
+/// number, capture time and encode time as big-endian u64.
+fn sei_nal(stamp: FrameStamp) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(16 + 24);
+    payload.extend_from_slice(&SEI_UUID);
+    payload.extend_from_slice(&stamp.frame.to_be_bytes());
+    payload.extend_from_slice(&stamp.capture_us.to_be_bytes());
+    payload.extend_from_slice(&stamp.encode_us.to_be_bytes());
+
+    // SEI message: payload type, payload size (both < 255), payload, then the RBSP
+    // stop bit.
+    let mut rbsp = vec![5, payload.len() as u8];
+    rbsp.extend_from_slice(&payload);
+    rbsp.push(0x80);
+
+    let mut nal = vec![0, 0, 0, 1, 0x06];
+    // Emulation prevention: the payload must not contain anything that looks like a
+    // start code, so a 0x03 goes after every two zero bytes followed by 0-3.
+    let mut zeros = 0;
+    for byte in rbsp {
+        if zeros >= 2 && byte <= 3 {
+            nal.push(3);
+            zeros = 0;
+        }
+        nal.push(byte);
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+    }
+    nal
+}
+
+/// Byte offset of the start code of the first slice NAL unit (IDR or non-IDR).
+fn first_slice(annex_b: &[u8]) -> Option<usize> {
+    let mut i = 0;
+    while i + 3 < annex_b.len() {
+        if annex_b[i] == 0 && annex_b[i + 1] == 0 && annex_b[i + 2] == 1 {
+            let nal_type = annex_b[i + 3] & 0x1f;
+            if nal_type == 1 || nal_type == 5 {
+                // Include the leading zero of a four-byte start code.
+                return Some(if i > 0 && annex_b[i - 1] == 0 { i - 1 } else { i });
+            }
+            i += 3;
+        } else {
+            i += 1;
+        }
+    }
+    None
+}
@@ -1,23 +1,48 @@
 // These 'mod' declarations tell Rust to look for other files in this project.
 // For example, 'mod capture' looks for capture.rs and makes its contents available here.
+mod admin;
+mod approval;
 mod capture;
+mod clipboard;
+mod cursor;
+mod display;
 mod encoder;
+mod events;
+mod gamepad;
+mod ice;
 mod input;
+mod keymap;
+mod latency;
+mod metrics;
+mod pointer;
+mod protocol;
+mod sendinput;
+mod session;
 mod signaling;
+mod transfer;
+#[cfg(target_os = "linux")]
+mod uinput;
+mod whep;
+mod wire;
 
 // 'use' statements are like imports in other languages. 
 // They bring external or internal items into the current scope.
-use std::{collections::HashMap, net::IpAddr, sync::Arc};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
 use anyhow::Result;
 use axum::{
-    extract::State,
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode},
     response::IntoResponse,
     routing::{get, post},
     Json, Router,
 };
-use tokio::sync::{broadcast, Mutex};
+use tokio::sync::{broadcast, watch, Mutex};
 use tower_http::cors::CorsLayer;
-use tracing::info;
+use tracing::{info, warn};
 use webrtc::{
     api::{
         interceptor_registry::register_default_interceptors,
@@ -29,6 +54,7 @@ use webrtc::{
     interceptor::registry::Registry,
     peer_connection::{
         configuration::RTCConfiguration,
+        peer_connection_state::RTCPeerConnectionState,
         sdp::session_description::RTCSessionDescription,
         RTCPeerConnection,
     },
@@ -46,9 +72,34 @@ pub struct AppState {
     // The WebRTC video track that we will push screen frames into.
     pub video_track: Arc<TrackLocalStaticSample>,
     // A list of connected peers. 'Mutex' ensures only one thread can modify this list at a time.
-    pub peers:       Arc<Mutex<HashMap<String, Arc<RTCPeerConnection>>>>,
+    // Peers are removed when their connection fails or closes.
+    pub peers:       Arc<Mutex<HashMap<String, Arc<session::Session>>>>,
     // A broadcast channel to send frames to multiple listeners if needed.
     pub frame_tx:    broadcast::Sender<Vec<u8>>,
+    // The current size of the captured display, updated by the capture loop.
+    pub display:     watch::Receiver<display::DisplayGeometry>,
+    // Entry point to the dedicated input injection thread.
+    pub input:       input::InputHandle,
+    // Entry point to the clipboard sync thread.
+    pub clipboard:   clipboard::ClipboardHandle,
+    // Host directories for file transfer.
+    pub transfer:    Arc<transfer::TransferConfig>,
+    // Protocol features this host offers in its 'hello'.
+    pub features:    Arc<[protocol::Feature]>,
+    // Cursor, encoder and host-wide messages for the control channels.
+    pub feeds:       protocol::HostFeeds,
+    // Counters and histograms served at '/metrics'.
+    pub metrics:     Arc<metrics::Metrics>,
+    // Lets the admin API force keyframes and change the bitrate.
+    pub encoder:     Arc<encoder::EncoderControl>,
+    // Token for the '/admin' routes.
+    pub admin:       Arc<admin::AdminConfig>,
+    // Offers waiting for the person at the host to approve them.
+    pub approvals:   Arc<approval::Approvals>,
+    // STUN/TURN servers for the host's peers and the served client.
+    pub ice:         Arc<ice::IceConfig>,
+    // Sessions created by WHEP players, by the resource token they were given.
+    pub whep:        Arc<whep::WhepSessions>,
 }
 
 /// The 'main' function is the entry point of the program.
@@ -56,11 +107,21 @@ pub struct AppState {
 /// tasks (like capture and web serving) concurrently.
 #[tokio::main]
 async fn main() -> Result<()> {
+    // '--print-schema' writes the data channel protocol's JSON schema and exits,
+    // so clients can be checked against it without starting a host.
+    if std::env::args().any(|arg| arg == "--print-schema") {
+        println!("{}", serde_json::to_string_pretty(&protocol::schema())?);
+        return Ok(());
+    }
+
     // Initialize logging so we can see what's happening in the console.
     tracing_subscriber::fmt()
         .with_env_filter("info,pixelbridge=debug,localbridge=debug,webrtc=error")
         .init();
 
+    // Work in physical pixels so capture size and cursor coordinates always agree.
+    display::enable_dpi_awareness();
+
     // Create the video track. This is the "pipe" through which our video data flows.
     let video_track = Arc::new(TrackLocalStaticSample::new(
         RTCRtpCodecCapability {
@@ -77,11 +138,45 @@ async fn main() -> Result<()> {
     // Create a broadcast channel for internal frame distribution.
     let (frame_tx, _) = broadcast::channel::<Vec<u8>>(32);
 
+    // The capture loop publishes the display geometry here; input handlers read it.
+    let (display_tx, display_rx) = watch::channel(display::DisplayGeometry::default());
+    // With 'LOCALBRIDGE_CURSOR=client' the cursor stays out of the video and clients draw it.
+    let cursor_mode = cursor::CursorMode::from_env();
+    info!("Cursor mode: {cursor_mode:?}");
+    let cursor_in_video = cursor_mode == cursor::CursorMode::Video;
+    // Input sessions in relative mode ask the capture loop to leave the cursor out.
+    let (capture_cursor_tx, capture_cursor_rx) = watch::channel(cursor_in_video);
+    // The capture loop also publishes the encoder settings it is running with.
+    let (encoder_tx, encoder_rx) = watch::channel(encoder::EncoderSettings::default());
+    let feeds = protocol::HostFeeds {
+        cursor:    cursor::spawn(),
+        encoder:   encoder_rx,
+        broadcast: broadcast::channel(16).0,
+    };
+
+    let clipboard = clipboard::spawn();
+    let transfer = Arc::new(transfer::TransferConfig::from_env());
+    let features = protocol::host_features(&clipboard, &transfer, cursor_mode).into();
+    let metrics = metrics::Metrics::new();
+    let encoder_control = Arc::new(encoder::EncoderControl::default());
+
     // Initialize our shared state.
     let state = AppState {
         video_track: video_track.clone(),
         peers:       Arc::new(Mutex::new(HashMap::new())),
         frame_tx:    frame_tx.clone(),
+        display:     display_rx.clone(),
+        input:       input::spawn(display_rx, capture_cursor_tx, cursor_in_video, metrics.clone()),
+        clipboard,
+        transfer,
+        features,
+        feeds,
+        metrics:     metrics.clone(),
+        encoder:     encoder_control.clone(),
+        admin:       Arc::new(admin::AdminConfig::from_env()),
+        approvals:   approval::Approvals::from_env(),
+        ice:         Arc::new(ice::IceConfig::from_env()),
+        whep:        Arc::default(),
     };
 
     // Spawn the screen capture loop on its own asynchronous task.
@@ -89,21 +184,36 @@ async fn main() -> Result<()> {
     let track_for_capture = video_track.clone();
     let tx_clone = frame_tx.clone();
     tokio::spawn(async move {
-        if let Err(e) = capture::run(track_for_capture, tx_clone).await {
+        if let Err(e) = capture::run(track_for_capture, tx_clone, display_tx, encoder_tx, encoder_control, capture_cursor_rx, metrics).await {
             tracing::error!("Capture loop error: {e}");
         }
     });
 
     // Define our web server routes.
     // - "/" serves the HTML/JS client.
-    // - "/offer" handles the WebRTC handshake.
-    // - "/ws/input" is a WebSocket for control messages.
+    // - "/offer" handles the WebRTC handshake ("/offer/:ticket" while it awaits approval).
+    // - "/ws/signal" is WebSocket signaling with trickle ICE ("/ws/input" is its old name).
+    // - "/ice-servers" gives the client the STUN/TURN servers to use.
+    // - "/protocol/schema.json" describes every data channel message.
+    // - "/metrics" exposes Prometheus metrics.
+    // - "/admin/..." lists and manages sessions (needs LOCALBRIDGE_ADMIN_TOKEN).
+    // - "/approve" lets the person at the host accept sessions (LOCALBRIDGE_APPROVAL=web).
+    // - "/whep" lets WHEP players (OBS, GStreamer's whepsrc) watch the stream.
     let app = Router::new()
-        .route("/",         get(serve_client))
-        .route("/offer",    post(handle_offer))
-        .route("/ws/input", get(signaling::ws_input_handler))
-        .with_state(state)
-        .layer(CorsLayer::permissive());
+        .route("/",                     get(serve_client))
+        .route("/offer",                post(handle_offer))
+        .route("/offer/:ticket",        get(handle_offer_status))
+        .route("/ws/signal",            get(signaling::ws_signal_handler))
+        .route("/ws/input",             get(signaling::ws_signal_handler))
+        .route("/ice-servers",          get(serve_ice_servers))
+        .route("/protocol/schema.json", get(serve_schema))
+        .route("/metrics",              get(serve_metrics))
+        .nest("/admin",                 admin::router(state.clone()))
+        .merge(whep::router())
+        .layer(CorsLayer::permissive())
+        // Only for the browser on the host itself, so no CORS.
+        .merge(approval::router())
+        .with_state(state.clone());
 
     // Bind the server to all network interfaces on port 7878.
     let addr = "0.0.0.0:7878";
@@ -111,16 +221,58 @@ async fn main() -> Result<()> {
     info!("LocalBridge listening on http://{addr}");
     info!("Open browser at http://<YOUR-LAN-IP>:7878");
 
-    // Start serving requests.
-    axum::serve(listener, app).await?;
+    // Start serving requests until Ctrl+C.
+    // The connect info gives handlers the client's address.
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown(state))
+        .await?;
     Ok(())
 }
 
+/// Waits for Ctrl+C, then tells every client why it is about to be disconnected
+/// and closes the peer connections, so browsers don't sit on a frozen picture.
+async fn shutdown(state: AppState) {
+    if tokio::signal::ctrl_c().await.is_err() {
+        // No signal handler: keep serving forever.
+        std::future::pending::<()>().await;
+    }
+    info!("Shutting down");
+    let _ = state.feeds.broadcast.send(protocol::HostMessage::Disconnect {
+        reason:  protocol::DisconnectReason::HostShutdown,
+        message: "The host is shutting down".to_owned(),
+    });
+    // Give the control channels a moment to deliver it.
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    for (_, session) in state.peers.lock().await.drain() {
+        let _ = session.pc.close().await;
+    }
+}
+
 /// Serves the embedded 'index.html' file to the browser.
 async fn serve_client() -> impl IntoResponse {
     axum::response::Html(include_str!("../client/index.html"))
 }
 
+/// Serves the STUN/TURN servers as the browser's 'iceServers' list.
+async fn serve_ice_servers(State(state): State<AppState>) -> impl IntoResponse {
+    Json(state.ice.servers.clone())
+}
+
+/// Serves the JSON schema generated from the protocol types.
+async fn serve_schema() -> impl IntoResponse {
+    Json(protocol::schema())
+}
+
+/// Serves the metrics in the Prometheus text format.
+async fn serve_metrics(State(state): State<AppState>) -> impl IntoResponse {
+    // Copy the sessions out so the lock isn't held while their statistics are read.
+    let sessions = state.peers.lock().await.values().cloned().collect();
+    (
+        [(axum::http::header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        state.metrics.render(sessions, false).await,
+    )
+}
+
 /// Defines the structure of the JSON we expect when a client sends a WebRTC offer.
 #[derive(serde::Deserialize)]
 struct OfferBody {
@@ -131,39 +283,143 @@ struct OfferBody {
 
 /// Axum handler for the POST /offer route.
 async fn handle_offer(
-    State(state): State<AppState>,
-    Json(body):   Json<OfferBody>,
+    State(state):             State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers:                  HeaderMap,
+    Json(body):               Json<OfferBody>,
 ) -> impl IntoResponse {
+    // The bundled client always gathers before posting; an empty offer means it broke.
+    if !body.sdp.contains("a=candidate:") {
+        tracing::error!("Offer error: offer contained 0 ICE candidates");
+        return (StatusCode::OK, Json(serde_json::json!({
+            "error": "Offer contained 0 ICE candidates. Refresh the client and retry.",
+        })));
+    }
+
+    // When the host has to approve sessions, park the offer and let the client poll.
+    if state.approvals.is_required() {
+        let request = approval::ApprovalRequest {
+            ticket:         uuid::Uuid::new_v4().to_string(),
+            remote_address: remote_addr,
+            user_agent:     headers.get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned),
+        };
+        let ticket = request.ticket.clone();
+        let approvals = Arc::clone(&state.approvals);
+        let parked = approvals.park(request, async move {
+            Ok(do_offer(state, body.sdp, remote_addr).await?.1.sdp)
+        });
+        if let Err(busy) = parked {
+            return (StatusCode::TOO_MANY_REQUESTS, Json(serde_json::json!({ "error": busy.refusal() })));
+        }
+        return (StatusCode::ACCEPTED, Json(serde_json::json!({ "status": "pending", "ticket": ticket })));
+    }
+
     // We delegate the actual logic to 'do_offer'.
-    match do_offer(state, body).await {
-        Ok(ans) => Json(serde_json::json!({ "sdp": ans.sdp, "type": "answer" })),
-        Err(e)  => {
+    match do_offer(state, body.sdp, remote_addr).await {
+        Ok((_, ans)) => (StatusCode::OK, Json(serde_json::json!({ "sdp": ans.sdp, "type": "answer" }))),
+        Err(e)       => {
             tracing::error!("Offer error: {e}");
-            Json(serde_json::json!({ "error": e.to_string() }))
+            (StatusCode::OK, Json(serde_json::json!({ "error": e.to_string() })))
         }
     }
 }
 
+/// Axum handler for GET /offer/:ticket. Waits a while for the host to decide on a
+/// parked offer, then answers like POST /offer, or "pending" again.
+async fn handle_offer_status(
+    State(state): State<AppState>,
+    Path(ticket): Path<String>,
+) -> impl IntoResponse {
+    match state.approvals.status(&ticket).await {
+        Some(approval::OfferStatus::Pending) => {
+            (StatusCode::ACCEPTED, Json(serde_json::json!({ "status": "pending", "ticket": ticket })))
+        }
+        Some(approval::OfferStatus::Answer(sdp)) => {
+            (StatusCode::OK, Json(serde_json::json!({ "sdp": sdp, "type": "answer" })))
+        }
+        Some(approval::OfferStatus::Failed(error)) => {
+            (StatusCode::OK, Json(serde_json::json!({ "error": error })))
+        }
+        None => (StatusCode::NOT_FOUND, Json(serde_json::json!({ "error": "Unknown or expired ticket" }))),
+    }
+}
+
 /// Performs the WebRTC handshake: receives an offer, sets up a connection, and returns an answer.
-async fn do_offer(state: AppState, body: OfferBody) -> Result<RTCSessionDescription> {
-    let remote_candidate_count = body.sdp.matches("a=candidate:").count();
+/// This is the non-trickle path: the answer only goes out once ICE gathering is complete
+/// and carries every host candidate. '/ws/signal' trickles them instead.
+/// Returns the new peer's ID with the answer.
+pub async fn do_offer(state: AppState, sdp: String, remote_addr: SocketAddr) -> Result<(String, RTCSessionDescription)> {
+    let remote_candidate_count = sdp.matches("a=candidate:").count();
     info!("Received offer with {remote_candidate_count} ICE candidate(s)");
-    if remote_candidate_count == 0 {
-        anyhow::bail!("Offer contained 0 ICE candidates. Refresh the client and retry.");
-    }
     info!(
         "Offer codec hints: h264={}, vp8={}",
-        body.sdp.contains("H264/90000"),
-        body.sdp.contains("VP8/90000")
+        sdp.contains("H264/90000"),
+        sdp.contains("VP8/90000")
     );
 
+    let (id, pc) = new_peer(&state, remote_addr).await?;
+    // The SDP comes from the client, so any step below may fail; the peer is already
+    // registered by then and must not linger.
+    let negotiated = async {
+        // Process the SDP offer from the client.
+        let offer = RTCSessionDescription::offer(sdp)?;
+        pc.set_remote_description(offer).await?;
+
+        // Create an answer to send back to the client.
+        let answer = pc.create_answer(None).await?;
+
+        // Wait for the ICE gathering to complete so we have all necessary network info.
+        let mut gather = pc.gathering_complete_promise().await;
+        pc.set_local_description(answer).await?;
+        let _ = gather.recv().await;
+
+        // Return the final local description (the "answer").
+        let local = pc.local_description().await
+            .ok_or_else(|| anyhow::anyhow!("No local description"))?;
+        let local_candidate_count = local.sdp.matches("a=candidate:").count();
+        if local_candidate_count == 0 {
+            anyhow::bail!("Server gathered 0 ICE candidates. Verify WLAN interface is up.");
+        }
+        info!(
+            "Answer codec hints: h264={}, vp8={}",
+            local.sdp.contains("H264/90000"),
+            local.sdp.contains("VP8/90000")
+        );
+        info!("Peer {id} connected (remote={remote_candidate_count}, local={local_candidate_count} candidates)");
+        Ok::<_, anyhow::Error>(local)
+    }.await;
+    match negotiated {
+        Ok(local) => Ok((id, local)),
+        Err(e) => {
+            discard_peer(&state, &id, &pc).await;
+            Err(e)
+        }
+    }
+}
+
+/// Undoes 'new_peer' for a session whose negotiation failed: forgets it and closes
+/// its peer connection, which ends its input, clipboard and transfer sessions.
+pub async fn discard_peer(state: &AppState, id: &str, pc: &RTCPeerConnection) {
+    state.peers.lock().await.remove(id);
+    if let Err(e) = pc.close().await {
+        warn!("Could not close peer {id}: {e}");
+    }
+}
+
+/// Creates the peer connection for a new session: video track, data channel
+/// handlers and cleanup, registered in 'AppState.peers'. Returns the peer ID.
+/// Every signaling path ('/offer', the WebSocket) starts here.
+pub async fn new_peer(state: &AppState, remote_addr: SocketAddr) -> Result<(String, Arc<RTCPeerConnection>)> {
     // Re-configure the MediaEngine for this specific connection.
     let mut me = MediaEngine::default();
     me.register_default_codecs()?;
     let reg = Registry::new();
     let reg = register_default_interceptors(reg, &mut me)?;
     let mut se = SettingEngine::default();
-    // WLAN/LAN default: avoid IPv6/STUN-related gather errors on Windows and keep host UDP/IPv4 candidates.
+    // Avoid IPv6-related gather errors on Windows: UDP/IPv4 host candidates, plus the
+    // server-reflexive and relay candidates of any STUN/TURN servers.
     se.set_network_types(vec![NetworkType::Udp4]);
     se.set_ip_filter(Box::new(|ip: IpAddr| match ip {
         IpAddr::V4(v4) => !v4.is_link_local() && !v4.is_unspecified(),
@@ -175,9 +431,9 @@ async fn do_offer(state: AppState, body: OfferBody) -> Result<RTCSessionDescript
         .with_interceptor_registry(reg)
         .build();
 
-    // LAN/WLAN mode: host candidates only (no public STUN/TURN).
+    // Host candidates only unless STUN/TURN servers are configured.
     let config = RTCConfiguration {
-        ice_servers: vec![],
+        ice_servers: state.ice.rtc_ice_servers(),
         ..Default::default()
     };
 
@@ -187,39 +443,59 @@ async fn do_offer(state: AppState, body: OfferBody) -> Result<RTCSessionDescript
     // Add our shared video track to this new connection so the client can see the screen.
     pc.add_track(Arc::clone(&state.video_track) as Arc<dyn TrackLocal + Send + Sync>).await?;
 
-    // Set up a Data Channel to receive mouse/keyboard input from the client.
-    pc.on_data_channel(Box::new(|dc| {
-        Box::pin(async move { input::handle_data_channel(dc).await; })
+    let id = uuid::Uuid::new_v4().to_string();
+
+    // Set up the Data Channels the client opens: "control" for the hello exchange and
+    // host notifications, "input" for mouse/keyboard events, "clipboard" for
+    // clipboard sync and "files" for file transfer.
+    let input_session = state.input.session(id.clone());
+    let clipboard_session = state.clipboard.session(id.clone());
+    let session_for_dc = Arc::clone(&input_session);
+    let transfer_config = Arc::clone(&state.transfer);
+    let host_features = Arc::clone(&state.features);
+    let host_feeds = state.feeds.clone();
+    pc.on_data_channel(Box::new(move |dc| {
+        let input = Arc::clone(&session_for_dc);
+        let clipboard = Arc::clone(&clipboard_session);
+        let transfer = Arc::clone(&transfer_config);
+        let features = Arc::clone(&host_features);
+        let feeds = host_feeds.clone();
+        Box::pin(async move {
+            match dc.label() {
+                "control"   => protocol::handle_data_channel(dc, input, features, feeds).await,
+                "clipboard" => clipboard::handle_data_channel(dc, clipboard, input).await,
+                "files"     => transfer::handle_data_channel(dc, transfer, input).await,
+                _           => input::handle_data_channel(dc, input).await,
+            }
+        })
+    }));
+
+    // If the peer drops without closing the data channel cleanly, don't leave
+    // keys or buttons stuck down on the host. Failed or closed peers are forgotten.
+    let session_for_state = Arc::clone(&input_session);
+    let peers = Arc::clone(&state.peers);
+    let peer_id = id.clone();
+    pc.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+        if matches!(
+            s,
+            RTCPeerConnectionState::Disconnected
+                | RTCPeerConnectionState::Failed
+                | RTCPeerConnectionState::Closed
+        ) {
+            session_for_state.release_all(&format!("peer connection {s}"));
+        }
+        let peers = Arc::clone(&peers);
+        let peer_id = peer_id.clone();
+        Box::pin(async move {
+            if matches!(s, RTCPeerConnectionState::Failed | RTCPeerConnectionState::Closed) {
+                peers.lock().await.remove(&peer_id);
+            }
+        })
     }));
 
     // Store the connection in our state.
-    let id = uuid::Uuid::new_v4().to_string();
-    state.peers.lock().await.insert(id.clone(), pc.clone());
+    let session = session::Session::new(id.clone(), pc.clone(), remote_addr, input_session);
+    state.peers.lock().await.insert(id.clone(), Arc::new(session));
+    Ok((id, pc))
 
-    // Process the SDP offer from the client.
-    let offer = RTCSessionDescription::offer(body.sdp)?;
-    pc.set_remote_description(offer).await?;
-    
-    // Create an answer to send back to the client.
-    let answer = pc.create_answer(None).await?;
-    
-    // Wait for the ICE gathering to complete so we have all necessary network info.
-    let mut gather = pc.gathering_complete_promise().await;
-    pc.set_local_description(answer).await?;
-    let _ = gather.recv().await;
-
-    // Return the final local description (the "answer").
-    let local = pc.local_description().await
-        .ok_or_else(|| anyhow::anyhow!("No local description"))?;
-    let local_candidate_count = local.sdp.matches("a=candidate:").count();
-    if local_candidate_count == 0 {
-        anyhow::bail!("Server gathered 0 ICE candidates. Verify WLAN interface is up.");
-    }
-    info!(
-        "Answer codec hints: h264={}, vp8={}",
-        local.sdp.contains("H264/90000"),
-        local.sdp.contains("VP8/90000")
-    );
-    info!("Peer {id} connected (remote={remote_candidate_count}, local={local_candidate_count} candidates)");
-    Ok(local)
 }
@@ -0,0 +1,218 @@
+use std::{
+    fmt::Write,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use crate::session::Session;
+
+// Prometheus metrics, served as text at 'GET /metrics'.
+// The capture and input threads update plain atomics, so recording costs next to
+// nothing; per-peer numbers (RTT, loss, bitrate) come from the RTCP statistics of
+// each session ('Session::video_stats') and are only read when someone scrapes.
+// '/metrics' needs no credentials, so it only reports them across all peers (worst
+// RTT and loss, totals); series labelled with session ids are served at
+// '/admin/metrics' behind the admin token.
+
+// Upper bounds of the time histograms, in seconds (1 ms to 50 ms around the 16.6 ms
+// frame budget).
+const TIME_BUCKETS: &[f64] = &[0.001, 0.002, 0.004, 0.006, 0.008, 0.010, 0.0125, 0.0166, 0.025, 0.05];
+// Upper bounds of the frame size histogram, in bytes.
+const SIZE_BUCKETS: &[f64] = &[1_000.0, 4_000.0, 16_000.0, 32_000.0, 64_000.0, 128_000.0, 256_000.0, 512_000.0, 1_000_000.0];
+
+/// 'Metrics' holds everything the host counts. One instance is shared by the capture
+/// loop, the input thread and the web server.
+pub struct Metrics {
+    pub capture_frames:   Counter,
+    pub capture_fps:      Gauge,
+    pub convert_seconds:  Histogram,
+    pub encode_seconds:   Histogram,
+    pub frame_bytes:      Histogram,
+    pub keyframes:        Counter,
+    pub dropped_frames:   Counter,
+    pub input_events:     Counter,
+    pub input_events_per_second: Gauge,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            capture_frames:   Counter::default(),
+            capture_fps:      Gauge::default(),
+            convert_seconds:  Histogram::new(TIME_BUCKETS),
+            encode_seconds:   Histogram::new(TIME_BUCKETS),
+            frame_bytes:      Histogram::new(SIZE_BUCKETS),
+            keyframes:        Counter::default(),
+            dropped_frames:   Counter::default(),
+            input_events:     Counter::default(),
+            input_events_per_second: Gauge::default(),
+        })
+    }
+
+    /// Renders every metric in the Prometheus text format, reading the statistics
+    /// of the given peers first. 'per_peer' adds a series per session, labelled
+    /// with its id.
+    pub async fn render(&self, sessions: Vec<Arc<Session>>, per_peer: bool) -> String {
+        let mut out = String::new();
+        counter(&mut out, "localbridge_capture_frames_total", "Frames captured and encoded.", &self.capture_frames);
+        gauge(&mut out, "localbridge_capture_fps", "Frames captured and encoded in the last second.", self.capture_fps.get());
+        histogram(&mut out, "localbridge_convert_seconds", "Time to convert a frame from BGRA to YUV420.", &self.convert_seconds);
+        histogram(&mut out, "localbridge_encode_seconds", "Time to encode a frame to H.264.", &self.encode_seconds);
+        histogram(&mut out, "localbridge_frame_bytes", "Size of encoded frames.", &self.frame_bytes);
+        counter(&mut out, "localbridge_keyframes_total", "Keyframes encoded.", &self.keyframes);
+        counter(&mut out, "localbridge_dropped_frames_total", "Frames skipped by the encoder or not written to the track.", &self.dropped_frames);
+        counter(&mut out, "localbridge_input_events_total", "Input events injected.", &self.input_events);
+        gauge(&mut out, "localbridge_input_events_per_second", "Input events injected in the last second.", self.input_events_per_second.get());
+
+        let mut connected = 0;
+        let mut rtt = Vec::new();
+        let mut fraction_lost = Vec::new();
+        let mut packets_lost = Vec::new();
+        let mut bitrate = Vec::new();
+        for session in sessions.iter().filter(|s| s.is_connected()) {
+            connected += 1;
+            let stats = session.video_stats().await;
+            let id = &session.id;
+            if let Some(seconds) = stats.rtt_seconds {
+                rtt.push((id.clone(), seconds));
+            }
+            if let Some(fraction) = stats.fraction_lost {
+                fraction_lost.push((id.clone(), fraction));
+            }
+            if let Some(packets) = stats.packets_lost {
+                packets_lost.push((id.clone(), packets as f64));
+            }
+            if let Some(bps) = stats.bitrate_bps {
+                bitrate.push((id.clone(), bps));
+            }
+        }
+
+        gauge(&mut out, "localbridge_active_sessions", "Connected peers.", connected as f64);
+        optional_gauge(&mut out, "localbridge_peers_rtt_seconds_max", "Highest round-trip time reported by a peer over RTCP.", max_of(&rtt));
+        optional_gauge(&mut out, "localbridge_peers_fraction_lost_max", "Highest fraction of video packets lost, from the peers' last receiver reports.", max_of(&fraction_lost));
+        gauge(&mut out, "localbridge_peers_packets_lost", "Video packets lost, as reported by all peers.", sum_of(&packets_lost));
+        gauge(&mut out, "localbridge_peers_bitrate_bps", "Video bitrate sent to all peers, averaged since their statistics were last read.", sum_of(&bitrate));
+        if !per_peer {
+            return out;
+        }
+        peer_gauge(&mut out, "localbridge_peer_rtt_seconds", "Round-trip time reported by the peer over RTCP.", &rtt);
+        peer_gauge(&mut out, "localbridge_peer_fraction_lost", "Fraction of video packets lost, from the peer's last receiver report.", &fraction_lost);
+        peer_gauge(&mut out, "localbridge_peer_packets_lost", "Video packets lost, as reported by the peer.", &packets_lost);
+        peer_gauge(&mut out, "localbridge_peer_bitrate_bps", "Video bitrate sent to the peer, averaged since its statistics were last read.", &bitrate);
+        out
+    }
+}
+
+/// A value that only goes up.
+#[derive(Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// A value that is set from time to time; stored as the bits of an 'f64'.
+#[derive(Default)]
+pub struct Gauge(AtomicU64);
+
+impl Gauge {
+    pub fn set(&self, value: f64) {
+        self.0.store(value.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> f64 {
+        f64::from_bits(self.0.load(Ordering::Relaxed))
+    }
+}
+
+/// Counts observations into fixed buckets, like a Prometheus histogram.
+pub struct Histogram {
+    bounds: &'static [f64],
+    // One count per bound plus the '+Inf' bucket; not cumulative.
+    counts: Vec<AtomicU64>,
+    // Sum of all observations, as the bits of an 'f64'.
+    sum:    AtomicU64,
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            counts: (0..=bounds.len()).map(|_| AtomicU64::new(0)).collect(),
+            sum:    AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, value: f64) {
+        let bucket = self.bounds.iter().position(|&bound| value <= bound).unwrap_or(self.bounds.len());
+        self.counts[bucket].fetch_add(1, Ordering::Relaxed);
+        let _ = self.sum.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bits| {
+            Some((f64::from_bits(bits) + value).to_bits())
+        });
+    }
+
+    pub fn observe_duration(&self, duration: Duration) {
+        self.observe(duration.as_secs_f64());
+    }
+}
+
+fn header(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn counter(out: &mut String, name: &str, help: &str, counter: &Counter) {
+    header(out, name, help, "counter");
+    let _ = writeln!(out, "{name} {}", counter.get());
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    header(out, name, help, "gauge");
+    let _ = writeln!(out, "{name} {value}");
+}
+
+fn max_of(series: &[(String, f64)]) -> Option<f64> {
+    series.iter().map(|(_, value)| *value).reduce(f64::max)
+}
+
+fn sum_of(series: &[(String, f64)]) -> f64 {
+    series.iter().map(|(_, value)| *value).sum()
+}
+
+/// A gauge left out while there is no value (no peer reported one yet).
+fn optional_gauge(out: &mut String, name: &str, help: &str, value: Option<f64>) {
+    if let Some(value) = value {
+        gauge(out, name, help, value);
+    }
+}
+
+fn peer_gauge(out: &mut String, name: &str, help: &str, values: &[(String, f64)]) {
+    header(out, name, help, "gauge");
+    for (peer, value) in values {
+        let _ = writeln!(out, "{name}{{peer=\"{peer}\"}} {value}");
+    }
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    header(out, name, help, "histogram");
+    // Buckets are cumulative in the exposition format.
+    let mut count = 0;
+    for (i, bucket) in histogram.counts.iter().enumerate() {
+        count += bucket.load(Ordering::Relaxed);
+        match histogram.bounds.get(i) {
+            Some(bound) => { let _ = writeln!(out, "{name}_bucket{{le=\"{bound}\"}} {count}"); }
+            None        => { let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {count}"); }
+        }
+    }
+    let _ = writeln!(out, "{name}_sum {}", f64::from_bits(histogram.sum.load(Ordering::Relaxed)));
+    let _ = writeln!(out, "{name}_count {count}");
+}
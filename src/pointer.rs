@@ -0,0 +1,681 @@
+// Touch and pen injection. On Windows it goes through the synthetic pointer API
+// ('CreateSyntheticPointerDevice', Windows 10 1809+), on Linux through uinput
+// multitouch and tablet devices. Either way applications receive real touch and
+// pen input, so inking, pressure and multi-finger gestures work.
+
+pub use crate::events::{PointerContact, PointerKind, PointerPhase};
+#[cfg(windows)]
+pub use windows_backend::PointerInjector;
+#[cfg(target_os = "linux")]
+pub use uinput_backend::PointerInjector;
+
+/// Most simultaneous touch contacts we report; browsers rarely track more than ten.
+pub const MAX_TOUCH_CONTACTS: u32 = 10;
+
+/// Where a browser pointer was assigned on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PointerSlot {
+    // Index into the touch device's contacts.
+    Touch(u32),
+    Pen,
+}
+
+/// Windows backend: applications get these as WM_POINTER input.
+#[cfg(windows)]
+mod windows_backend {
+    use std::{collections::BTreeMap, ffi::c_void};
+    use tracing::{info, warn};
+    use crate::display::DisplayGeometry;
+    use super::{PointerContact, PointerPhase, MAX_TOUCH_CONTACTS};
+
+    #[derive(Clone, Copy)]
+    struct TouchState {
+        location: (i32, i32),
+        contact:  PointerContact,
+    }
+
+    /// 'PointerInjector' owns the synthetic touch and pen devices. Both are created on
+    /// first use; if the host's Windows is too old, touch and pen input are dropped.
+    pub struct PointerInjector {
+        touch:       Device,
+        pen:         Device,
+        // Touch contacts currently down, by slot. Every injected touch frame has to
+        // report all of them, or Windows treats the missing ones as lifted.
+        contacts:    BTreeMap<u32, TouchState>,
+        pen_contact: bool,
+        pen_last:    Option<((i32, i32), PointerContact)>,
+    }
+
+    impl PointerInjector {
+        pub fn new() -> Self {
+            Self {
+                touch:       Device::new(PT_TOUCH, MAX_TOUCH_CONTACTS, "touch"),
+                pen:         Device::new(PT_PEN, 1, "pen"),
+                contacts:    BTreeMap::new(),
+                pen_contact: false,
+                pen_last:    None,
+            }
+        }
+
+        /// Puts a new finger down and returns the slot it was assigned,
+        /// or 'None' if all slots are taken or touch injection is unavailable.
+        pub fn touch_down(&mut self, geometry: &DisplayGeometry, contact: PointerContact) -> Option<u32> {
+            let location = geometry.to_desktop(contact.x, contact.y)?;
+            let Some(slot) = (0..MAX_TOUCH_CONTACTS).find(|s| !self.contacts.contains_key(s)) else {
+                warn!("Touch contact dropped: all {MAX_TOUCH_CONTACTS} contacts are in use");
+                return None;
+            };
+            self.contacts.insert(slot, TouchState { location, contact });
+            if !self.inject_touch(slot, POINTER_FLAG_DOWN | POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT, geometry) {
+                self.contacts.remove(&slot);
+                return None;
+            }
+            Some(slot)
+        }
+
+        pub fn touch_move(&mut self, slot: u32, geometry: &DisplayGeometry, contact: PointerContact) {
+            let Some(location) = geometry.to_desktop(contact.x, contact.y) else { return };
+            if let Some(state) = self.contacts.get_mut(&slot) {
+                *state = TouchState { location, contact };
+                self.inject_touch(slot, POINTER_FLAG_UPDATE | POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT, geometry);
+            }
+        }
+
+        /// Lifts a finger. A cancelled contact tells applications to undo the gesture.
+        pub fn touch_up(&mut self, slot: u32, geometry: &DisplayGeometry, cancel: bool) {
+            if !self.contacts.contains_key(&slot) {
+                return;
+            }
+            let flags = if cancel { POINTER_FLAG_UP | POINTER_FLAG_CANCELED } else { POINTER_FLAG_UP };
+            self.inject_touch(slot, flags, geometry);
+            self.contacts.remove(&slot);
+        }
+
+        /// Reports one touch frame: 'slot' with 'flags', every other contact unchanged.
+        fn inject_touch(&mut self, slot: u32, flags: u32, geometry: &DisplayGeometry) -> bool {
+            let frame: Vec<PointerTypeInfo> = self.contacts.iter()
+                .map(|(&id, state)| {
+                    let flags = if id == slot {
+                        flags
+                    } else {
+                        POINTER_FLAG_UPDATE | POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT
+                    };
+                    touch_info(id, flags, state, geometry)
+                })
+                .collect();
+            self.touch.inject(&frame)
+        }
+
+        /// Moves, presses or lifts the pen. Moves without a preceding 'Down' are hover.
+        pub fn pen(&mut self, phase: PointerPhase, geometry: &DisplayGeometry, contact: PointerContact) {
+            // Lifting has to happen even if the final position is unusable.
+            let location = geometry.to_desktop(contact.x, contact.y)
+                .or_else(|| self.pen_last.map(|(location, _)| location));
+            let Some(location) = location else { return };
+
+            let (flags, change) = match phase {
+                PointerPhase::Down => {
+                    self.pen_contact = true;
+                    (POINTER_FLAG_DOWN | POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_FIRSTBUTTON,
+                     POINTER_CHANGE_FIRSTBUTTON_DOWN)
+                }
+                PointerPhase::Move if self.pen_contact => {
+                    (POINTER_FLAG_UPDATE | POINTER_FLAG_INRANGE | POINTER_FLAG_INCONTACT | POINTER_FLAG_FIRSTBUTTON,
+                     POINTER_CHANGE_NONE)
+                }
+                PointerPhase::Move => (POINTER_FLAG_UPDATE | POINTER_FLAG_INRANGE, POINTER_CHANGE_NONE),
+                PointerPhase::Up => {
+                    self.pen_contact = false;
+                    (POINTER_FLAG_UP | POINTER_FLAG_INRANGE, POINTER_CHANGE_FIRSTBUTTON_UP)
+                }
+                PointerPhase::Cancel | PointerPhase::Leave => {
+                    let was_down = std::mem::take(&mut self.pen_contact);
+                    let mut flags = POINTER_FLAG_UPDATE;
+                    if was_down {
+                        flags = POINTER_FLAG_UP;
+                        if phase == PointerPhase::Cancel {
+                            flags |= POINTER_FLAG_CANCELED;
+                        }
+                    }
+                    (flags, if was_down { POINTER_CHANGE_FIRSTBUTTON_UP } else { POINTER_CHANGE_NONE })
+                }
+            };
+
+            let in_range = flags & POINTER_FLAG_INRANGE != 0;
+            self.pen_last = in_range.then_some((location, contact));
+            self.pen.inject(&[pen_info(flags, change, location, &contact)]);
+        }
+
+        /// Ends the pen contact (if any) and takes the pen out of range.
+        pub fn pen_release(&mut self, geometry: &DisplayGeometry) {
+            if let Some((_, contact)) = self.pen_last {
+                self.pen(PointerPhase::Cancel, geometry, contact);
+            }
+        }
+    }
+
+    /// A synthetic pointer device, created on first use.
+    struct Device {
+        kind:      u32,
+        max_count: u32,
+        name:      &'static str,
+        handle:    Option<*mut c_void>,
+        failed:    bool,
+    }
+
+    impl Device {
+        fn new(kind: u32, max_count: u32, name: &'static str) -> Self {
+            Self { kind, max_count, name, handle: None, failed: false }
+        }
+
+        fn inject(&mut self, frame: &[PointerTypeInfo]) -> bool {
+            if self.handle.is_none() && !self.failed {
+                let handle = unsafe { CreateSyntheticPointerDevice(self.kind, self.max_count, POINTER_FEEDBACK_DEFAULT) };
+                if handle.is_null() {
+                    // Only log once; without the device every later event would fail the same way.
+                    warn!("CreateSyntheticPointerDevice ({}) failed; {} input needs Windows 10 1809 or newer", self.name, self.name);
+                    self.failed = true;
+                } else {
+                    info!("Created synthetic {} device", self.name);
+                    self.handle = Some(handle);
+                }
+            }
+            let Some(handle) = self.handle else { return false };
+            if unsafe { InjectSyntheticPointerInput(handle, frame.as_ptr(), frame.len() as u32) } == 0 {
+                warn!("InjectSyntheticPointerInput ({}) failed", self.name);
+                return false;
+            }
+            true
+        }
+    }
+
+    impl Drop for Device {
+        fn drop(&mut self) {
+            if let Some(handle) = self.handle.take() {
+                unsafe { DestroySyntheticPointerDevice(handle) };
+            }
+        }
+    }
+
+    fn pointer_info(kind: u32, id: u32, flags: u32, location: (i32, i32), change: i32) -> PointerInfo {
+        PointerInfo {
+            pointer_type:             kind,
+            pointer_id:               id,
+            frame_id:                 0,
+            pointer_flags:            flags,
+            source_device:            std::ptr::null_mut(),
+            hwnd_target:              std::ptr::null_mut(),
+            pt_pixel_location:        Point { x: location.0, y: location.1 },
+            pt_himetric_location:     Point::default(),
+            pt_pixel_location_raw:    Point { x: location.0, y: location.1 },
+            pt_himetric_location_raw: Point::default(),
+            time:                     0,
+            history_count:            0,
+            input_data:               0,
+            key_states:               0,
+            performance_count:        0,
+            button_change_type:       change,
+        }
+    }
+
+    fn touch_info(slot: u32, flags: u32, state: &TouchState, geometry: &DisplayGeometry) -> PointerTypeInfo {
+        let contact = &state.contact;
+        let (x, y) = state.location;
+        // Without a reported size, Windows still wants a small contact rectangle.
+        let half_w = ((contact.width * geometry.width as f64) / 2.0).clamp(2.0, 100.0) as i32;
+        let half_h = ((contact.height * geometry.height as f64) / 2.0).clamp(2.0, 100.0) as i32;
+        let area = Rect { left: x - half_w, top: y - half_h, right: x + half_w, bottom: y + half_h };
+        PointerTypeInfo {
+            kind: PT_TOUCH,
+            u: PointerTypeUnion {
+                touch: TouchInfo {
+                    pointer_info:    pointer_info(PT_TOUCH, slot, flags, state.location, POINTER_CHANGE_NONE),
+                    touch_flags:     0,
+                    touch_mask:      TOUCH_MASK_CONTACTAREA | TOUCH_MASK_ORIENTATION | TOUCH_MASK_PRESSURE,
+                    rc_contact:      area,
+                    rc_contact_raw:  area,
+                    orientation:     contact.twist.clamp(0.0, 359.0) as u32,
+                    pressure:        scale_pressure(contact.pressure),
+                },
+            },
+        }
+    }
+
+    fn pen_info(flags: u32, change: i32, location: (i32, i32), contact: &PointerContact) -> PointerTypeInfo {
+        let mut pen_flags = 0;
+        if contact.barrel {
+            pen_flags |= PEN_FLAG_BARREL;
+        }
+        if contact.eraser {
+            pen_flags |= PEN_FLAG_INVERTED;
+            if flags & POINTER_FLAG_INCONTACT != 0 {
+                pen_flags |= PEN_FLAG_ERASER;
+            }
+        }
+        let pressure = if flags & POINTER_FLAG_INCONTACT != 0 { scale_pressure(contact.pressure) } else { 0 };
+        PointerTypeInfo {
+            kind: PT_PEN,
+            u: PointerTypeUnion {
+                pen: PenInfo {
+                    pointer_info: pointer_info(PT_PEN, 0, flags, location, change),
+                    pen_flags,
+                    pen_mask:     PEN_MASK_PRESSURE | PEN_MASK_ROTATION | PEN_MASK_TILT_X | PEN_MASK_TILT_Y,
+                    pressure,
+                    rotation:     contact.twist.clamp(0.0, 359.0) as u32,
+                    tilt_x:       contact.tilt_x.clamp(-90.0, 90.0) as i32,
+                    tilt_y:       contact.tilt_y.clamp(-90.0, 90.0) as i32,
+                },
+            },
+        }
+    }
+
+    /// Browser pressure (0.0 - 1.0) to the Windows range (0 - 1024).
+    fn scale_pressure(pressure: f64) -> u32 {
+        if !pressure.is_finite() {
+            return 512;
+        }
+        (pressure.clamp(0.0, 1.0) * 1024.0).round() as u32
+    }
+
+    // Win32 definitions (WinUser.h).
+    const PT_TOUCH: u32 = 2;
+    const PT_PEN:   u32 = 3;
+
+    const POINTER_FEEDBACK_DEFAULT: u32 = 1;
+
+    const POINTER_FLAG_INRANGE:     u32 = 0x0000_0002;
+    const POINTER_FLAG_INCONTACT:   u32 = 0x0000_0004;
+    const POINTER_FLAG_FIRSTBUTTON: u32 = 0x0000_0010;
+    const POINTER_FLAG_CANCELED:    u32 = 0x0000_8000;
+    const POINTER_FLAG_DOWN:        u32 = 0x0001_0000;
+    const POINTER_FLAG_UPDATE:      u32 = 0x0002_0000;
+    const POINTER_FLAG_UP:          u32 = 0x0004_0000;
+
+    const POINTER_CHANGE_NONE:             i32 = 0;
+    const POINTER_CHANGE_FIRSTBUTTON_DOWN: i32 = 1;
+    const POINTER_CHANGE_FIRSTBUTTON_UP:   i32 = 2;
+
+    const TOUCH_MASK_CONTACTAREA: u32 = 0x1;
+    const TOUCH_MASK_ORIENTATION: u32 = 0x2;
+    const TOUCH_MASK_PRESSURE:    u32 = 0x4;
+
+    const PEN_FLAG_BARREL:   u32 = 0x1;
+    const PEN_FLAG_INVERTED: u32 = 0x2;
+    const PEN_FLAG_ERASER:   u32 = 0x4;
+
+    const PEN_MASK_PRESSURE: u32 = 0x1;
+    const PEN_MASK_ROTATION: u32 = 0x2;
+    const PEN_MASK_TILT_X:   u32 = 0x4;
+    const PEN_MASK_TILT_Y:   u32 = 0x8;
+
+    #[repr(C)]
+    #[derive(Clone, Copy, Default)]
+    struct Point {
+        x: i32,
+        y: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Rect {
+        left:   i32,
+        top:    i32,
+        right:  i32,
+        bottom: i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct PointerInfo {
+        pointer_type:             u32,
+        pointer_id:               u32,
+        frame_id:                 u32,
+        pointer_flags:            u32,
+        source_device:            *mut c_void,
+        hwnd_target:              *mut c_void,
+        pt_pixel_location:        Point,
+        pt_himetric_location:     Point,
+        pt_pixel_location_raw:    Point,
+        pt_himetric_location_raw: Point,
+        time:                     u32,
+        history_count:            u32,
+        input_data:               i32,
+        key_states:               u32,
+        performance_count:        u64,
+        button_change_type:       i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct TouchInfo {
+        pointer_info:   PointerInfo,
+        touch_flags:    u32,
+        touch_mask:     u32,
+        rc_contact:     Rect,
+        rc_contact_raw: Rect,
+        orientation:    u32,
+        pressure:       u32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct PenInfo {
+        pointer_info: PointerInfo,
+        pen_flags:    u32,
+        pen_mask:     u32,
+        pressure:     u32,
+        rotation:     u32,
+        tilt_x:       i32,
+        tilt_y:       i32,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    union PointerTypeUnion {
+        touch: TouchInfo,
+        pen:   PenInfo,
+    }
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct PointerTypeInfo {
+        kind: u32,
+        u:    PointerTypeUnion,
+    }
+
+    extern "system" {
+        fn CreateSyntheticPointerDevice(pointer_type: u32, max_count: u32, mode: u32) -> *mut c_void;
+        fn InjectSyntheticPointerInput(device: *mut c_void, info: *const PointerTypeInfo, count: u32) -> i32;
+        fn DestroySyntheticPointerDevice(device: *mut c_void);
+    }
+}
+
+/// Linux backend: a multitouch screen (protocol B, one slot per contact) and a pen
+/// tablet, both direct-input uinput devices covering the captured monitor, so
+/// positions map exactly like the mouse's. On hosts with several monitors the
+/// compositor may still need to be told which output they belong to.
+#[cfg(target_os = "linux")]
+mod uinput_backend {
+    use std::collections::BTreeMap;
+    use anyhow::Result;
+    use tracing::warn;
+    use crate::{
+        display::DisplayGeometry,
+        uinput::{Device, DeviceBuilder, BUS_VIRTUAL, EV_ABS, EV_KEY, INPUT_PROP_DIRECT},
+    };
+    use super::{PointerContact, PointerPhase, MAX_TOUCH_CONTACTS};
+
+    const MAX_PRESSURE: i32 = 1024;
+    // Devices units are desktop pixels; assume 96 DPI (about 4 per mm). Tablets
+    // without a resolution are ignored by libinput.
+    const PIXELS_PER_MM: i32 = 4;
+    // Tilt is in degrees; the resolution of angles is in units per radian.
+    const DEGREES_PER_RADIAN: i32 = 57;
+
+    /// A uinput device for the captured monitor, created on first use.
+    struct Surface {
+        name:   &'static str,
+        build:  fn(DeviceBuilder, &DisplayGeometry) -> Result<DeviceBuilder>,
+        device: Option<(Device, DisplayGeometry)>,
+        failed: bool,
+    }
+
+    impl Surface {
+        /// The device for 'geometry'. One made for another monitor is only replaced
+        /// while 'idle', so a contact never moves between devices.
+        fn get(&mut self, geometry: &DisplayGeometry, idle: bool) -> Option<&Device> {
+            let area = |g: &DisplayGeometry| (g.x, g.y, g.width, g.height);
+            if idle && self.device.as_ref().is_some_and(|(_, made_for)| area(made_for) != area(geometry)) {
+                self.device = None;
+            }
+            if self.device.is_none() && !self.failed {
+                let device = DeviceBuilder::new(self.name)
+                    .and_then(|builder| (self.build)(builder, geometry))
+                    .and_then(|builder| builder.create(BUS_VIRTUAL, 0, 0));
+                match device {
+                    Ok(device) => self.device = Some((device, *geometry)),
+                    Err(e) => {
+                        // Only log once; without the device every later event would fail the same way.
+                        warn!("Could not create the uinput device '{}', its input is dropped: {e:#}", self.name);
+                        self.failed = true;
+                    }
+                }
+            }
+            self.device.as_ref().map(|(device, _)| device)
+        }
+    }
+
+    /// 'PointerInjector' owns the touch and pen devices.
+    pub struct PointerInjector {
+        touch:            Surface,
+        pen:              Surface,
+        // Tracking ID of each touch contact currently down, by slot.
+        contacts:         BTreeMap<u32, i32>,
+        next_tracking_id: i32,
+        // 'BTN_TOOL_PEN' or 'BTN_TOOL_RUBBER' while the pen is in range.
+        pen_tool:         Option<u16>,
+        pen_contact:      bool,
+        pen_last:         Option<((i32, i32), PointerContact)>,
+    }
+
+    impl PointerInjector {
+        pub fn new() -> Self {
+            Self {
+                touch:            Surface { name: "LocalBridge touch", build: touch_device, device: None, failed: false },
+                pen:              Surface { name: "LocalBridge pen", build: pen_device, device: None, failed: false },
+                contacts:         BTreeMap::new(),
+                next_tracking_id: 0,
+                pen_tool:         None,
+                pen_contact:      false,
+                pen_last:         None,
+            }
+        }
+
+        /// Puts a new finger down and returns the slot it was assigned,
+        /// or 'None' if all slots are taken or touch injection is unavailable.
+        pub fn touch_down(&mut self, geometry: &DisplayGeometry, contact: PointerContact) -> Option<u32> {
+            let location = geometry.to_desktop(contact.x, contact.y)?;
+            let Some(slot) = (0..MAX_TOUCH_CONTACTS).find(|s| !self.contacts.contains_key(s)) else {
+                warn!("Touch contact dropped: all {MAX_TOUCH_CONTACTS} contacts are in use");
+                return None;
+            };
+            let first = self.contacts.is_empty();
+            let device = self.touch.get(geometry, first)?;
+            let tracking_id = self.next_tracking_id;
+            self.next_tracking_id = (tracking_id + 1) & 0xFFFF;
+
+            let mut events = vec![(EV_ABS, ABS_MT_SLOT, slot as i32), (EV_ABS, ABS_MT_TRACKING_ID, tracking_id)];
+            events.extend(touch_events(location, &contact, geometry));
+            if first {
+                // Single-touch emulation follows the first finger.
+                events.extend([(EV_KEY, BTN_TOUCH, 1), (EV_ABS, ABS_X, location.0), (EV_ABS, ABS_Y, location.1)]);
+            }
+            if let Err(e) = device.emit(&events) {
+                warn!("{e:#}");
+                return None;
+            }
+            self.contacts.insert(slot, tracking_id);
+            Some(slot)
+        }
+
+        pub fn touch_move(&mut self, slot: u32, geometry: &DisplayGeometry, contact: PointerContact) {
+            let Some(location) = geometry.to_desktop(contact.x, contact.y) else { return };
+            if !self.contacts.contains_key(&slot) {
+                return;
+            }
+            let primary = self.contacts.keys().next() == Some(&slot);
+            let Some(device) = self.touch.get(geometry, false) else { return };
+            let mut events = vec![(EV_ABS, ABS_MT_SLOT, slot as i32)];
+            events.extend(touch_events(location, &contact, geometry));
+            if primary {
+                events.extend([(EV_ABS, ABS_X, location.0), (EV_ABS, ABS_Y, location.1)]);
+            }
+            if let Err(e) = device.emit(&events) {
+                warn!("{e:#}");
+            }
+        }
+
+        /// Lifts a finger. Linux has no cancelled contacts, so a cancel is a lift too.
+        pub fn touch_up(&mut self, slot: u32, geometry: &DisplayGeometry, _cancel: bool) {
+            if self.contacts.remove(&slot).is_none() {
+                return;
+            }
+            let last = self.contacts.is_empty();
+            let Some(device) = self.touch.get(geometry, false) else { return };
+            let mut events = vec![(EV_ABS, ABS_MT_SLOT, slot as i32), (EV_ABS, ABS_MT_TRACKING_ID, -1)];
+            if last {
+                events.push((EV_KEY, BTN_TOUCH, 0));
+            }
+            if let Err(e) = device.emit(&events) {
+                warn!("{e:#}");
+            }
+        }
+
+        /// Moves, presses or lifts the pen. Moves without a preceding 'Down' are hover.
+        pub fn pen(&mut self, phase: PointerPhase, geometry: &DisplayGeometry, contact: PointerContact) {
+            // Lifting has to happen even if the final position is unusable.
+            let location = geometry.to_desktop(contact.x, contact.y)
+                .or_else(|| self.pen_last.map(|(location, _)| location));
+            let Some(location) = location else { return };
+            let Some(device) = self.pen.get(geometry, self.pen_tool.is_none()) else { return };
+
+            // Turning the pen around while in range switches tools: the old one leaves first.
+            let tool = if contact.eraser { BTN_TOOL_RUBBER } else { BTN_TOOL_PEN };
+            if let Some(old) = self.pen_tool.filter(|&old| old != tool) {
+                let mut events = Vec::new();
+                if std::mem::take(&mut self.pen_contact) {
+                    events.extend([(EV_KEY, BTN_TOUCH, 0), (EV_ABS, ABS_PRESSURE, 0)]);
+                }
+                events.push((EV_KEY, old, 0));
+                let _ = device.emit(&events);
+                self.pen_tool = None;
+            }
+
+            let position = [
+                (EV_ABS, ABS_X, location.0),
+                (EV_ABS, ABS_Y, location.1),
+                (EV_ABS, ABS_TILT_X, contact.tilt_x.clamp(-90.0, 90.0) as i32),
+                (EV_ABS, ABS_TILT_Y, contact.tilt_y.clamp(-90.0, 90.0) as i32),
+                (EV_KEY, BTN_STYLUS, contact.barrel as i32),
+            ];
+            let mut events = Vec::new();
+            match phase {
+                PointerPhase::Down | PointerPhase::Move => {
+                    if phase == PointerPhase::Down {
+                        self.pen_contact = true;
+                    }
+                    let pressure = if self.pen_contact { scale_pressure(contact.pressure) } else { 0 };
+                    events.push((EV_KEY, tool, 1));
+                    events.extend(position);
+                    events.push((EV_ABS, ABS_PRESSURE, pressure));
+                    events.push((EV_KEY, BTN_TOUCH, self.pen_contact as i32));
+                    self.pen_tool = Some(tool);
+                }
+                PointerPhase::Up => {
+                    self.pen_contact = false;
+                    events.extend(position);
+                    events.extend([(EV_ABS, ABS_PRESSURE, 0), (EV_KEY, BTN_TOUCH, 0)]);
+                }
+                PointerPhase::Cancel | PointerPhase::Leave => {
+                    if std::mem::take(&mut self.pen_contact) {
+                        events.extend([(EV_ABS, ABS_PRESSURE, 0), (EV_KEY, BTN_TOUCH, 0)]);
+                    }
+                    events.push((EV_KEY, BTN_STYLUS, 0));
+                    if let Some(tool) = self.pen_tool.take() {
+                        events.push((EV_KEY, tool, 0));
+                    }
+                }
+            }
+
+            self.pen_last = self.pen_tool.is_some().then_some((location, contact));
+            if let Err(e) = device.emit(&events) {
+                warn!("{e:#}");
+            }
+        }
+
+        /// Ends the pen contact (if any) and takes the pen out of range.
+        pub fn pen_release(&mut self, geometry: &DisplayGeometry) {
+            if let Some((_, contact)) = self.pen_last {
+                self.pen(PointerPhase::Cancel, geometry, contact);
+            }
+        }
+    }
+
+    fn touch_events(location: (i32, i32), contact: &PointerContact, geometry: &DisplayGeometry) -> [(u16, u16, i32); 5] {
+        // Without a reported size, report a small contact like the Windows backend.
+        let major = (contact.width * geometry.width as f64).max(contact.height * geometry.height as f64).max(4.0);
+        let minor = (contact.width * geometry.width as f64).min(contact.height * geometry.height as f64).max(4.0);
+        [
+            (EV_ABS, ABS_MT_POSITION_X, location.0),
+            (EV_ABS, ABS_MT_POSITION_Y, location.1),
+            (EV_ABS, ABS_MT_PRESSURE, scale_pressure(contact.pressure)),
+            (EV_ABS, ABS_MT_TOUCH_MAJOR, major.min(200.0) as i32),
+            (EV_ABS, ABS_MT_TOUCH_MINOR, minor.min(200.0) as i32),
+        ]
+    }
+
+    /// Browser pressure (0.0 - 1.0) to the device range (0 - 1024).
+    fn scale_pressure(pressure: f64) -> i32 {
+        if !pressure.is_finite() {
+            return MAX_PRESSURE / 2;
+        }
+        (pressure.clamp(0.0, 1.0) * MAX_PRESSURE as f64).round() as i32
+    }
+
+    /// The monitor's rectangle in desktop pixels: (left, top, right, bottom).
+    fn bounds(geometry: &DisplayGeometry) -> (i32, i32, i32, i32) {
+        (
+            geometry.x,
+            geometry.y,
+            geometry.x + geometry.width.max(1) as i32 - 1,
+            geometry.y + geometry.height.max(1) as i32 - 1,
+        )
+    }
+
+    fn touch_device(builder: DeviceBuilder, geometry: &DisplayGeometry) -> Result<DeviceBuilder> {
+        let (left, top, right, bottom) = bounds(geometry);
+        builder
+            .property(INPUT_PROP_DIRECT)?
+            .keys(&[BTN_TOUCH])?
+            .absolute(ABS_X, left, right, PIXELS_PER_MM)?
+            .absolute(ABS_Y, top, bottom, PIXELS_PER_MM)?
+            .absolute(ABS_MT_SLOT, 0, MAX_TOUCH_CONTACTS as i32 - 1, 0)?
+            .absolute(ABS_MT_TRACKING_ID, 0, 0xFFFF, 0)?
+            .absolute(ABS_MT_POSITION_X, left, right, PIXELS_PER_MM)?
+            .absolute(ABS_MT_POSITION_Y, top, bottom, PIXELS_PER_MM)?
+            .absolute(ABS_MT_PRESSURE, 0, MAX_PRESSURE, 0)?
+            .absolute(ABS_MT_TOUCH_MAJOR, 0, 200, PIXELS_PER_MM)?
+            .absolute(ABS_MT_TOUCH_MINOR, 0, 200, PIXELS_PER_MM)
+    }
+
+    fn pen_device(builder: DeviceBuilder, geometry: &DisplayGeometry) -> Result<DeviceBuilder> {
+        let (left, top, right, bottom) = bounds(geometry);
+        builder
+            .property(INPUT_PROP_DIRECT)?
+            .keys(&[BTN_TOOL_PEN, BTN_TOOL_RUBBER, BTN_TOUCH, BTN_STYLUS])?
+            .absolute(ABS_X, left, right, PIXELS_PER_MM)?
+            .absolute(ABS_Y, top, bottom, PIXELS_PER_MM)?
+            .absolute(ABS_PRESSURE, 0, MAX_PRESSURE, 0)?
+            .absolute(ABS_TILT_X, -90, 90, DEGREES_PER_RADIAN)?
+            .absolute(ABS_TILT_Y, -90, 90, DEGREES_PER_RADIAN)
+    }
+
+    // linux/input-event-codes.h
+    const BTN_TOOL_PEN:    u16 = 0x140;
+    const BTN_TOOL_RUBBER: u16 = 0x141;
+    const BTN_TOUCH:       u16 = 0x14a;
+    const BTN_STYLUS:      u16 = 0x14b;
+
+    const ABS_X:              u16 = 0x00;
+    const ABS_Y:              u16 = 0x01;
+    const ABS_PRESSURE:       u16 = 0x18;
+    const ABS_TILT_X:         u16 = 0x1a;
+    const ABS_TILT_Y:         u16 = 0x1b;
+    const ABS_MT_SLOT:        u16 = 0x2f;
+    const ABS_MT_TOUCH_MAJOR: u16 = 0x30;
+    const ABS_MT_TOUCH_MINOR: u16 = 0x31;
+    const ABS_MT_POSITION_X:  u16 = 0x35;
+    const ABS_MT_POSITION_Y:  u16 = 0x36;
+    const ABS_MT_TRACKING_ID: u16 = 0x39;
+    const ABS_MT_PRESSURE:    u16 = 0x3a;
+}
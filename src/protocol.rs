@@ -0,0 +1,377 @@
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    broadcast::{self, error::RecvError},
+    watch,
+};
+use tracing::{info, warn};
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::{
+    clipboard::{self, ClipboardHandle},
+    cursor::{CursorFeed, CursorMode, CursorShape},
+    display::DisplayGeometry,
+    encoder::EncoderSettings,
+    input::{InputEvent, InputSession},
+    latency,
+    transfer::{self, TransferConfig},
+};
+
+// The control protocol spoken on the "control" data channel.
+// The client opens it next to the other channels and starts with 'hello'; the host
+// answers with its own 'hello' and from then on uses the channel for everything it
+// has to tell the client. The types in this file (and the message types of the
+// other channels) are the protocol: the JSON schema served at
+// '/protocol/schema.json' and printed by '--print-schema' is generated from them.
+
+/// Bumped whenever a message changes in a way older clients can't handle.
+pub const PROTOCOL_VERSION: u32 = 1;
+/// Oldest client protocol version the host still speaks.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// 'Feature' names an optional part of the protocol. Both sides list what they
+/// support in 'hello'; a feature is used only if both list it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Feature {
+    // Pointer lock with 'mouse_move_relative'.
+    RelativeMouse,
+    // Mouse buttons past left/middle/right.
+    ExtraButtons,
+    TextInput,
+    Touch,
+    Pen,
+    Gamepad,
+    // The "clipboard" data channel.
+    Clipboard,
+    // The "files" data channel.
+    FileTransfer,
+    // Input events in the compact binary encoding of wire.rs.
+    BinaryInput,
+    // The cursor is left out of the video and drawn by the client.
+    ClientCursor,
+}
+
+impl Feature {
+    /// Parses a feature name sent by the client. Names from newer clients that this
+    /// host doesn't know are skipped rather than rejected.
+    fn parse(name: &str) -> Option<Self> {
+        serde_json::from_value(serde_json::Value::String(name.to_owned())).ok()
+    }
+}
+
+/// 'ClientMessage' is what the client sends on the control channel.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ClientMessage {
+    // Must be the first message. 'features' are 'Feature' names.
+    Hello {
+        version: u32,
+        #[serde(default)]
+        features: Vec<String>,
+    },
+    // Clock sync: answered right away with 'pong'. 'client_time' is echoed back.
+    // Sent every 2 s, which also keeps the session's held keys and buttons held.
+    Ping { id: u32, client_time: f64 },
+    // Average latency per stage over the last 'frames' displayed frames, in ms,
+    // measured with the timestamps in the video's SEI messages.
+    LatencyReport {
+        frames:     u32,
+        encode_ms:  f64,
+        network_ms: f64,
+        display_ms: f64,
+        total_ms:   f64,
+    },
+}
+
+/// 'HostMessage' is everything the host sends to the client on the control channel.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum HostMessage {
+    // Reply to the client's 'hello': the version both sides will speak, the features
+    // both support and the current display.
+    Hello { version: u32, features: Vec<Feature>, display: DisplayGeometry },
+    // Something the client sent was rejected.
+    Error { code: ErrorCode, message: String },
+    // Reply to 'ping'; 'host_time' is the host clock in ms, the clock of the
+    // capture times in the video's SEI messages.
+    Pong { id: u32, client_time: f64, host_time: f64 },
+    DisplayChanged(DisplayGeometry),
+    // The host cursor changed shape or was hidden.
+    Cursor(CursorShape),
+    // Where the host cursor is, normalized over the video like input positions.
+    // Outside 0.0 - 1.0 while it is on another monitor. Only with 'client_cursor'.
+    CursorPosition { x: f64, y: f64 },
+    // The video encoder was (re)configured.
+    EncoderSettings(EncoderSettings),
+    // A message for the user, e.g. that no controller slot was free.
+    Notice { level: NoticeLevel, message: String },
+    // The host is about to close the connection; the client shouldn't reconnect
+    // automatically.
+    Disconnect { reason: DisconnectReason, message: String },
+    // A game set the rumble motors of the session's gamepad 'index' (0.0 - 1.0).
+    Rumble { index: u8, strong: f64, weak: f64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum NoticeLevel {
+    Info,
+    Warning,
+}
+
+/// 'DisconnectReason' says why the host ended a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum DisconnectReason {
+    // The host process is exiting.
+    HostShutdown,
+    // An administrator ended the session.
+    Kicked,
+}
+
+/// 'ErrorCode' tells the client why a message was rejected, without parsing 'message'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCode {
+    // The client's protocol version is outside what the host speaks.
+    UnsupportedVersion,
+    // The message isn't valid JSON or doesn't match any message type.
+    MalformedMessage,
+    // The message was well-formed but its values were rejected (e.g. a NaN position).
+    InvalidInput,
+    // The session is view-only and the message would have changed the host
+    // (clipboard writes, file transfers).
+    ViewOnly,
+}
+
+impl HostMessage {
+    pub fn error(code: ErrorCode, message: impl Into<String>) -> Self {
+        HostMessage::Error { code, message: message.into() }
+    }
+
+    pub fn notice(level: NoticeLevel, message: impl Into<String>) -> Self {
+        HostMessage::Notice { level, message: message.into() }
+    }
+}
+
+/// 'HostFeeds' is the host-wide state every control channel reports to its client.
+#[derive(Clone)]
+pub struct HostFeeds {
+    pub cursor:    CursorFeed,
+    pub encoder:   watch::Receiver<EncoderSettings>,
+    // Messages for every connected client (notices, disconnects).
+    pub broadcast: broadcast::Sender<HostMessage>,
+}
+
+/// The features this host offers, given its configuration.
+pub fn host_features(
+    clipboard: &ClipboardHandle,
+    transfer:  &TransferConfig,
+    cursor:    CursorMode,
+) -> Vec<Feature> {
+    let mut features = vec![
+        Feature::RelativeMouse,
+        Feature::ExtraButtons,
+        Feature::TextInput,
+        Feature::Touch,
+        Feature::Pen,
+        Feature::Gamepad,
+        Feature::BinaryInput,
+    ];
+    if clipboard.is_enabled() {
+        features.push(Feature::Clipboard);
+    }
+    if transfer.is_enabled() {
+        features.push(Feature::FileTransfer);
+    }
+    if cursor == CursorMode::Client {
+        features.push(Feature::ClientCursor);
+    }
+    features
+}
+
+/// The JSON schema of every data channel message, generated from the Rust types.
+pub fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "version": PROTOCOL_VERSION,
+        "min_version": MIN_PROTOCOL_VERSION,
+        "channels": {
+            "control": {
+                "client": schema_for!(ClientMessage),
+                "host":   schema_for!(HostMessage),
+            },
+            "input":     schema_for!(InputEvent),
+            "clipboard": clipboard::schema(),
+            "files":     transfer::schema(),
+        },
+    })
+}
+
+/// Sets up the handler for the "control" data channel of one session.
+pub async fn handle_data_channel(
+    dc:       Arc<RTCDataChannel>,
+    session:  Arc<InputSession>,
+    features: Arc<[Feature]>,
+    feeds:    HostFeeds,
+) {
+    let greeted = Arc::new(AtomicBool::new(false));
+    dc.on_message(Box::new(move |msg| {
+        let dc = Arc::clone(&dc);
+        let session = Arc::clone(&session);
+        let features = Arc::clone(&features);
+        let feeds = feeds.clone();
+        let greeted = Arc::clone(&greeted);
+        Box::pin(async move {
+            // Any control message, even a malformed one, shows the client is still there.
+            session.keepalive();
+            let message = std::str::from_utf8(&msg.data)
+                .map_err(|e| e.to_string())
+                .and_then(|text| serde_json::from_str::<ClientMessage>(text).map_err(|e| e.to_string()));
+            let reply = match message {
+                Ok(ClientMessage::Hello { version, features: client_features }) => {
+                    if greeted.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    if version < MIN_PROTOCOL_VERSION {
+                        warn!("Client speaks protocol version {version}, host needs at least {MIN_PROTOCOL_VERSION}");
+                        HostMessage::error(
+                            ErrorCode::UnsupportedVersion,
+                            format!("protocol version {version} is not supported (host speaks {MIN_PROTOCOL_VERSION} to {PROTOCOL_VERSION})"),
+                        )
+                    } else {
+                        // A newer client talks down to the host's version.
+                        let version = version.min(PROTOCOL_VERSION);
+                        let client_features: Vec<Feature> =
+                            client_features.iter().filter_map(|name| Feature::parse(name)).collect();
+                        let agreed: Vec<Feature> =
+                            features.iter().copied().filter(|f| client_features.contains(f)).collect();
+                        info!("Client hello: protocol version {version}, features {agreed:?}");
+                        greeted.store(true, Ordering::Relaxed);
+                        if agreed.contains(&Feature::BinaryInput) {
+                            session.enable_binary_input();
+                        }
+                        let client_cursor = agreed.contains(&Feature::ClientCursor);
+                        if features.contains(&Feature::ClientCursor) && !client_cursor {
+                            warn!("Client can't draw the cursor and the video doesn't contain it (LOCALBRIDGE_CURSOR=client)");
+                        }
+                        let hello = HostMessage::Hello { version, features: agreed, display: session.display() };
+                        // Only start forwarding once the reply is out, so 'hello' comes first.
+                        if send(&dc, &hello).await {
+                            forward_host_messages(dc, &session, feeds, client_cursor);
+                        }
+                        return;
+                    }
+                }
+                Ok(ClientMessage::Ping { id, client_time }) => {
+                    HostMessage::Pong { id, client_time, host_time: latency::host_clock_ms() }
+                }
+                Ok(ClientMessage::LatencyReport { frames, encode_ms, network_ms, display_ms, total_ms }) => {
+                    info!(
+                        "Latency (peer {}, {frames} frames) -> capture+encode {encode_ms:.1} ms, network {network_ms:.1} ms, \
+                         receive->display {display_ms:.1} ms, glass-to-glass {total_ms:.1} ms",
+                        session.id()
+                    );
+                    return;
+                }
+                Err(e) => HostMessage::error(ErrorCode::MalformedMessage, e),
+            };
+            send(&dc, &reply).await;
+        })
+    }));
+}
+
+/// Sends everything the client should hear about (display, cursor and encoder changes,
+/// what the session raises and host-wide notices) until the channel closes.
+/// With 'client_cursor' the cursor position is streamed as well.
+/// Only the first call per session does anything.
+fn forward_host_messages(
+    dc:            Arc<RTCDataChannel>,
+    session:       &InputSession,
+    feeds:         HostFeeds,
+    client_cursor: bool,
+) {
+    let Some(mut host_messages) = session.take_host_messages() else { return };
+    let mut display_changes = session.display_changes();
+    // The hello reply carries the current display, so only later changes are news.
+    display_changes.borrow_and_update();
+    let HostFeeds { cursor: CursorFeed { shape: mut cursor, mut position }, mut encoder, broadcast } = feeds;
+    let mut broadcast = broadcast.subscribe();
+    tokio::spawn(async move {
+        // Cursor and encoder state are sent right away so the client starts complete.
+        let settings = *encoder.borrow_and_update();
+        let mut initial = vec![HostMessage::Cursor(cursor.borrow_and_update().clone())];
+        if client_cursor {
+            let point = *position.borrow_and_update();
+            initial.push(cursor_position(point, &display_changes.borrow()));
+        }
+        // Zero until the capture loop has built its first encoder.
+        if settings.width > 0 {
+            initial.push(HostMessage::EncoderSettings(settings));
+        }
+        for message in initial {
+            if !send(&dc, &message).await {
+                return;
+            }
+        }
+        loop {
+            let message = tokio::select! {
+                changed = display_changes.changed() => match changed {
+                    Ok(()) => HostMessage::DisplayChanged(*display_changes.borrow_and_update()),
+                    Err(_) => break,
+                },
+                changed = cursor.changed() => match changed {
+                    Ok(()) => HostMessage::Cursor(cursor.borrow_and_update().clone()),
+                    Err(_) => break,
+                },
+                changed = position.changed(), if client_cursor => match changed {
+                    Ok(()) => {
+                        let point = *position.borrow_and_update();
+                        cursor_position(point, &display_changes.borrow())
+                    }
+                    Err(_) => break,
+                },
+                changed = encoder.changed() => match changed {
+                    Ok(()) => HostMessage::EncoderSettings(*encoder.borrow_and_update()),
+                    Err(_) => break,
+                },
+                next = broadcast.recv() => match next {
+                    Ok(message) => message,
+                    // Missed some notices; the next one is still worth sending.
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break,
+                },
+                next = host_messages.recv() => match next {
+                    Some(message) => message,
+                    None => break,
+                },
+            };
+            // Sending fails once the channel is closed; that ends this task.
+            if !send(&dc, &message).await {
+                break;
+            }
+        }
+    });
+}
+
+/// Maps a virtual-desktop pixel to the normalized position on the captured display.
+fn cursor_position((x, y): (i32, i32), display: &DisplayGeometry) -> HostMessage {
+    let width = display.width.max(1) as f64;
+    let height = display.height.max(1) as f64;
+    // Pixel centers, so the last column maps just below 1.0 like client input does.
+    HostMessage::CursorPosition {
+        x: ((x - display.x) as f64 + 0.5) / width,
+        y: ((y - display.y) as f64 + 0.5) / height,
+    }
+}
+
+async fn send(dc: &RTCDataChannel, message: &HostMessage) -> bool {
+    match serde_json::to_string(message) {
+        Ok(json) => dc.send_text(json).await.is_ok(),
+        Err(_) => true,
+    }
+}
@@ -0,0 +1,155 @@
+use tracing::warn;
+
+// Thin wrapper around the Win32 'SendInput' API for the cases enigo doesn't cover.
+// Layouts and constants follow WinUser.h.
+
+const INPUT_MOUSE: u32 = 0;
+const INPUT_KEYBOARD: u32 = 1;
+
+const MOUSEEVENTF_MOVE: u32 = 0x0001;
+const MOUSEEVENTF_XDOWN: u32 = 0x0080;
+const MOUSEEVENTF_XUP: u32 = 0x0100;
+const MOUSEEVENTF_WHEEL: u32 = 0x0800;
+const MOUSEEVENTF_HWHEEL: u32 = 0x1000;
+
+const KEYEVENTF_EXTENDEDKEY: u32 = 0x0001;
+const KEYEVENTF_KEYUP: u32 = 0x0002;
+const KEYEVENTF_UNICODE: u32 = 0x0004;
+const KEYEVENTF_SCANCODE: u32 = 0x0008;
+
+const VK_RETURN: u16 = 0x0D;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct MouseInput {
+    dx:          i32,
+    dy:          i32,
+    mouse_data:  u32,
+    flags:       u32,
+    time:        u32,
+    extra_info:  usize,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct KeybdInput {
+    vk:          u16,
+    scan:        u16,
+    flags:       u32,
+    time:        u32,
+    extra_info:  usize,
+}
+
+// 'INPUT' is a tagged union; the mouse variant is the largest, so it has to be
+// present for the struct size passed to 'SendInput' to be right.
+#[repr(C)]
+#[derive(Clone, Copy)]
+union InputUnion {
+    mi: MouseInput,
+    ki: KeybdInput,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Input {
+    kind: u32,
+    u:    InputUnion,
+}
+
+extern "system" {
+    fn SendInput(count: u32, inputs: *const Input, size: i32) -> u32;
+}
+
+fn send(inputs: &[Input]) {
+    let sent = unsafe {
+        SendInput(inputs.len() as u32, inputs.as_ptr(), std::mem::size_of::<Input>() as i32)
+    };
+    if sent as usize != inputs.len() {
+        // Typically UIPI: the foreground window belongs to an elevated process.
+        warn!("SendInput injected {sent} of {} event(s)", inputs.len());
+    }
+}
+
+fn mouse(dx: i32, dy: i32, mouse_data: u32, flags: u32) -> Input {
+    Input {
+        kind: INPUT_MOUSE,
+        u: InputUnion {
+            mi: MouseInput { dx, dy, mouse_data, flags, time: 0, extra_info: 0 },
+        },
+    }
+}
+
+fn keybd(vk: u16, scan: u16, flags: u32) -> Input {
+    Input {
+        kind: INPUT_KEYBOARD,
+        u: InputUnion {
+            ki: KeybdInput { vk, scan, flags, time: 0, extra_info: 0 },
+        },
+    }
+}
+
+/// Presses or releases a key by its hardware (set 1) scancode.
+/// Extended keys are written as 0xE0xx, e.g. 0xE04B for ArrowLeft.
+/// Windows runs the scancode through the host's own keyboard layout, so the
+/// character produced is whatever that physical key means on the host.
+pub fn key_scancode(scancode: u16, key_up: bool) {
+    let mut flags = KEYEVENTF_SCANCODE;
+    if scancode & 0xFF00 == 0xE000 {
+        flags |= KEYEVENTF_EXTENDEDKEY;
+    }
+    if key_up {
+        flags |= KEYEVENTF_KEYUP;
+    }
+    send(&[keybd(0, scancode & 0x00FF, flags)]);
+}
+
+/// Types arbitrary Unicode text, independent of the host keyboard layout or IME.
+/// Each character is sent as UTF-16 code units with 'KEYEVENTF_UNICODE'
+/// (surrogate pairs included). Newlines become a real Enter key, since many
+/// applications ignore a Unicode line feed.
+pub fn type_text(text: &str) {
+    let mut inputs = Vec::with_capacity(text.len() * 2);
+    for ch in text.chars() {
+        match ch {
+            '\r' => continue,
+            '\n' => {
+                inputs.push(keybd(VK_RETURN, 0, 0));
+                inputs.push(keybd(VK_RETURN, 0, KEYEVENTF_KEYUP));
+            }
+            _ => {
+                let mut buf = [0u16; 2];
+                let units = ch.encode_utf16(&mut buf);
+                for unit in units.iter() {
+                    inputs.push(keybd(0, *unit, KEYEVENTF_UNICODE));
+                }
+                for unit in units.iter() {
+                    inputs.push(keybd(0, *unit, KEYEVENTF_UNICODE | KEYEVENTF_KEYUP));
+                }
+            }
+        }
+    }
+    if !inputs.is_empty() {
+        send(&inputs);
+    }
+}
+
+/// Moves the cursor by a relative amount, like a physical mouse would.
+/// Applications reading raw input (most games) see exactly these deltas.
+pub fn mouse_relative(dx: i32, dy: i32) {
+    send(&[mouse(dx, dy, 0, MOUSEEVENTF_MOVE)]);
+}
+
+/// Presses or releases side button 'XBUTTON1' (1, "back") or 'XBUTTON2' (2, "forward").
+pub fn mouse_xbutton(xbutton: u16, up: bool) {
+    let flags = if up { MOUSEEVENTF_XUP } else { MOUSEEVENTF_XDOWN };
+    send(&[mouse(0, 0, xbutton as u32, flags)]);
+}
+
+/// Turns the mouse wheel by 'delta' in 'WHEEL_DELTA' units (120 = one notch).
+/// Values below 120 are high-resolution scrolling; applications that support it
+/// scroll smoothly, the rest accumulate until a full notch.
+/// Positive vertical deltas scroll up, positive horizontal deltas scroll right.
+pub fn mouse_wheel(delta: i32, horizontal: bool) {
+    let flags = if horizontal { MOUSEEVENTF_HWHEEL } else { MOUSEEVENTF_WHEEL };
+    send(&[mouse(0, 0, delta as u32, flags)]);
+}
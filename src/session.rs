@@ -0,0 +1,119 @@
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::{Instant, SystemTime},
+};
+use serde::{Deserialize, Serialize};
+use webrtc::{
+    peer_connection::{peer_connection_state::RTCPeerConnectionState, RTCPeerConnection},
+    stats::StatsReportType,
+};
+
+use crate::input::InputSession;
+
+// A bitrate is only recomputed when the previous sample is at least this old, so
+// a scrape right after an admin request doesn't divide by a few milliseconds.
+const MIN_BITRATE_INTERVAL_SECS: f64 = 1.0;
+
+/// 'Session' is one connected peer, as kept in 'AppState.peers'.
+pub struct Session {
+    pub id:           String,
+    pub pc:           Arc<RTCPeerConnection>,
+    // Where the offer came from.
+    pub remote_addr:  SocketAddr,
+    pub connected_at: SystemTime,
+    pub input:        Arc<InputSession>,
+    // Bytes sent at the last sample, when, and the bitrate worked out then.
+    bitrate: Mutex<Option<(u64, Instant, Option<f64>)>>,
+}
+
+/// 'Role' says what a session may do. Everyone starts with 'control'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Sees the screen and drives the host's input.
+    Control,
+    /// Only watches; its input is dropped.
+    View,
+}
+
+/// 'VideoStats' is what the RTCP statistics say about the video sent to a peer.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VideoStats {
+    pub rtt_seconds:   Option<f64>,
+    // From the peer's last receiver report.
+    pub fraction_lost: Option<f64>,
+    pub packets_lost:  Option<i64>,
+    pub bitrate_bps:   Option<f64>,
+}
+
+impl Session {
+    pub fn new(
+        id:          String,
+        pc:          Arc<RTCPeerConnection>,
+        remote_addr: SocketAddr,
+        input:       Arc<InputSession>,
+    ) -> Self {
+        Self {
+            id,
+            pc,
+            remote_addr,
+            connected_at: SystemTime::now(),
+            input,
+            bitrate: Mutex::new(None),
+        }
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.pc.connection_state() == RTCPeerConnectionState::Connected
+    }
+
+    pub fn role(&self) -> Role {
+        if self.input.has_control() { Role::Control } else { Role::View }
+    }
+
+    pub fn set_role(&self, role: Role) {
+        self.input.set_control(role == Role::Control);
+    }
+
+    /// Reads the peer connection's statistics. The bitrate is averaged since the
+    /// previous call, which may have come from a scrape or an admin request.
+    pub async fn video_stats(&self) -> VideoStats {
+        let mut stats = VideoStats::default();
+        for report in self.pc.get_stats().await.reports.values() {
+            match report {
+                // What the client reported back in RTCP receiver reports.
+                StatsReportType::RemoteInboundRTP(remote) if remote.kind == "video" => {
+                    stats.rtt_seconds = remote.round_trip_time;
+                    stats.fraction_lost = Some(remote.fraction_lost);
+                    stats.packets_lost = Some(remote.packets_lost);
+                }
+                StatsReportType::OutboundRTP(outbound) if outbound.kind == "video" => {
+                    stats.bitrate_bps = self.sample_bitrate(outbound.bytes_sent);
+                }
+                _ => {}
+            }
+        }
+        stats
+    }
+
+    fn sample_bitrate(&self, bytes_sent: u64) -> Option<f64> {
+        let now = Instant::now();
+        let mut last = self.bitrate.lock().unwrap();
+        match *last {
+            Some((bytes, at, bitrate)) => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                if elapsed < MIN_BITRATE_INTERVAL_SECS {
+                    return bitrate;
+                }
+                let bitrate = Some(bytes_sent.saturating_sub(bytes) as f64 * 8.0 / elapsed);
+                *last = Some((bytes_sent, now, bitrate));
+                bitrate
+            }
+            None => {
+                *last = Some((bytes_sent, now, None));
+                None
+            }
+        }
+    }
+}
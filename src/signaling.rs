@@ -1,25 +1,209 @@
+use std::{net::SocketAddr, sync::Arc};
+use anyhow::Result;
 use axum::{
-    extract::{ws::WebSocket, State, WebSocketUpgrade},
+    extract::{
+        ws::{Message, WebSocket},
+        ConnectInfo, State, WebSocketUpgrade,
+    },
+    http::{header, HeaderMap},
     response::IntoResponse,
 };
-use crate::AppState;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+use webrtc::{
+    ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit},
+    peer_connection::{sdp::session_description::RTCSessionDescription, RTCPeerConnection},
+};
+
+use crate::{approval::ApprovalRequest, AppState};
+
+// WebSocket signaling with trickle ICE, at '/ws/signal' ('/ws/input' is the old name).
+// Unlike POST '/offer', nobody waits for ICE gathering: the client sends its offer as
+// soon as it has one, the host answers right away, and both sides send their
+// candidates as they find them. Messages are JSON with a "type":
+//
+//   client -> host: offer {sdp}, candidate {candidate}, end_of_candidates
+//   host -> client: pending (waiting for host approval), answer {sdp},
+//                   candidate {candidate}, end_of_candidates, error {message}
+//
+// 'candidate' is the browser's 'RTCIceCandidateInit' JSON. The socket may be closed
+// once the peer connection is up.
+
+/// What the client sends on the signaling socket.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientSignal {
+    Offer { sdp: String },
+    Candidate { candidate: RTCIceCandidateInit },
+    EndOfCandidates,
+}
+
+/// What the host sends on the signaling socket.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HostSignal {
+    Pending,
+    Answer { sdp: String },
+    Candidate { candidate: RTCIceCandidateInit },
+    EndOfCandidates,
+    Error { message: String },
+}
 
 /// This handler handles upgrading an HTTP connection to a WebSocket connection.
 /// WebSockets allow for two-way, real-time communication between the browser and the server.
-pub async fn ws_input_handler(
-    ws:            WebSocketUpgrade,
-    State(_state): State<AppState>,
+pub async fn ws_signal_handler(
+    ws:                       WebSocketUpgrade,
+    State(state):             State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers:                  HeaderMap,
 ) -> impl IntoResponse {
+    let user_agent = headers.get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_owned);
     // If the browser wants to upgrade, we call 'handle_ws'.
-    ws.on_upgrade(handle_ws)
+    ws.on_upgrade(move |socket| handle_ws(socket, state, remote_addr, user_agent))
+}
+
+/// Once the WebSocket is established, this function runs until either side closes it.
+async fn handle_ws(
+    mut socket:  WebSocket,
+    state:       AppState,
+    remote_addr: SocketAddr,
+    user_agent:  Option<String>,
+) {
+    // The host's candidates come from WebRTC callbacks; they queue here and this
+    // loop writes them, so only one place ever writes to the socket.
+    let (signals_tx, mut signals_rx) = mpsc::unbounded_channel();
+    let mut peer: Option<Arc<RTCPeerConnection>> = None;
+
+    loop {
+        tokio::select! {
+            msg = socket.recv() => {
+                let text = match msg {
+                    Some(Ok(Message::Text(text))) => text,
+                    Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                    Some(Ok(_)) => continue,
+                };
+                let signal = match serde_json::from_str::<ClientSignal>(&text) {
+                    Ok(signal) => signal,
+                    Err(e) => {
+                        warn!("Signaling from {remote_addr}: invalid message: {e}");
+                        let _ = send(&mut socket, &error(format!("Invalid message: {e}"))).await;
+                        continue;
+                    }
+                };
+                match signal {
+                    ClientSignal::Offer { sdp } => {
+                        if peer.is_some() {
+                            let _ = send(&mut socket, &error("This socket already negotiated a session")).await;
+                            continue;
+                        }
+                        if state.approvals.is_required() {
+                            let _ = send(&mut socket, &HostSignal::Pending).await;
+                            let request = ApprovalRequest {
+                                ticket:         uuid::Uuid::new_v4().to_string(),
+                                remote_address: remote_addr,
+                                user_agent:     user_agent.clone(),
+                            };
+                            if let Some(refusal) = state.approvals.ask(request).await.refusal() {
+                                let _ = send(&mut socket, &error(refusal)).await;
+                                break;
+                            }
+                        }
+                        match answer(&state, sdp, remote_addr, signals_tx.clone()).await {
+                            Ok((pc, sdp)) => {
+                                peer = Some(pc);
+                                // Sent before this loop gets to any queued candidate.
+                                if send(&mut socket, &HostSignal::Answer { sdp }).await.is_err() {
+                                    break;
+                                }
+                            }
+                            Err(e) => {
+                                error!("Offer error: {e}");
+                                let _ = send(&mut socket, &error(e.to_string())).await;
+                                break;
+                            }
+                        }
+                    }
+                    ClientSignal::Candidate { candidate } => match &peer {
+                        Some(pc) => {
+                            if let Err(e) = pc.add_ice_candidate(candidate).await {
+                                warn!("Signaling from {remote_addr}: bad candidate: {e}");
+                            }
+                        }
+                        None => debug!("Signaling from {remote_addr}: candidate before offer, ignored"),
+                    },
+                    ClientSignal::EndOfCandidates => debug!("Signaling from {remote_addr}: end of candidates"),
+                }
+            }
+            Some(signal) = signals_rx.recv() => {
+                if send(&mut socket, &signal).await.is_err() {
+                    break;
+                }
+            }
+        }
+    }
 }
 
-/// Once the WebSocket is established, this function runs.
-async fn handle_ws(mut socket: WebSocket) {
-    // This is a simple loop that waits for messages from the browser.
-    // Currently, we don't use this much because most communication happens via WebRTC data channels,
-    // but it's here for future features like multi-monitor support or advanced signaling.
-    while let Some(Ok(msg)) = socket.recv().await {
-        tracing::debug!("WS: {:?}", msg);
+/// Creates the session's peer connection and answers the offer without waiting for
+/// ICE gathering; the host's candidates go to 'signals' as they are found.
+async fn answer(
+    state:       &AppState,
+    sdp:         String,
+    remote_addr: SocketAddr,
+    signals:     mpsc::UnboundedSender<HostSignal>,
+) -> Result<(Arc<RTCPeerConnection>, String)> {
+    info!(
+        "Received trickle offer from {remote_addr} (h264={}, vp8={})",
+        sdp.contains("H264/90000"),
+        sdp.contains("VP8/90000")
+    );
+    let (id, pc) = crate::new_peer(state, remote_addr).await?;
+
+    // 'None' means gathering is complete.
+    pc.on_ice_candidate(Box::new(move |candidate: Option<RTCIceCandidate>| {
+        let signal = match candidate.map(|c| c.to_json()) {
+            Some(Ok(candidate)) => Some(HostSignal::Candidate { candidate }),
+            Some(Err(e)) => {
+                warn!("Could not serialize local candidate: {e}");
+                None
+            }
+            None => Some(HostSignal::EndOfCandidates),
+        };
+        if let Some(signal) = signal {
+            let _ = signals.send(signal);
+        }
+        Box::pin(async {})
+    }));
+
+    // A client-controlled SDP can fail any of these; don't leave the peer registered.
+    let negotiated = async {
+        pc.set_remote_description(RTCSessionDescription::offer(sdp)?).await?;
+        let answer = pc.create_answer(None).await?;
+        pc.set_local_description(answer).await?;
+        let local = pc.local_description().await
+            .ok_or_else(|| anyhow::anyhow!("No local description"))?;
+        Ok::<_, anyhow::Error>(local)
+    }.await;
+    match negotiated {
+        Ok(local) => {
+            info!("Peer {id} answered over WebSocket; trickling candidates");
+            Ok((pc, local.sdp))
+        }
+        Err(e) => {
+            crate::discard_peer(state, &id, &pc).await;
+            Err(e)
+        }
     }
-}
\ No newline at end of file
+}
+
+fn error(message: impl Into<String>) -> HostSignal {
+    HostSignal::Error { message: message.into() }
+}
+
+async fn send(socket: &mut WebSocket, signal: &HostSignal) -> Result<()> {
+    let text = serde_json::to_string(signal)?;
+    socket.send(Message::Text(text)).await?;
+    Ok(())
+}
@@ -0,0 +1,587 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use schemars::{schema_for, JsonSchema};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::{AsyncReadExt, AsyncWriteExt},
+    sync::Mutex,
+};
+use tracing::{info, warn};
+use webrtc::data_channel::RTCDataChannel;
+
+use crate::{input::InputSession, protocol::ErrorCode};
+
+// File transfer runs on its own reliable, ordered data channel ("files").
+// Requests and replies are JSON text messages; file data travels in binary
+// messages framed as [transfer id: u32 BE][offset: u64 BE][bytes].
+
+// File bytes per data message.
+const CHUNK_SIZE: usize = 16 * 1024;
+const FRAME_HEADER: usize = 12;
+// Pause a download while this much is still queued on the channel.
+const MAX_BUFFERED: usize = 1024 * 1024;
+// Uploads report progress every this many bytes.
+const PROGRESS_STEP: u64 = 1024 * 1024;
+
+/// 'TransferConfig' holds the host directories, read once at startup.
+/// 'LOCALBRIDGE_DOWNLOAD_DIR' is where files sent from the browser are saved
+/// (unset: uploads are disabled, so no client can write to the host's disk
+/// unless the operator asked for it).
+/// 'LOCALBRIDGE_SHARE_DIR' is the directory clients may browse and download from
+/// (unset: browsing is disabled).
+/// It also tracks which partial files uploads are writing to, across all sessions.
+pub struct TransferConfig {
+    download_dir: Option<PathBuf>,
+    share_dir:    Option<PathBuf>,
+    parts:        std::sync::Mutex<HashSet<PathBuf>>,
+}
+
+impl TransferConfig {
+    pub fn from_env() -> Self {
+        // "off" was how uploads used to be disabled; it still means that.
+        let download_dir = std::env::var_os("LOCALBRIDGE_DOWNLOAD_DIR")
+            .filter(|dir| !dir.is_empty() && dir != "off")
+            .map(PathBuf::from);
+        let share_dir = std::env::var_os("LOCALBRIDGE_SHARE_DIR").map(PathBuf::from);
+        match &download_dir {
+            Some(dir) => info!("File uploads are saved to {}", dir.display()),
+            None => info!("File uploads disabled"),
+        }
+        if let Some(dir) = &share_dir {
+            info!("Clients may browse and download from {}", dir.display());
+        }
+        Self { download_dir, share_dir, parts: Default::default() }
+    }
+
+    /// Whether uploads or browsing is allowed.
+    pub fn is_enabled(&self) -> bool {
+        self.download_dir.is_some() || self.share_dir.is_some()
+    }
+
+    /// Reserves a partial file for one upload; 'None' while another upload uses it.
+    fn claim_part(self: &Arc<Self>, part: &Path) -> Option<PartClaim> {
+        let mut parts = self.parts.lock().unwrap();
+        parts.insert(part.to_path_buf()).then(|| PartClaim {
+            config: Arc::clone(self),
+            part:   part.to_path_buf(),
+        })
+    }
+}
+
+/// 'PartClaim' keeps a partial file reserved until the upload writing it is dropped.
+struct PartClaim {
+    config: Arc<TransferConfig>,
+    part:   PathBuf,
+}
+
+impl Drop for PartClaim {
+    fn drop(&mut self) {
+        self.config.parts.lock().unwrap().remove(&self.part);
+    }
+}
+
+/// Schema of the "files" channel's JSON messages. Binary frames are
+/// '[u32 id][u64 offset][data]', both integers big-endian.
+pub fn schema() -> serde_json::Value {
+    serde_json::json!({
+        "request": schema_for!(Request),
+        "reply":   schema_for!(Reply),
+    })
+}
+
+/// Requests from the browser.
+#[derive(Debug, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    // Starts or resumes sending a file to the host. 'sha256' (hex) is checked if given.
+    UploadStart { id: u32, name: String, size: u64, sha256: Option<String> },
+    // Abandons an upload and deletes what was received.
+    UploadCancel { id: u32 },
+    // Lists a directory below the share root ("" is the root itself).
+    List { path: String },
+    // Downloads a file below the share root, starting at 'offset' to resume.
+    DownloadStart { id: u32, path: String, offset: u64 },
+    DownloadCancel { id: u32 },
+}
+
+/// Replies and events sent to the browser.
+#[derive(Debug, Serialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Reply {
+    Hello { can_upload: bool, can_browse: bool },
+    // Send upload data from 'offset'; non-zero when a previous attempt is resumed.
+    UploadReady { id: u32, offset: u64 },
+    Progress { id: u32, bytes: u64, total: u64 },
+    UploadDone { id: u32, name: String, size: u64, sha256: String },
+    Listing { path: String, entries: Vec<FileEntry> },
+    DownloadInfo { id: u32, name: String, size: u64, offset: u64 },
+    // 'sha256' covers the whole file, including the part a resumed download skipped.
+    DownloadDone { id: u32, sha256: String },
+    Error { id: Option<u32>, message: String },
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct FileEntry {
+    name:     String,
+    is_dir:   bool,
+    size:     u64,
+    // Seconds since the Unix epoch.
+    modified: u64,
+}
+
+struct Upload {
+    file:          File,
+    part:          PathBuf,
+    name:          String,
+    size:          u64,
+    received:      u64,
+    sha256:        Option<String>,
+    last_progress: u64,
+    // Released when the upload ends, however it ends.
+    _claim:        PartClaim,
+}
+
+/// 'FilesChannel' is the state of one peer's "files" data channel.
+struct FilesChannel {
+    dc:        Arc<RTCDataChannel>,
+    config:    Arc<TransferConfig>,
+    // The peer's input side, which holds its role.
+    session:   Arc<InputSession>,
+    uploads:   Mutex<HashMap<u32, Upload>>,
+    downloads: Mutex<HashMap<u32, Arc<AtomicBool>>>,
+}
+
+/// Sets up the "files" data channel of one peer. View-only sessions can't upload,
+/// browse or download.
+pub async fn handle_data_channel(dc: Arc<RTCDataChannel>, config: Arc<TransferConfig>, session: Arc<InputSession>) {
+    let channel = Arc::new(FilesChannel {
+        dc:        Arc::clone(&dc),
+        config,
+        session,
+        uploads:   Mutex::new(HashMap::new()),
+        downloads: Mutex::new(HashMap::new()),
+    });
+
+    let channel_open = Arc::clone(&channel);
+    dc.on_open(Box::new(move || {
+        Box::pin(async move {
+            let hello = Reply::Hello {
+                can_upload: channel_open.config.download_dir.is_some(),
+                can_browse: channel_open.config.share_dir.is_some(),
+            };
+            channel_open.reply(&hello).await;
+        })
+    }));
+
+    // Partial uploads stay on disk, so the client can resume them after reconnecting.
+    let channel_close = Arc::clone(&channel);
+    dc.on_close(Box::new(move || {
+        let channel = Arc::clone(&channel_close);
+        Box::pin(async move {
+            channel.uploads.lock().await.clear();
+            for cancel in channel.downloads.lock().await.values() {
+                cancel.store(true, Ordering::Relaxed);
+            }
+        })
+    }));
+
+    dc.on_message(Box::new(move |msg| {
+        let channel = Arc::clone(&channel);
+        Box::pin(async move {
+            if msg.is_string {
+                let request = match std::str::from_utf8(&msg.data).map(serde_json::from_str::<Request>) {
+                    Ok(Ok(request)) => request,
+                    _ => {
+                        channel.error(None, "malformed request").await;
+                        return;
+                    }
+                };
+                channel.handle_request(request).await;
+            } else {
+                channel.handle_chunk(&msg.data).await;
+            }
+        })
+    }));
+}
+
+impl FilesChannel {
+    async fn reply(&self, reply: &Reply) {
+        if let Ok(json) = serde_json::to_string(reply) {
+            let _ = self.dc.send_text(json).await;
+        }
+    }
+
+    async fn error(&self, id: Option<u32>, message: impl Into<String>) {
+        let message = message.into();
+        warn!("File transfer error ({id:?}): {message}");
+        self.reply(&Reply::Error { id, message }).await;
+    }
+
+    /// Fails for view-only sessions, which also get a 'view_only' error on their
+    /// control channel.
+    fn check_control(&self) -> Result<()> {
+        if !self.session.has_control() {
+            self.session.report_error(ErrorCode::ViewOnly, "view-only sessions can't transfer files");
+            bail!("this session is view-only");
+        }
+        Ok(())
+    }
+
+    async fn handle_request(self: &Arc<Self>, request: Request) {
+        let result = match request {
+            Request::UploadStart { id, name, size, sha256 } => {
+                self.upload_start(id, &name, size, sha256).await.map_err(|e| (Some(id), e))
+            }
+            Request::UploadCancel { id } => {
+                if let Some(upload) = self.uploads.lock().await.remove(&id) {
+                    drop(upload.file);
+                    let _ = fs::remove_file(&upload.part).await;
+                    info!("Upload of '{}' cancelled", upload.name);
+                }
+                Ok(())
+            }
+            Request::List { path } => self.list(&path).await.map_err(|e| (None, e)),
+            Request::DownloadStart { id, path, offset } => {
+                self.download_start(id, &path, offset).await.map_err(|e| (Some(id), e))
+            }
+            Request::DownloadCancel { id } => {
+                if let Some(cancel) = self.downloads.lock().await.remove(&id) {
+                    cancel.store(true, Ordering::Relaxed);
+                }
+                Ok(())
+            }
+        };
+        if let Err((id, e)) = result {
+            self.error(id, e.to_string()).await;
+        }
+    }
+
+    async fn upload_start(&self, id: u32, name: &str, size: u64, sha256: Option<String>) -> Result<()> {
+        self.check_control()?;
+        let dir = self.config.download_dir.as_ref().ok_or_else(|| anyhow!("uploads are disabled on this host"))?;
+        fs::create_dir_all(dir).await?;
+        let name = sanitize_file_name(name);
+        // The partial file is keyed by name and size, so a retry of the same file resumes.
+        // A second upload appending to it at the same time would corrupt it, so it has
+        // to wait until the first one ends. Starting again under the same id replaces
+        // this session's earlier attempt.
+        let part = dir.join(format!("{name}.{size}.part"));
+        self.uploads.lock().await.remove(&id);
+        let claim = self.config.claim_part(&part)
+            .ok_or_else(|| anyhow!("'{name}' ({size} bytes) is already being uploaded"))?;
+        let mut file = OpenOptions::new().create(true).append(true).open(&part).await?;
+        let mut received = file.metadata().await?.len();
+        if received > size {
+            file.set_len(0).await?;
+            received = 0;
+        }
+        if received > 0 {
+            info!("Resuming upload of '{name}' at {received} of {size} bytes");
+        } else {
+            info!("Receiving '{name}' ({size} bytes)");
+        }
+        self.uploads.lock().await.insert(id, Upload {
+            file,
+            part,
+            name,
+            size,
+            received,
+            sha256: sha256.map(|h| h.to_ascii_lowercase()),
+            last_progress: received,
+            _claim: claim,
+        });
+        self.reply(&Reply::UploadReady { id, offset: received }).await;
+        // An empty file (or a fully received one) is finished right away.
+        if received == size {
+            self.finish_upload(id).await;
+        }
+        Ok(())
+    }
+
+    async fn handle_chunk(&self, frame: &[u8]) {
+        if frame.len() < FRAME_HEADER {
+            return;
+        }
+        let id = u32::from_be_bytes(frame[0..4].try_into().unwrap());
+        let offset = u64::from_be_bytes(frame[4..12].try_into().unwrap());
+        let data = &frame[FRAME_HEADER..];
+
+        let (progress, done) = {
+            let mut uploads = self.uploads.lock().await;
+            let Some(upload) = uploads.get_mut(&id) else { return };
+            // Made view-only mid-upload: stop, keeping the part file to resume later.
+            if !self.session.has_control() {
+                uploads.remove(&id);
+                drop(uploads);
+                self.error(Some(id), "this session is view-only").await;
+                return;
+            }
+            if offset != upload.received {
+                // Tell the client where to continue instead of corrupting the file.
+                let received = upload.received;
+                drop(uploads);
+                self.reply(&Reply::UploadReady { id, offset: received }).await;
+                return;
+            }
+            if upload.received + data.len() as u64 > upload.size {
+                let part = upload.part.clone();
+                uploads.remove(&id);
+                drop(uploads);
+                let _ = fs::remove_file(&part).await;
+                self.error(Some(id), "more data than the announced size").await;
+                return;
+            }
+            if let Err(e) = upload.file.write_all(data).await {
+                uploads.remove(&id);
+                drop(uploads);
+                self.error(Some(id), format!("write failed: {e}")).await;
+                return;
+            }
+            upload.received += data.len() as u64;
+            let done = upload.received == upload.size;
+            let progress = (done || upload.received - upload.last_progress >= PROGRESS_STEP).then(|| {
+                upload.last_progress = upload.received;
+                Reply::Progress { id, bytes: upload.received, total: upload.size }
+            });
+            (progress, done)
+        };
+        if let Some(progress) = progress {
+            self.reply(&progress).await;
+        }
+        if done {
+            self.finish_upload(id).await;
+        }
+    }
+
+    /// Verifies a complete upload and moves it to its final name.
+    async fn finish_upload(&self, id: u32) {
+        let Some(mut upload) = self.uploads.lock().await.remove(&id) else { return };
+        if let Err(e) = upload.file.flush().await {
+            self.error(Some(id), format!("write failed: {e}")).await;
+            return;
+        }
+        drop(upload.file);
+
+        let result = async {
+            let sha256 = sha256_file(&upload.part).await?;
+            if let Some(expected) = &upload.sha256 {
+                if *expected != sha256 {
+                    let _ = fs::remove_file(&upload.part).await;
+                    bail!("SHA-256 mismatch (expected {expected}, got {sha256}); the file was discarded");
+                }
+            }
+            let dir = upload.part.parent().unwrap_or(Path::new("."));
+            let target = unique_path(dir, &upload.name).await;
+            fs::rename(&upload.part, &target).await?;
+            Ok((target, sha256))
+        }.await;
+
+        match result {
+            Ok((target, sha256)) => {
+                info!("Saved upload to {} ({} bytes, sha256 {sha256})", target.display(), upload.size);
+                let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+                self.reply(&Reply::UploadDone { id, name, size: upload.size, sha256 }).await;
+            }
+            Err(e) => self.error(Some(id), e.to_string()).await,
+        }
+    }
+
+    async fn list(&self, path: &str) -> Result<()> {
+        self.check_control()?;
+        let root = self.config.share_dir.as_ref().ok_or_else(|| anyhow!("browsing is disabled on this host"))?;
+        let dir = resolve_shared(root, path)?;
+        let mut entries = Vec::new();
+        let mut read_dir = fs::read_dir(&dir).await?;
+        while let Some(entry) = read_dir.next_entry().await? {
+            let Ok(meta) = entry.metadata().await else { continue };
+            let modified = meta.modified().ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |d| d.as_secs());
+            entries.push(FileEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                is_dir: meta.is_dir(),
+                size: if meta.is_dir() { 0 } else { meta.len() },
+                modified,
+            });
+        }
+        // Directories first, then by name.
+        entries.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.to_lowercase().cmp(&b.name.to_lowercase())));
+        self.reply(&Reply::Listing { path: path.to_owned(), entries }).await;
+        Ok(())
+    }
+
+    async fn download_start(self: &Arc<Self>, id: u32, path: &str, offset: u64) -> Result<()> {
+        self.check_control()?;
+        let root = self.config.share_dir.as_ref().ok_or_else(|| anyhow!("browsing is disabled on this host"))?;
+        let file_path = resolve_shared(root, path)?;
+        let meta = fs::metadata(&file_path).await?;
+        if !meta.is_file() {
+            bail!("not a file");
+        }
+        let size = meta.len();
+        if offset > size {
+            bail!("offset {offset} is past the end of the file ({size} bytes)");
+        }
+        let name = file_path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+        info!("Sending {} from byte {offset} ({size} bytes)", file_path.display());
+        self.reply(&Reply::DownloadInfo { id, name, size, offset }).await;
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.downloads.lock().await.insert(id, Arc::clone(&cancel));
+        let channel = Arc::clone(self);
+        tokio::spawn(async move {
+            let result = channel.send_file(id, &file_path, offset, &cancel).await;
+            channel.downloads.lock().await.remove(&id);
+            match result {
+                Ok(Some(sha256)) => {
+                    info!("Sent {} (sha256 {sha256})", file_path.display());
+                    channel.reply(&Reply::DownloadDone { id, sha256 }).await;
+                }
+                Ok(None) => info!("Download of {} cancelled", file_path.display()),
+                Err(e) => channel.error(Some(id), e.to_string()).await,
+            }
+        });
+        Ok(())
+    }
+
+    /// Streams a file as binary frames and returns its SHA-256, or 'None' if it was
+    /// cancelled. Hashing happens here, in the download's own task, so a large file
+    /// doesn't hold up the channel's other requests before its first byte is sent.
+    async fn send_file(&self, id: u32, path: &Path, offset: u64, cancel: &AtomicBool) -> Result<Option<String>> {
+        let mut file = File::open(path).await?;
+        let mut hasher = Sha256::new();
+        let mut position = 0;
+
+        // A resumed download skips the bytes before 'offset', but the digest covers them.
+        let mut buf = vec![0u8; 256 * 1024];
+        while position < offset {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            let wanted = (offset - position).min(buf.len() as u64) as usize;
+            let n = file.read(&mut buf[..wanted]).await?;
+            if n == 0 {
+                bail!("the file shrank while it was being sent");
+            }
+            hasher.update(&buf[..n]);
+            position += n as u64;
+        }
+
+        let mut buf = vec![0u8; CHUNK_SIZE];
+        loop {
+            if cancel.load(Ordering::Relaxed) {
+                return Ok(None);
+            }
+            if !self.session.has_control() {
+                bail!("this session is view-only");
+            }
+            // Backpressure: don't queue the whole file in the SCTP send buffer.
+            while self.dc.buffered_amount().await > MAX_BUFFERED {
+                if cancel.load(Ordering::Relaxed) {
+                    return Ok(None);
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(Some(hex::encode(hasher.finalize())));
+            }
+            hasher.update(&buf[..n]);
+            let mut frame = Vec::with_capacity(FRAME_HEADER + n);
+            frame.extend_from_slice(&id.to_be_bytes());
+            frame.extend_from_slice(&position.to_be_bytes());
+            frame.extend_from_slice(&buf[..n]);
+            self.dc.send(&Bytes::from(frame)).await?;
+            position += n as u64;
+        }
+    }
+}
+
+/// Maps a client path ("dir/sub/file.txt") to a path inside 'root', refusing
+/// anything that would escape it ('..', absolute paths, drive letters, links).
+fn resolve_shared(root: &Path, path: &str) -> Result<PathBuf> {
+    let mut resolved = root.to_path_buf();
+    for part in path.split(['/', '\\']) {
+        match part {
+            "" | "." => {}
+            ".." => bail!("'..' is not allowed"),
+            part if part.contains(':') => bail!("invalid path component '{part}'"),
+            part => resolved.push(part),
+        }
+    }
+    let root = root.canonicalize()?;
+    let resolved = resolved.canonicalize()?;
+    if !resolved.starts_with(&root) {
+        bail!("path is outside the shared directory");
+    }
+    Ok(resolved)
+}
+
+/// Reduces a client-supplied name to a plain, safe file name.
+fn sanitize_file_name(name: &str) -> String {
+    let base = name.rsplit(['/', '\\']).next().unwrap_or("");
+    let cleaned: String = base
+        .chars()
+        .map(|c| if c.is_control() || "<>:\"|?*".contains(c) { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('.').to_owned();
+    if cleaned.is_empty() {
+        return "upload".to_owned();
+    }
+    // Device names like "CON" or "nul.txt" can't be used as files on Windows.
+    let stem = cleaned.split('.').next().unwrap_or("").to_ascii_uppercase();
+    let reserved = matches!(stem.as_str(), "CON" | "PRN" | "AUX" | "NUL")
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit());
+    if reserved {
+        format!("_{cleaned}")
+    } else {
+        cleaned
+    }
+}
+
+/// 'name' in 'dir', or "name (1).ext", "name (2).ext", ... if it already exists.
+async fn unique_path(dir: &Path, name: &str) -> PathBuf {
+    let candidate = dir.join(name);
+    if fs::metadata(&candidate).await.is_err() {
+        return candidate;
+    }
+    let (stem, ext) = match name.rfind('.') {
+        Some(i) if i > 0 => (&name[..i], &name[i..]),
+        _ => (name, ""),
+    };
+    for n in 1.. {
+        let candidate = dir.join(format!("{stem} ({n}){ext}"));
+        if fs::metadata(&candidate).await.is_err() {
+            return candidate;
+        }
+    }
+    unreachable!()
+}
+
+/// Hex SHA-256 of a file's contents.
+async fn sha256_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 256 * 1024];
+    loop {
+        let n = file.read(&mut buf).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
@@ -0,0 +1,447 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{c_int, c_long, c_ulong},
+    fs::{File, OpenOptions},
+    io::{ErrorKind, Read, Write},
+    os::{fd::AsRawFd, unix::fs::OpenOptionsExt},
+    sync::Mutex,
+};
+use anyhow::{Context, Result};
+use tracing::{info, warn};
+
+// Virtual input devices through the Linux uinput module ('/dev/uinput'), for input
+// enigo can't inject on Linux: multitouch, pens, game controllers and
+// high-resolution wheels. The host user needs write access to '/dev/uinput'
+// (membership of the 'input' group or a udev rule, depending on the distribution).
+// Layouts and constants follow linux/uinput.h and linux/input-event-codes.h.
+
+pub const EV_SYN: u16 = 0x00;
+pub const EV_KEY: u16 = 0x01;
+pub const EV_REL: u16 = 0x02;
+pub const EV_ABS: u16 = 0x03;
+pub const EV_FF:  u16 = 0x15;
+// Requests from the kernel to the device's owner (force feedback uploads).
+const EV_UINPUT:  u16 = 0x0101;
+const SYN_REPORT: u16 = 0;
+
+const UI_FF_UPLOAD: u16 = 1;
+const UI_FF_ERASE:  u16 = 2;
+
+pub const FF_RUMBLE: u16 = 0x50;
+
+pub const BUS_USB:     u16 = 0x03;
+pub const BUS_VIRTUAL: u16 = 0x06;
+
+pub const INPUT_PROP_DIRECT: u16 = 0x01;
+
+/// One evdev event: type, code and value. The kernel fills in the time.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct RawEvent {
+    time:  [c_long; 2],
+    kind:  u16,
+    code:  u16,
+    value: i32,
+}
+
+#[repr(C)]
+struct InputId {
+    bustype: u16,
+    vendor:  u16,
+    product: u16,
+    version: u16,
+}
+
+#[repr(C)]
+struct UinputSetup {
+    id:             InputId,
+    name:           [u8; 80],
+    ff_effects_max: u32,
+}
+
+#[repr(C)]
+struct AbsInfo {
+    value:      i32,
+    minimum:    i32,
+    maximum:    i32,
+    fuzz:       i32,
+    flat:       i32,
+    resolution: i32,
+}
+
+#[repr(C)]
+struct UinputAbsSetup {
+    code:    u16,
+    absinfo: AbsInfo,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfRumble {
+    strong_magnitude: u16,
+    weak_magnitude:   u16,
+}
+
+// 'ff_effect's union is as large as its periodic variant, which ends in a pointer.
+#[cfg(target_pointer_width = "64")]
+const FF_UNION_WORDS: usize = 4;
+#[cfg(target_pointer_width = "32")]
+const FF_UNION_WORDS: usize = 7;
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+union FfEffectUnion {
+    rumble: FfRumble,
+    _size:  [usize; FF_UNION_WORDS],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct FfEffect {
+    kind:      u16,
+    id:        i16,
+    direction: u16,
+    trigger:   [u16; 2],
+    replay:    [u16; 2],
+    u:         FfEffectUnion,
+}
+
+#[repr(C)]
+struct UinputFfUpload {
+    request_id: u32,
+    retval:     i32,
+    effect:     FfEffect,
+    old:        FfEffect,
+}
+
+#[repr(C)]
+struct UinputFfErase {
+    request_id: u32,
+    retval:     i32,
+    effect_id:  u32,
+}
+
+// ioctl request numbers ('_IO', '_IOW' and '_IOWR' with type 'U', as on x86 and ARM).
+const fn ioc(dir: u32, nr: u32, size: usize) -> c_ulong {
+    ((dir << 30) | ((size as u32) << 16) | (0x55 << 8) | nr) as c_ulong
+}
+const UI_DEV_CREATE:       c_ulong = ioc(0, 1, 0);
+const UI_DEV_DESTROY:      c_ulong = ioc(0, 2, 0);
+const UI_DEV_SETUP:        c_ulong = ioc(1, 3, std::mem::size_of::<UinputSetup>());
+const UI_ABS_SETUP:        c_ulong = ioc(1, 4, std::mem::size_of::<UinputAbsSetup>());
+const UI_SET_EVBIT:        c_ulong = ioc(1, 100, std::mem::size_of::<c_int>());
+const UI_SET_KEYBIT:       c_ulong = ioc(1, 101, std::mem::size_of::<c_int>());
+const UI_SET_RELBIT:       c_ulong = ioc(1, 102, std::mem::size_of::<c_int>());
+const UI_SET_ABSBIT:       c_ulong = ioc(1, 103, std::mem::size_of::<c_int>());
+const UI_SET_FFBIT:        c_ulong = ioc(1, 107, std::mem::size_of::<c_int>());
+const UI_SET_PROPBIT:      c_ulong = ioc(1, 110, std::mem::size_of::<c_int>());
+const UI_BEGIN_FF_UPLOAD:  c_ulong = ioc(3, 200, std::mem::size_of::<UinputFfUpload>());
+const UI_END_FF_UPLOAD:    c_ulong = ioc(1, 201, std::mem::size_of::<UinputFfUpload>());
+const UI_BEGIN_FF_ERASE:   c_ulong = ioc(3, 202, std::mem::size_of::<UinputFfErase>());
+const UI_END_FF_ERASE:     c_ulong = ioc(1, 203, std::mem::size_of::<UinputFfErase>());
+
+extern "C" {
+    fn ioctl(fd: c_int, request: c_ulong, ...) -> c_int;
+}
+
+/// 'DeviceBuilder' declares what a new virtual device can report before it is created.
+pub struct DeviceBuilder {
+    file:           File,
+    name:           &'static str,
+    ff_effects_max: u32,
+}
+
+impl DeviceBuilder {
+    pub fn new(name: &'static str) -> Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            // Only force feedback devices read, and they poll.
+            .custom_flags(O_NONBLOCK)
+            .open("/dev/uinput")
+            .context("can't open /dev/uinput (is the uinput module loaded and writable?)")?;
+        Ok(Self { file, name, ff_effects_max: 0 })
+    }
+
+    pub fn keys(self, codes: &[u16]) -> Result<Self> {
+        self.set_bit(UI_SET_EVBIT, EV_KEY)?;
+        for &code in codes {
+            self.set_bit(UI_SET_KEYBIT, code)?;
+        }
+        Ok(self)
+    }
+
+    pub fn relative(self, codes: &[u16]) -> Result<Self> {
+        self.set_bit(UI_SET_EVBIT, EV_REL)?;
+        for &code in codes {
+            self.set_bit(UI_SET_RELBIT, code)?;
+        }
+        Ok(self)
+    }
+
+    /// An absolute axis from 'min' to 'max'; 'resolution' is in units per mm
+    /// (or per radian for angles), 0 if unknown.
+    pub fn absolute(self, code: u16, min: i32, max: i32, resolution: i32) -> Result<Self> {
+        self.set_bit(UI_SET_EVBIT, EV_ABS)?;
+        self.set_bit(UI_SET_ABSBIT, code)?;
+        let setup = UinputAbsSetup {
+            code,
+            absinfo: AbsInfo { value: 0, minimum: min, maximum: max, fuzz: 0, flat: 0, resolution },
+        };
+        self.ioctl(UI_ABS_SETUP, &setup as *const _ as usize)?;
+        Ok(self)
+    }
+
+    pub fn property(self, property: u16) -> Result<Self> {
+        self.set_bit(UI_SET_PROPBIT, property)?;
+        Ok(self)
+    }
+
+    /// Lets applications upload up to 'max' force feedback effects of the given kinds.
+    pub fn force_feedback(mut self, kinds: &[u16], max: u32) -> Result<Self> {
+        self.set_bit(UI_SET_EVBIT, EV_FF)?;
+        for &kind in kinds {
+            self.set_bit(UI_SET_FFBIT, kind)?;
+        }
+        self.ff_effects_max = max;
+        Ok(self)
+    }
+
+    pub fn create(self, bustype: u16, vendor: u16, product: u16) -> Result<Device> {
+        let mut name = [0u8; 80];
+        let len = self.name.len().min(name.len() - 1);
+        name[..len].copy_from_slice(&self.name.as_bytes()[..len]);
+        let setup = UinputSetup {
+            id: InputId { bustype, vendor, product, version: 1 },
+            name,
+            ff_effects_max: self.ff_effects_max,
+        };
+        self.ioctl(UI_DEV_SETUP, &setup as *const _ as usize)?;
+        self.ioctl(UI_DEV_CREATE, 0)?;
+        info!("Created uinput device '{}'", self.name);
+        Ok(Device { file: self.file, name: self.name, effects: Mutex::new(HashMap::new()) })
+    }
+
+    fn set_bit(&self, request: c_ulong, bit: u16) -> Result<()> {
+        self.ioctl(request, bit as usize)
+    }
+
+    fn ioctl(&self, request: c_ulong, arg: usize) -> Result<()> {
+        raw_ioctl(&self.file, request, arg).with_context(|| format!("uinput setup of '{}' failed", self.name))
+    }
+}
+
+/// 'Device' is one created virtual device. Dropping it removes the device.
+pub struct Device {
+    file:    File,
+    name:    &'static str,
+    // Uploaded rumble effects by ID: strong and weak magnitude.
+    effects: Mutex<HashMap<i16, (u16, u16)>>,
+}
+
+impl Device {
+    /// Writes 'events' (type, code, value) followed by a 'SYN_REPORT', so
+    /// applications see them as one atomic update.
+    pub fn emit(&self, events: &[(u16, u16, i32)]) -> Result<()> {
+        let mut frame: Vec<RawEvent> = events.iter()
+            .map(|&(kind, code, value)| RawEvent { time: [0; 2], kind, code, value })
+            .collect();
+        frame.push(RawEvent { time: [0; 2], kind: EV_SYN, code: SYN_REPORT, value: 0 });
+        let bytes = unsafe {
+            std::slice::from_raw_parts(frame.as_ptr() as *const u8, std::mem::size_of_val(frame.as_slice()))
+        };
+        (&self.file).write_all(bytes).with_context(|| format!("writing to uinput device '{}' failed", self.name))
+    }
+
+    /// Handles what applications asked of the device since the last call (force
+    /// feedback uploads and playback) and returns the rumble to play, as strong and
+    /// weak magnitude from 0.0 to 1.0, if playback changed.
+    pub fn poll_rumble(&self) -> Option<(f64, f64)> {
+        let mut rumble = None;
+        let mut event = RawEvent { time: [0; 2], kind: 0, code: 0, value: 0 };
+        loop {
+            let buf = unsafe {
+                std::slice::from_raw_parts_mut(&mut event as *mut RawEvent as *mut u8, std::mem::size_of::<RawEvent>())
+            };
+            match (&self.file).read(buf) {
+                Ok(n) if n == buf.len() => {}
+                Ok(_) => break,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    warn!("Reading from uinput device '{}' failed: {e}", self.name);
+                    break;
+                }
+            }
+            match (event.kind, event.code) {
+                (EV_UINPUT, UI_FF_UPLOAD) => self.upload_effect(event.value as u32),
+                (EV_UINPUT, UI_FF_ERASE) => self.erase_effect(event.value as u32),
+                (EV_FF, id) => {
+                    let magnitudes = self.effects.lock().unwrap().get(&(id as i16)).copied();
+                    if let Some((strong, weak)) = magnitudes {
+                        rumble = Some(if event.value > 0 {
+                            (strong as f64 / u16::MAX as f64, weak as f64 / u16::MAX as f64)
+                        } else {
+                            (0.0, 0.0)
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        rumble
+    }
+
+    fn upload_effect(&self, request_id: u32) {
+        let mut upload: UinputFfUpload = unsafe { std::mem::zeroed() };
+        upload.request_id = request_id;
+        if raw_ioctl(&self.file, UI_BEGIN_FF_UPLOAD, &mut upload as *mut _ as usize).is_err() {
+            return;
+        }
+        if upload.effect.kind == FF_RUMBLE {
+            let rumble = unsafe { upload.effect.u.rumble };
+            self.effects.lock().unwrap().insert(upload.effect.id, (rumble.strong_magnitude, rumble.weak_magnitude));
+            upload.retval = 0;
+        } else {
+            upload.retval = -EINVAL;
+        }
+        let _ = raw_ioctl(&self.file, UI_END_FF_UPLOAD, &upload as *const _ as usize);
+    }
+
+    fn erase_effect(&self, request_id: u32) {
+        let mut erase = UinputFfErase { request_id, retval: 0, effect_id: 0 };
+        if raw_ioctl(&self.file, UI_BEGIN_FF_ERASE, &mut erase as *mut _ as usize).is_err() {
+            return;
+        }
+        self.effects.lock().unwrap().remove(&(erase.effect_id as i16));
+        erase.retval = 0;
+        let _ = raw_ioctl(&self.file, UI_END_FF_ERASE, &erase as *const _ as usize);
+    }
+}
+
+impl Drop for Device {
+    fn drop(&mut self) {
+        let _ = raw_ioctl(&self.file, UI_DEV_DESTROY, 0);
+    }
+}
+
+fn raw_ioctl(file: &File, request: c_ulong, arg: usize) -> std::io::Result<()> {
+    if unsafe { ioctl(file.as_raw_fd(), request, arg) } < 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+const O_NONBLOCK: c_int = 0o4000;
+const EINVAL: i32 = 22;
+
+const REL_X:             u16 = 0x00;
+const REL_Y:             u16 = 0x01;
+const REL_HWHEEL:        u16 = 0x06;
+const REL_WHEEL:         u16 = 0x08;
+const REL_WHEEL_HI_RES:  u16 = 0x0b;
+const REL_HWHEEL_HI_RES: u16 = 0x0c;
+const BTN_LEFT:          u16 = 0x110;
+const BTN_RIGHT:         u16 = 0x111;
+const BTN_MIDDLE:        u16 = 0x112;
+
+// A wheel notch in 'REL_WHEEL_HI_RES' units; the same 120 as Windows' 'WHEEL_DELTA'.
+const HI_RES_PER_NOTCH: i32 = 120;
+
+/// A mouse that only scrolls. It reports 'REL_WHEEL_HI_RES' for smooth scrolling
+/// and the classic 'REL_WHEEL' notches for applications that only read those.
+struct Wheel {
+    device: Device,
+    // Hi-res units not yet reported as a whole notch (horizontal, vertical).
+    rest:   (i32, i32),
+}
+
+thread_local! {
+    // Only the input thread scrolls. 'Err' once creating the device failed.
+    static WHEEL: RefCell<Option<std::result::Result<Wheel, ()>>> = const { RefCell::new(None) };
+}
+
+/// Scrolls by 'units' (120 per notch, positive is up or right), like
+/// 'sendinput::mouse_wheel' on Windows.
+pub fn mouse_wheel(units: i32, horizontal: bool) {
+    WHEEL.with(|wheel| {
+        let mut wheel = wheel.borrow_mut();
+        let wheel = wheel.get_or_insert_with(|| {
+            create_wheel().map_err(|e| warn!("Could not create the uinput wheel, scrolling disabled: {e:#}"))
+        });
+        let Ok(wheel) = wheel else { return };
+
+        let (hi_res, notch, rest) = if horizontal {
+            (REL_HWHEEL_HI_RES, REL_HWHEEL, &mut wheel.rest.0)
+        } else {
+            (REL_WHEEL_HI_RES, REL_WHEEL, &mut wheel.rest.1)
+        };
+        let notches = take_notches(rest, units);
+        let mut events = vec![(EV_REL, hi_res, units)];
+        if notches != 0 {
+            events.push((EV_REL, notch, notches));
+        }
+        if let Err(e) = wheel.device.emit(&events) {
+            warn!("{e:#}");
+        }
+    });
+}
+
+/// Adds 'units' to 'rest' and takes out the whole notches, keeping the sign.
+fn take_notches(rest: &mut i32, units: i32) -> i32 {
+    *rest += units;
+    let notches = *rest / HI_RES_PER_NOTCH;
+    *rest -= notches * HI_RES_PER_NOTCH;
+    notches
+}
+
+fn create_wheel() -> Result<Wheel> {
+    // Pointer motion and buttons make desktops treat it as a mouse; they're never sent.
+    let device = DeviceBuilder::new("LocalBridge wheel")?
+        .keys(&[BTN_LEFT, BTN_RIGHT, BTN_MIDDLE])?
+        .relative(&[REL_X, REL_Y, REL_WHEEL, REL_HWHEEL, REL_WHEEL_HI_RES, REL_HWHEEL_HI_RES])?
+        .create(BUS_VIRTUAL, 0, 0)?;
+    Ok(Wheel { device, rest: (0, 0) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sizes from the kernel headers; a mismatch would make every ioctl fail.
+    #[test]
+    fn structs_match_the_kernel_abi() {
+        assert_eq!(std::mem::size_of::<UinputSetup>(), 92);
+        assert_eq!(std::mem::size_of::<UinputAbsSetup>(), 28);
+        assert_eq!(std::mem::size_of::<UinputFfErase>(), 12);
+        #[cfg(target_pointer_width = "64")]
+        {
+            assert_eq!(std::mem::size_of::<RawEvent>(), 24);
+            assert_eq!(std::mem::size_of::<FfEffect>(), 48);
+            assert_eq!(std::mem::size_of::<UinputFfUpload>(), 104);
+        }
+        assert_eq!(UI_DEV_SETUP, 0x405c_5503);
+        assert_eq!(UI_ABS_SETUP, 0x401c_5504);
+        assert_eq!(UI_SET_EVBIT, 0x4004_5564);
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(UI_BEGIN_FF_UPLOAD, 0xc068_55c8);
+    }
+
+    #[test]
+    fn hi_res_wheel_units_add_up_to_notches() {
+        let mut rest = 0;
+        // A trackpad's small steps only make a notch once they add up to 120.
+        assert_eq!(take_notches(&mut rest, 40), 0);
+        assert_eq!(take_notches(&mut rest, 40), 0);
+        assert_eq!(take_notches(&mut rest, 40), 1);
+        assert_eq!(rest, 0);
+        // A fast flick is several notches at once, with the remainder kept.
+        assert_eq!(take_notches(&mut rest, 300), 2);
+        assert_eq!(rest, 60);
+        // Reversing direction eats the remainder first.
+        assert_eq!(take_notches(&mut rest, -90), 0);
+        assert_eq!(rest, -30);
+        assert_eq!(take_notches(&mut rest, -90), -1);
+        assert_eq!(rest, 0);
+    }
+}
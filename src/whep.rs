@@ -0,0 +1,192 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+use axum::{
+    extract::{ConnectInfo, Path, State},
+    http::{header, HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+    routing::{patch, post},
+    Router,
+};
+use tracing::{error, info, warn};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+
+use crate::{
+    approval::{ApprovalRequest, Decision},
+    session::Session,
+    AppState,
+};
+
+// WHEP (WebRTC-HTTP Egress Protocol, RFC 9725) playback, so OBS, GStreamer's
+// 'whepsrc' and other WHEP players can watch the stream.
+//
+//   POST   /whep             application/sdp offer -> 201, SDP answer, Location: /whep/<resource>
+//   PATCH  /whep/<resource>  application/trickle-ice-sdpfrag -> 204, adds the player's candidates
+//   DELETE /whep/<resource>  -> 200, ends the session
+//
+// The offer goes through 'do_offer' like the bundled client's, so the answer carries
+// every host candidate and the session shows up in the admin API. Players only
+// receive video: they don't open data channels, so they never control the host.
+// ICE restarts aren't supported.
+//
+// '<resource>' is a random token handed out only in the 'Location' header, not the
+// session id: whoever knows a session id (a log, the admin API) still can't end it
+// or add candidates, and sessions of the bundled client can't be reached here.
+
+const SDP: &str = "application/sdp";
+const TRICKLE_ICE_SDPFRAG: &str = "application/trickle-ice-sdpfrag";
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/whep",           post(create_session))
+        .route("/whep/:resource", patch(trickle).delete(delete_session))
+}
+
+/// 'WhepSessions' maps the resource tokens of sessions created through '/whep' to
+/// their session ids.
+#[derive(Default)]
+pub struct WhepSessions {
+    resources: Mutex<HashMap<String, String>>,
+}
+
+impl WhepSessions {
+    /// The session behind a resource, forgetting resources whose session ended.
+    async fn find(&self, state: &AppState, resource: &str) -> Option<Arc<Session>> {
+        let id = self.resources.lock().unwrap().get(resource).cloned()?;
+        let session = state.peers.lock().await.get(&id).cloned();
+        if session.is_none() {
+            self.resources.lock().unwrap().remove(resource);
+        }
+        session
+    }
+}
+
+fn has_content_type(headers: &HeaderMap, expected: &str) -> bool {
+    headers.get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(';').next())
+        .is_some_and(|v| v.trim().eq_ignore_ascii_case(expected))
+}
+
+fn plain(status: StatusCode, message: impl Into<String>) -> Response {
+    (status, [(header::CONTENT_TYPE, "text/plain")], message.into()).into_response()
+}
+
+async fn create_session(
+    State(state):             State<AppState>,
+    ConnectInfo(remote_addr): ConnectInfo<SocketAddr>,
+    headers:                  HeaderMap,
+    offer:                    String,
+) -> Response {
+    if !has_content_type(&headers, SDP) {
+        return plain(StatusCode::UNSUPPORTED_MEDIA_TYPE, "expected an application/sdp offer");
+    }
+    info!("WHEP session requested from {remote_addr}");
+
+    // WHEP has no pending state, so the request itself waits for the host's answer.
+    if state.approvals.is_required() {
+        let request = ApprovalRequest {
+            ticket:         uuid::Uuid::new_v4().to_string(),
+            remote_address: remote_addr,
+            user_agent:     headers.get(header::USER_AGENT)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned),
+        };
+        let decision = state.approvals.ask(request).await;
+        if let Some(refusal) = decision.refusal() {
+            let status = if decision == Decision::Busy { StatusCode::SERVICE_UNAVAILABLE } else { StatusCode::FORBIDDEN };
+            return plain(status, refusal);
+        }
+    }
+
+    let whep = Arc::clone(&state.whep);
+    let peers = Arc::clone(&state.peers);
+    match crate::do_offer(state, offer, remote_addr).await {
+        Ok((id, answer)) => {
+            let resource = uuid::Uuid::new_v4().simple().to_string();
+            {
+                let peers = peers.lock().await;
+                let mut resources = whep.resources.lock().unwrap();
+                // Players that never sent DELETE leave their resource behind.
+                resources.retain(|_, id| peers.contains_key(id));
+                resources.insert(resource.clone(), id);
+            }
+            (
+                StatusCode::CREATED,
+                [
+                    (header::CONTENT_TYPE, SDP.to_owned()),
+                    (header::LOCATION, format!("/whep/{resource}")),
+                ],
+                answer.sdp,
+            ).into_response()
+        }
+        Err(e) => {
+            error!("WHEP offer error: {e}");
+            plain(StatusCode::BAD_REQUEST, e.to_string())
+        }
+    }
+}
+
+async fn trickle(
+    State(state):   State<AppState>,
+    Path(resource): Path<String>,
+    headers:        HeaderMap,
+    fragment:       String,
+) -> Response {
+    if !has_content_type(&headers, TRICKLE_ICE_SDPFRAG) {
+        return plain(StatusCode::UNSUPPORTED_MEDIA_TYPE, "expected an application/trickle-ice-sdpfrag body");
+    }
+    let Some(session) = state.whep.find(&state, &resource).await else {
+        return plain(StatusCode::NOT_FOUND, "no such session");
+    };
+    for candidate in parse_sdpfrag(&fragment) {
+        if let Err(e) = session.pc.add_ice_candidate(candidate).await {
+            warn!("WHEP session {}: bad candidate: {e}", session.id);
+        }
+    }
+    StatusCode::NO_CONTENT.into_response()
+}
+
+async fn delete_session(
+    State(state):   State<AppState>,
+    Path(resource): Path<String>,
+) -> Response {
+    let Some(session) = state.whep.find(&state, &resource).await else {
+        return plain(StatusCode::NOT_FOUND, "no such session");
+    };
+    state.whep.resources.lock().unwrap().remove(&resource);
+    state.peers.lock().await.remove(&session.id);
+    info!("WHEP session {} ended by the player", session.id);
+    let _ = session.pc.close().await;
+    StatusCode::OK.into_response()
+}
+
+/// Reads the candidates out of a trickle ICE SDP fragment (RFC 8840): each
+/// 'a=candidate' belongs to the media section it appears in, identified by its
+/// 'a=mid' and position.
+fn parse_sdpfrag(fragment: &str) -> Vec<RTCIceCandidateInit> {
+    let mut candidates = Vec::new();
+    let mut ufrag = None;
+    let mut mid = None;
+    let mut mline_index: Option<u16> = None;
+    for line in fragment.lines().map(str::trim) {
+        if let Some(value) = line.strip_prefix("a=ice-ufrag:") {
+            ufrag = Some(value.to_owned());
+        } else if line.starts_with("m=") {
+            mline_index = Some(mline_index.map_or(0, |i| i + 1));
+            mid = None;
+        } else if let Some(value) = line.strip_prefix("a=mid:") {
+            mid = Some(value.to_owned());
+        } else if let Some(value) = line.strip_prefix("a=").filter(|v| v.starts_with("candidate:")) {
+            candidates.push(RTCIceCandidateInit {
+                candidate:         value.to_owned(),
+                sdp_mid:           mid.clone(),
+                sdp_mline_index:   mline_index,
+                username_fragment: ufrag.clone(),
+            });
+        }
+    }
+    candidates
+}
@@ -0,0 +1,381 @@
+use anyhow::{anyhow, bail, Result};
+
+use crate::events::{DeltaMode, InputEvent, PointerContact, PointerKind, PointerMode, PointerPhase};
+
+// Compact binary encoding of 'InputEvent', used on the "input" data channel once
+// both sides list the 'binary_input' feature in their hello. A mouse move is 9 bytes
+// instead of ~40 bytes of JSON and decodes without a parser. The client may still
+// send JSON text messages at any time (handy for debugging from the console).
+//
+// Every message is one opcode byte followed by the event's fields in order:
+// floats are little-endian f32, integers little-endian, strings are UTF-8 with a
+// length prefix (u8 for key codes, u16 for text). Nothing may follow the last field.
+// The client's encoder is 'encodeInput' in client/index.html; keep the two in sync.
+// 'encode' below is the same in Rust, for the tests and the fuzz target in fuzz/.
+
+const MOUSE_MOVE: u8 = 0x01;
+const MOUSE_MOVE_RELATIVE: u8 = 0x02;
+const SET_POINTER_MODE: u8 = 0x03;
+const MOUSE_DOWN: u8 = 0x04;
+const MOUSE_UP: u8 = 0x05;
+// dx, dy, mode (0 pixel, 1 line, 2 page).
+const MOUSE_SCROLL: u8 = 0x06;
+// id u32, kind (0 touch, 1 pen), phase (0 down, 1 move, 2 up, 3 cancel, 4 leave),
+// x, y, pressure, tilt_x, tilt_y, twist, width, height, flags (1 eraser, 2 barrel).
+const POINTER: u8 = 0x07;
+// index, u8 button count, buttons, u8 axis count, axes.
+const GAMEPAD: u8 = 0x08;
+const GAMEPAD_DISCONNECTED: u8 = 0x09;
+const KEY_DOWN: u8 = 0x0a;
+const KEY_UP: u8 = 0x0b;
+const TEXT_INPUT: u8 = 0x0c;
+const COMPOSITION_START: u8 = 0x0d;
+const COMPOSITION_UPDATE: u8 = 0x0e;
+const COMPOSITION_END: u8 = 0x0f;
+const RELEASE_ALL: u8 = 0x10;
+
+/// Decodes one binary input message.
+pub fn decode(bytes: &[u8]) -> Result<InputEvent> {
+    let mut r = Reader { bytes };
+    let event = match r.u8()? {
+        MOUSE_MOVE => InputEvent::MouseMove { x: r.f32()?, y: r.f32()? },
+        MOUSE_MOVE_RELATIVE => InputEvent::MouseMoveRelative { dx: r.f32()?, dy: r.f32()? },
+        SET_POINTER_MODE => InputEvent::SetPointerMode {
+            mode: match r.u8()? {
+                0 => PointerMode::Absolute,
+                1 => PointerMode::Relative,
+                v => bail!("unknown pointer mode {v}"),
+            },
+        },
+        MOUSE_DOWN => InputEvent::MouseDown { x: r.f32()?, y: r.f32()?, button: r.u8()? },
+        MOUSE_UP => InputEvent::MouseUp { x: r.f32()?, y: r.f32()?, button: r.u8()? },
+        MOUSE_SCROLL => InputEvent::MouseScroll {
+            dx:   r.f32()?,
+            dy:   r.f32()?,
+            mode: match r.u8()? {
+                0 => DeltaMode::Pixel,
+                1 => DeltaMode::Line,
+                2 => DeltaMode::Page,
+                v => bail!("unknown delta mode {v}"),
+            },
+        },
+        POINTER => {
+            let id = r.u32()?;
+            let kind = match r.u8()? {
+                0 => PointerKind::Touch,
+                1 => PointerKind::Pen,
+                v => bail!("unknown pointer kind {v}"),
+            };
+            let phase = match r.u8()? {
+                0 => PointerPhase::Down,
+                1 => PointerPhase::Move,
+                2 => PointerPhase::Up,
+                3 => PointerPhase::Cancel,
+                4 => PointerPhase::Leave,
+                v => bail!("unknown pointer phase {v}"),
+            };
+            let (x, y, pressure) = (r.f32()?, r.f32()?, r.f32()?);
+            let (tilt_x, tilt_y, twist) = (r.f32()?, r.f32()?, r.f32()?);
+            let (width, height) = (r.f32()?, r.f32()?);
+            let flags = r.u8()?;
+            let contact = PointerContact {
+                x, y, pressure, tilt_x, tilt_y, twist, width, height,
+                eraser: flags & 1 != 0,
+                barrel: flags & 2 != 0,
+            };
+            InputEvent::Pointer { id, kind, phase, contact }
+        }
+        GAMEPAD => {
+            let index = r.u8()?;
+            let buttons = r.f32_list()?;
+            let axes = r.f32_list()?;
+            InputEvent::Gamepad { index, buttons, axes }
+        }
+        GAMEPAD_DISCONNECTED => InputEvent::GamepadDisconnected { index: r.u8()? },
+        KEY_DOWN => InputEvent::KeyDown { code: r.str8()? },
+        KEY_UP => InputEvent::KeyUp { code: r.str8()? },
+        TEXT_INPUT => InputEvent::TextInput { text: r.str16()? },
+        COMPOSITION_START => InputEvent::CompositionStart,
+        COMPOSITION_UPDATE => InputEvent::CompositionUpdate { text: r.str16()? },
+        COMPOSITION_END => InputEvent::CompositionEnd { text: r.str16()? },
+        RELEASE_ALL => InputEvent::ReleaseAll,
+        op => bail!("unknown opcode {op:#04x}"),
+    };
+    if !r.bytes.is_empty() {
+        bail!("{} trailing byte(s)", r.bytes.len());
+    }
+    Ok(event)
+}
+
+/// Encodes one event, the inverse of 'decode'.
+#[cfg(any(test, fuzzing))]
+pub fn encode(event: &InputEvent) -> Vec<u8> {
+    let mut w = Writer { bytes: Vec::new() };
+    match event {
+        InputEvent::MouseMove { x, y } => {
+            w.u8(MOUSE_MOVE);
+            w.f32(*x);
+            w.f32(*y);
+        }
+        InputEvent::MouseMoveRelative { dx, dy } => {
+            w.u8(MOUSE_MOVE_RELATIVE);
+            w.f32(*dx);
+            w.f32(*dy);
+        }
+        InputEvent::SetPointerMode { mode } => {
+            w.u8(SET_POINTER_MODE);
+            w.u8(match mode {
+                PointerMode::Absolute => 0,
+                PointerMode::Relative => 1,
+            });
+        }
+        InputEvent::MouseDown { x, y, button } | InputEvent::MouseUp { x, y, button } => {
+            w.u8(if matches!(event, InputEvent::MouseDown { .. }) { MOUSE_DOWN } else { MOUSE_UP });
+            w.f32(*x);
+            w.f32(*y);
+            w.u8(*button);
+        }
+        InputEvent::MouseScroll { dx, dy, mode } => {
+            w.u8(MOUSE_SCROLL);
+            w.f32(*dx);
+            w.f32(*dy);
+            w.u8(match mode {
+                DeltaMode::Pixel => 0,
+                DeltaMode::Line => 1,
+                DeltaMode::Page => 2,
+            });
+        }
+        InputEvent::Pointer { id, kind, phase, contact } => {
+            w.u8(POINTER);
+            w.u32(*id);
+            w.u8(match kind {
+                PointerKind::Touch => 0,
+                PointerKind::Pen => 1,
+            });
+            w.u8(match phase {
+                PointerPhase::Down => 0,
+                PointerPhase::Move => 1,
+                PointerPhase::Up => 2,
+                PointerPhase::Cancel => 3,
+                PointerPhase::Leave => 4,
+            });
+            let c = contact;
+            for v in [c.x, c.y, c.pressure, c.tilt_x, c.tilt_y, c.twist, c.width, c.height] {
+                w.f32(v);
+            }
+            w.u8(u8::from(c.eraser) | u8::from(c.barrel) << 1);
+        }
+        InputEvent::Gamepad { index, buttons, axes } => {
+            w.u8(GAMEPAD);
+            w.u8(*index);
+            w.f32_list(buttons);
+            w.f32_list(axes);
+        }
+        InputEvent::GamepadDisconnected { index } => {
+            w.u8(GAMEPAD_DISCONNECTED);
+            w.u8(*index);
+        }
+        InputEvent::KeyDown { code } => {
+            w.u8(KEY_DOWN);
+            w.str8(code);
+        }
+        InputEvent::KeyUp { code } => {
+            w.u8(KEY_UP);
+            w.str8(code);
+        }
+        InputEvent::TextInput { text } => {
+            w.u8(TEXT_INPUT);
+            w.str16(text);
+        }
+        InputEvent::CompositionStart => w.u8(COMPOSITION_START),
+        InputEvent::CompositionUpdate { text } => {
+            w.u8(COMPOSITION_UPDATE);
+            w.str16(text);
+        }
+        InputEvent::CompositionEnd { text } => {
+            w.u8(COMPOSITION_END);
+            w.str16(text);
+        }
+        InputEvent::ReleaseAll => w.u8(RELEASE_ALL),
+    }
+    w.bytes
+}
+
+/// Reads fields off the front of a message; every read fails cleanly on truncation.
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < n {
+            bail!("message truncated");
+        }
+        let (head, rest) = self.bytes.split_at(n);
+        self.bytes = rest;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f64> {
+        Ok(f32::from_le_bytes(self.take(4)?.try_into()?) as f64)
+    }
+
+    fn f32_list(&mut self) -> Result<Vec<f64>> {
+        let len = self.u8()? as usize;
+        (0..len).map(|_| self.f32()).collect()
+    }
+
+    fn str8(&mut self) -> Result<String> {
+        let len = self.u8()? as usize;
+        self.utf8(len)
+    }
+
+    fn str16(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        self.utf8(len)
+    }
+
+    fn utf8(&mut self, len: usize) -> Result<String> {
+        let bytes = self.take(len)?;
+        std::str::from_utf8(bytes).map(str::to_owned).map_err(|e| anyhow!("invalid UTF-8: {e}"))
+    }
+}
+
+/// Appends fields in the order 'Reader' takes them. Lengths that don't fit their
+/// prefix are a bug in the caller, so they panic.
+#[cfg(any(test, fuzzing))]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+#[cfg(any(test, fuzzing))]
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.bytes.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.bytes.extend_from_slice(&v.to_le_bytes());
+    }
+
+    fn f32(&mut self, v: f64) {
+        self.bytes.extend_from_slice(&(v as f32).to_le_bytes());
+    }
+
+    fn f32_list(&mut self, values: &[f64]) {
+        self.u8(values.len().try_into().expect("more than 255 values"));
+        for &v in values {
+            self.f32(v);
+        }
+    }
+
+    fn str8(&mut self, s: &str) {
+        self.u8(s.len().try_into().expect("string longer than 255 bytes"));
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn str16(&mut self, s: &str) {
+        self.u16(s.len().try_into().expect("string longer than 65535 bytes"));
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::{collection::vec, prelude::*};
+
+    use super::*;
+
+    // Every float crosses the wire as an f32, and NaN never compares equal, so
+    // generate the values that survive a round trip unchanged.
+    fn wire_f64() -> impl Strategy<Value = f64> {
+        any::<f32>().prop_filter("NaN", |v| !v.is_nan()).prop_map(f64::from)
+    }
+
+    fn contact() -> impl Strategy<Value = PointerContact> {
+        (vec(wire_f64(), 8), any::<bool>(), any::<bool>()).prop_map(|(v, eraser, barrel)| PointerContact {
+            x: v[0], y: v[1], pressure: v[2], tilt_x: v[3], tilt_y: v[4], twist: v[5], width: v[6], height: v[7],
+            eraser, barrel,
+        })
+    }
+
+    fn input_event() -> impl Strategy<Value = InputEvent> {
+        let mode = prop_oneof![Just(PointerMode::Absolute), Just(PointerMode::Relative)];
+        let delta_mode = prop_oneof![Just(DeltaMode::Pixel), Just(DeltaMode::Line), Just(DeltaMode::Page)];
+        let kind = prop_oneof![Just(PointerKind::Touch), Just(PointerKind::Pen)];
+        let phase = prop_oneof![
+            Just(PointerPhase::Down),
+            Just(PointerPhase::Move),
+            Just(PointerPhase::Up),
+            Just(PointerPhase::Cancel),
+            Just(PointerPhase::Leave),
+        ];
+        // Key codes take a u8 length prefix; text a u16 one.
+        let code = "\\PC{0,60}";
+        let text = "\\PC{0,200}";
+        prop_oneof![
+            (wire_f64(), wire_f64()).prop_map(|(x, y)| InputEvent::MouseMove { x, y }),
+            (wire_f64(), wire_f64()).prop_map(|(dx, dy)| InputEvent::MouseMoveRelative { dx, dy }),
+            mode.prop_map(|mode| InputEvent::SetPointerMode { mode }),
+            (wire_f64(), wire_f64(), any::<u8>()).prop_map(|(x, y, button)| InputEvent::MouseDown { x, y, button }),
+            (wire_f64(), wire_f64(), any::<u8>()).prop_map(|(x, y, button)| InputEvent::MouseUp { x, y, button }),
+            (wire_f64(), wire_f64(), delta_mode).prop_map(|(dx, dy, mode)| InputEvent::MouseScroll { dx, dy, mode }),
+            (any::<u32>(), kind, phase, contact())
+                .prop_map(|(id, kind, phase, contact)| InputEvent::Pointer { id, kind, phase, contact }),
+            (any::<u8>(), vec(wire_f64(), 0..20), vec(wire_f64(), 0..8))
+                .prop_map(|(index, buttons, axes)| InputEvent::Gamepad { index, buttons, axes }),
+            any::<u8>().prop_map(|index| InputEvent::GamepadDisconnected { index }),
+            code.prop_map(|code| InputEvent::KeyDown { code }),
+            code.prop_map(|code| InputEvent::KeyUp { code }),
+            text.prop_map(|text| InputEvent::TextInput { text }),
+            Just(InputEvent::CompositionStart),
+            text.prop_map(|text| InputEvent::CompositionUpdate { text }),
+            text.prop_map(|text| InputEvent::CompositionEnd { text }),
+            Just(InputEvent::ReleaseAll),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn round_trip(event in input_event()) {
+            prop_assert_eq!(decode(&encode(&event)).unwrap(), event);
+        }
+
+        #[test]
+        fn truncated_or_padded_messages_are_rejected(event in input_event(), extra in any::<u8>()) {
+            let mut bytes = encode(&event);
+            for len in 0..bytes.len() {
+                prop_assert!(decode(&bytes[..len]).is_err());
+            }
+            bytes.push(extra);
+            prop_assert!(decode(&bytes).is_err());
+        }
+    }
+
+    // Pins the layout to what 'encodeInput' in the client sends.
+    #[test]
+    fn matches_the_client_encoding() {
+        assert_eq!(
+            encode(&InputEvent::MouseMove { x: 0.5, y: 0.25 }),
+            [MOUSE_MOVE, 0x00, 0x00, 0x00, 0x3f, 0x00, 0x00, 0x80, 0x3e],
+        );
+        assert_eq!(encode(&InputEvent::KeyDown { code: "KeyA".into() }), [KEY_DOWN, 4, b'K', b'e', b'y', b'A']);
+        assert_eq!(encode(&InputEvent::TextInput { text: "é".into() }), [TEXT_INPUT, 2, 0, 0xc3, 0xa9]);
+    }
+}
@@ -0,0 +1,148 @@
+// Connects two relay-only peers through an in-process TURN server, configured the
+// way the host reads it from the environment, and sends a data channel message
+// across. The session can only come up if the TURN settings reach the ICE agent.
+// The host's stack only relays over UDP, so TCP and TLS URLs in the same setting
+// must be left to browsers without breaking the UDP one.
+
+use std::{collections::HashMap, net::{IpAddr, Ipv4Addr, SocketAddr}, sync::Arc, time::Duration};
+
+use anyhow::Result;
+use tokio::{net::UdpSocket, sync::mpsc};
+use turn::{
+    auth::{generate_auth_key, AuthHandler},
+    relay::relay_static::RelayAddressGeneratorStatic,
+    server::{
+        config::{ConnConfig, ServerConfig},
+        Server,
+    },
+};
+use webrtc::{
+    api::{media_engine::MediaEngine, setting_engine::SettingEngine, APIBuilder},
+    ice::network_type::NetworkType,
+    ice_transport::ice_transport_policy::RTCIceTransportPolicy,
+    peer_connection::{configuration::RTCConfiguration, RTCPeerConnection},
+};
+use webrtc_util::vnet::net::Net;
+
+// The host's ICE configuration, built on its own (the host is a binary).
+#[path = "../src/ice.rs"]
+mod ice;
+
+const REALM: &str = "localbridge.test";
+const USERNAME: &str = "localbridge";
+const CREDENTIAL: &str = "relay-only";
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Knows the one test account.
+struct Credentials(HashMap<String, Vec<u8>>);
+
+impl AuthHandler for Credentials {
+    fn auth_handle(&self, username: &str, _realm: &str, _source: SocketAddr) -> Result<Vec<u8>, turn::Error> {
+        self.0.get(username).cloned().ok_or(turn::Error::ErrNoSuchUser)
+    }
+}
+
+/// Starts a TURN server on a free loopback port; it runs until dropped.
+async fn turn_server() -> Result<(Server, SocketAddr)> {
+    let conn = Arc::new(UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await?);
+    let address = conn.local_addr()?;
+    let key = generate_auth_key(USERNAME, REALM, CREDENTIAL);
+    let server = Server::new(ServerConfig {
+        conn_configs: vec![ConnConfig {
+            conn,
+            relay_addr_generator: Box::new(RelayAddressGeneratorStatic {
+                relay_address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+                address:       Ipv4Addr::LOCALHOST.to_string(),
+                net:           Arc::new(Net::new(None)),
+            }),
+        }],
+        realm:                REALM.to_owned(),
+        auth_handler:         Arc::new(Credentials(HashMap::from([(USERNAME.to_owned(), key)]))),
+        channel_bind_timeout: Duration::from_secs(0),
+        alloc_close_notify:   None,
+    })
+    .await?;
+    Ok((server, address))
+}
+
+/// A peer set up like the host's ('new_peer'), but allowed to use relay candidates only.
+async fn relay_peer(config: &ice::IceConfig) -> Result<Arc<RTCPeerConnection>> {
+    let mut media = MediaEngine::default();
+    media.register_default_codecs()?;
+    let mut settings = SettingEngine::default();
+    settings.set_network_types(vec![NetworkType::Udp4]);
+    let api = APIBuilder::new().with_media_engine(media).with_setting_engine(settings).build();
+    let pc = api.new_peer_connection(RTCConfiguration {
+        ice_servers:          config.rtc_ice_servers(),
+        ice_transport_policy: RTCIceTransportPolicy::Relay,
+        ..Default::default()
+    })
+    .await?;
+    Ok(Arc::new(pc))
+}
+
+/// Non-trickle negotiation, like 'POST /offer'.
+async fn connect(offerer: &RTCPeerConnection, answerer: &RTCPeerConnection) -> Result<()> {
+    let offer = offerer.create_offer(None).await?;
+    let mut gathered = offerer.gathering_complete_promise().await;
+    offerer.set_local_description(offer).await?;
+    let _ = gathered.recv().await;
+    let offer = offerer.local_description().await.expect("offer was set");
+    assert!(offer.sdp.contains("typ relay"), "no relay candidate in the offer:\n{}", offer.sdp);
+
+    answerer.set_remote_description(offer).await?;
+    let answer = answerer.create_answer(None).await?;
+    let mut gathered = answerer.gathering_complete_promise().await;
+    answerer.set_local_description(answer).await?;
+    let _ = gathered.recv().await;
+    offerer.set_remote_description(answerer.local_description().await.expect("answer was set")).await?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn sessions_connect_through_the_configured_turn_server() -> Result<()> {
+    let (server, address) = turn_server().await?;
+    // The only test in this binary, so nothing else reads the environment meanwhile.
+    let udp = format!("turn:{address}?transport=udp");
+    std::env::set_var("LOCALBRIDGE_TURN_SERVERS", format!("{udp},turn:{address}?transport=tcp,turns:{address}"));
+    std::env::set_var("LOCALBRIDGE_TURN_USERNAME", USERNAME);
+    std::env::set_var("LOCALBRIDGE_TURN_CREDENTIAL", CREDENTIAL);
+    let config = ice::IceConfig::from_env();
+    // Browsers get every URL ('/ice-servers'); the host only the one it can relay through.
+    assert_eq!(config.servers.len(), 1);
+    assert_eq!(config.servers[0].urls.len(), 3);
+    let host_servers = config.rtc_ice_servers();
+    assert_eq!(host_servers.len(), 1);
+    assert_eq!(host_servers[0].urls, [udp]);
+
+    let host = relay_peer(&config).await?;
+    let client = relay_peer(&config).await?;
+
+    // The client opens a channel, like "control", and says hello once it is open.
+    let channel = client.create_data_channel("control", None).await?;
+    let opened = Arc::clone(&channel);
+    channel.on_open(Box::new(move || {
+        Box::pin(async move {
+            let _ = opened.send_text("hello").await;
+        })
+    }));
+    let (received_tx, mut received) = mpsc::unbounded_channel();
+    host.on_data_channel(Box::new(move |dc| {
+        let received_tx = received_tx.clone();
+        Box::pin(async move {
+            dc.on_message(Box::new(move |msg| {
+                let _ = received_tx.send(String::from_utf8_lossy(&msg.data).into_owned());
+                Box::pin(async {})
+            }));
+        })
+    }));
+
+    connect(&client, &host).await?;
+    let message = tokio::time::timeout(TIMEOUT, received.recv()).await?;
+    assert_eq!(message.as_deref(), Some("hello"));
+
+    client.close().await?;
+    host.close().await?;
+    server.close().await?;
+    Ok(())
+}